@@ -1,6 +1,21 @@
-#[derive(Debug, Clone)]
+use crate::error::{Error, Result};
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// 持ち駒の種類（hands の添字の順番）
+pub const HAND_PIECES: [char; 7] = ['P', 'L', 'N', 'S', 'G', 'B', 'R'];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Board {
     pub squares: [[Option<char>; 9]; 9], // 9x9のマス（None = 空白, Some(c) = 駒）
+    pub promoted: [[bool; 9]; 9],        // 成駒かどうか（squares と同じ並び）
+    pub hands: [[u8; 7]; 2],             // 持ち駒の枚数（[先手, 後手][HAND_PIECES の順]）
+}
+
+impl Default for Board {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Board {
@@ -33,8 +48,8 @@ impl Board {
         ];
         squares[2] = [Some('p'); 9];
         // 中段は空
-        for rank in 3..6 {
-            squares[rank] = [None; 9];
+        for rank in squares.iter_mut().take(6).skip(3) {
+            *rank = [None; 9];
         }
         // 先手の駒（上段）
         squares[6] = [Some('P'); 9];
@@ -61,7 +76,11 @@ impl Board {
             Some('L'),
         ];
 
-        Board { squares }
+        Board {
+            squares,
+            promoted: [[false; 9]; 9],
+            hands: [[0; 7]; 2],
+        }
     }
 
     /// SFEN風の固定長文字列に変換（空白圧縮なし）
@@ -87,10 +106,156 @@ impl Board {
         flat
     }
 
-    /// 指し手（符号）を受け取って盤面に適用する（例: "５六歩(57)"）
-    pub fn apply_move(&mut self, fugo: &str, is_sente_turn: bool) -> Result<(), String> {
-        use regex::Regex;
+    /// SFEN 文字列に変換する（例: "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1"）
+    pub fn to_sfen(&self, sente_to_move: bool, move_number: usize) -> String {
+        let mut ranks = Vec::new();
+        for y in 0..9 {
+            let mut rank = String::new();
+            let mut empty = 0;
+            for x in 0..9 {
+                let Some(piece) = self.squares[y][x] else {
+                    empty += 1;
+                    continue;
+                };
+                if empty > 0 {
+                    rank.push_str(&empty.to_string());
+                    empty = 0;
+                }
+                if self.promoted[y][x] {
+                    rank.push('+');
+                }
+                rank.push(piece);
+            }
+            if empty > 0 {
+                rank.push_str(&empty.to_string());
+            }
+            ranks.push(rank);
+        }
+
+        // 持ち駒は飛角金銀桂香歩の順、先手（大文字）→後手（小文字）
+        let mut hands = String::new();
+        for (side, hand) in self.hands.iter().enumerate() {
+            for index in (0..HAND_PIECES.len()).rev() {
+                let count = hand[index];
+                if count == 0 {
+                    continue;
+                }
+                if count > 1 {
+                    hands.push_str(&count.to_string());
+                }
+                let piece = HAND_PIECES[index];
+                hands.push(if side == 0 {
+                    piece
+                } else {
+                    piece.to_ascii_lowercase()
+                });
+            }
+        }
+        if hands.is_empty() {
+            hands.push('-');
+        }
+
+        format!(
+            "{} {} {} {}",
+            ranks.join("/"),
+            if sente_to_move { 'b' } else { 'w' },
+            hands,
+            move_number
+        )
+    }
+
+    /// SFEN 文字列から盤面と手番（先手番なら true）を読み込む（"sfen " や "position sfen " は付いていてもよい）
+    pub fn from_sfen(sfen: &str) -> Result<(Board, bool)> {
+        let invalid = |message: &str| Error::query(message.to_string()).with_text(sfen);
+
+        let sfen = sfen.trim();
+        let sfen = sfen.strip_prefix("position ").unwrap_or(sfen);
+        let sfen = sfen.strip_prefix("sfen ").unwrap_or(sfen);
+        let mut fields = sfen.split_whitespace();
+        let placement = fields.next().ok_or_else(|| invalid("SFEN が空です"))?;
+        let side = fields.next().unwrap_or("b");
+        let hands = fields.next().unwrap_or("-");
+
+        let mut board = Board {
+            squares: [[None; 9]; 9],
+            promoted: [[false; 9]; 9],
+            hands: [[0; 7]; 2],
+        };
+
+        let ranks: Vec<&str> = placement.split('/').collect();
+        if ranks.len() != 9 {
+            return Err(invalid("SFEN の段の数が9ではありません"));
+        }
+        for (y, rank) in ranks.iter().enumerate() {
+            let mut x = 0;
+            let mut promoted = false;
+            for ch in rank.chars() {
+                if let Some(n) = ch.to_digit(10) {
+                    x += n as usize;
+                } else if ch == '+' {
+                    promoted = true;
+                    continue;
+                } else if "PLNSGBRKplnsgbrk".contains(ch) && x < 9 {
+                    board.squares[y][x] = Some(ch);
+                    board.promoted[y][x] = promoted;
+                    x += 1;
+                } else {
+                    return Err(invalid("SFEN に不正な駒があります"));
+                }
+                promoted = false;
+            }
+            if x != 9 {
+                return Err(invalid("SFEN の筋の数が9ではありません"));
+            }
+        }
 
+        let sente_to_move = match side {
+            "b" => true,
+            "w" => false,
+            _ => return Err(invalid("SFEN の手番は b か w で指定してください")),
+        };
+
+        if hands != "-" {
+            let mut count: u8 = 0;
+            for ch in hands.chars() {
+                if let Some(n) = ch.to_digit(10) {
+                    count = count
+                        .checked_mul(10)
+                        .and_then(|c| c.checked_add(n as u8))
+                        .ok_or_else(|| invalid("SFEN の持ち駒の数が多すぎます"))?;
+                    continue;
+                }
+                let index = HAND_PIECES
+                    .iter()
+                    .position(|&p| p == ch.to_ascii_uppercase())
+                    .ok_or_else(|| invalid("SFEN に不正な持ち駒があります"))?;
+                let side = if ch.is_ascii_uppercase() { 0 } else { 1 };
+                let hand = &mut board.hands[side][index];
+                *hand = hand
+                    .checked_add(count.max(1))
+                    .ok_or_else(|| invalid("SFEN の持ち駒の数が多すぎます"))?;
+                count = 0;
+            }
+        }
+
+        Ok((board, sente_to_move))
+    }
+
+    /// 自分から見た筋・段（後手は盤を 180 度回した位置）にある、成っていない自分の駒（大文字）
+    pub fn own_piece(&self, is_sente: bool, file: u8, rank: u8) -> Option<char> {
+        let (file, rank) = if is_sente {
+            (file, rank)
+        } else {
+            (10 - file, 10 - rank)
+        };
+        let (x, y) = (9 - file as usize, rank as usize - 1);
+        let piece = self.squares[y][x]?;
+        (piece.is_ascii_uppercase() == is_sente && !self.promoted[y][x])
+            .then(|| piece.to_ascii_uppercase())
+    }
+
+    /// 指し手（符号）を受け取って盤面に適用する（例: "５六歩(57)"）
+    pub fn apply_move(&mut self, fugo: &str, is_sente_turn: bool) -> Result<()> {
         let cleaned_fugo = fugo.replace("成", "");
 
         let re = Regex::new(r"(?P<to_file>[１２３４５６７８９])(?P<to_rank>[一二三四五六七八九])(?P<piece>..)（?(?P<from>[1-9]{2})?）?").unwrap();
@@ -104,18 +269,27 @@ impl Board {
             let to_rank_kanji = caps.name("to_rank").unwrap().as_str();
             let from = caps.name("from").map(|m| m.as_str());
 
-            let file = convert_kanji_to_digit(to_file_kanji)
-                .ok_or_else(|| format!("ファイルの全角数字が不正です: {}", to_file_kanji))?;
-            let rank = convert_kanji_to_rank(to_rank_kanji)
-                .ok_or_else(|| format!("ランクの漢数字が不正です: {}", to_rank_kanji))?;
+            let file = convert_kanji_to_digit(to_file_kanji).ok_or_else(|| {
+                Error::move_syntax(format!("ファイルの全角数字が不正です: {}", to_file_kanji))
+                    .with_text(fugo)
+            })?;
+            let rank = convert_kanji_to_rank(to_rank_kanji).ok_or_else(|| {
+                Error::move_syntax(format!("ランクの漢数字が不正です: {}", to_rank_kanji))
+                    .with_text(fugo)
+            })?;
 
             let to_x = 9 - file;
             let to_y = rank - 1;
 
             if fugo.ends_with("打") {
                 // 「打ち駒」はどこに打つかだけ分かればいいので、盤上にその駒を直接置く
+                let hand = hand_slot(self, piece, is_sente_turn)
+                    .filter(|count| **count > 0)
+                    .ok_or_else(|| Error::illegal_move("持ち駒がありません").with_text(fugo))?;
+                *hand -= 1;
                 self.squares[to_y][to_x] = Some(piece);
-                return Ok(());
+                self.promoted[to_y][to_x] = false;
+                Ok(())
             } else {
                 if let Some(from_str) = from {
                     let from_file = from_str.chars().nth(0).unwrap().to_digit(10).unwrap();
@@ -124,26 +298,48 @@ impl Board {
                     let from_x = 9 - from_file;
                     let from_y = from_rank - 1;
 
-                    if let Some(piece) = self.squares[from_y as usize][from_x as usize] {
-                        // 移動
-                        self.squares[from_y as usize][from_x as usize] = None;
-                        self.squares[to_y][to_x] = Some(piece);
-                    } else {
-                        println!("警告: 移動元に駒がありません ({} , {})", from_x, from_y);
+                    let Some(piece) = self.squares[from_y as usize][from_x as usize] else {
+                        eprintln!("警告: 移動元に駒がありません ({} , {})", from_x, from_y);
+                        return Ok(());
+                    };
+
+                    // 取った駒は成っていても元の駒として持ち駒に加える
+                    if let Some(captured) = self.squares[to_y][to_x]
+                        && let Some(hand) = hand_slot(self, captured, is_sente_turn)
+                    {
+                        *hand += 1;
                     }
 
+                    // 移動
+                    let promoted = self.promoted[from_y as usize][from_x as usize]
+                        || PROMOTE_RE.is_match(fugo);
+                    self.squares[from_y as usize][from_x as usize] = None;
+                    self.promoted[from_y as usize][from_x as usize] = false;
+                    self.squares[to_y][to_x] = Some(piece);
+                    self.promoted[to_y][to_x] = promoted;
+
                     Ok(())
                 } else {
-                    Err("未対応の指し手です".to_string())
+                    Err(Error::move_syntax("未対応の指し手です").with_text(fugo))
                 }
             }
         } else {
-            println!("不正な符号形式: {}", fugo);
-            Err("不正な符号形式です".to_string())
+            Err(Error::move_syntax("不正な符号形式です").with_text(fugo))
         }
     }
 }
 
+// 「角成」のように、この手で成る指し手（「成銀」「銀不成」は含まない）
+static PROMOTE_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"[歩香桂銀角飛]成").unwrap());
+
+/// 手番側の持ち駒のうち、駒 piece を入れる場所（玉など持ち駒にならない駒は None）
+fn hand_slot(board: &mut Board, piece: char, is_sente_turn: bool) -> Option<&mut u8> {
+    let index = HAND_PIECES
+        .iter()
+        .position(|&p| p == piece.to_ascii_uppercase())?;
+    Some(&mut board.hands[if is_sente_turn { 0 } else { 1 }][index])
+}
+
 pub fn convert_kanji_to_digit(c: &str) -> Option<usize> {
     let kanji_digits = ["１", "２", "３", "４", "５", "６", "７", "８", "９"];
     kanji_digits.iter().position(|&x| x == c).map(|i| i + 1)
@@ -176,3 +372,66 @@ pub fn convert_kanji_to_piece(piece_kanji: char, is_sente_turn: bool) -> char {
         lower
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCategory;
+
+    #[test]
+    fn test_apply_move_reports_error_category() {
+        let mut board = Board::new();
+
+        let err = board.apply_move("５五角打", true).unwrap_err();
+        assert_eq!(err.category, ErrorCategory::IllegalMove);
+        assert_eq!(err.location.text.as_deref(), Some("５五角打"));
+
+        // 移動元に駒がない手は警告だけで読み飛ばす
+        board.apply_move("７六歩(76)", true).unwrap();
+        assert_eq!(board.to_verbose_sfen(), Board::new().to_verbose_sfen());
+
+        let err = board.apply_move("投了", true).unwrap_err();
+        assert_eq!(err.category, ErrorCategory::MoveSyntax);
+    }
+
+    #[test]
+    fn test_hands_and_promotion() {
+        let mut board = Board::new();
+        for (te, fugo) in [
+            "７六歩(77)",
+            "３四歩(33)",
+            "２二角成(88)",
+            "同　銀(31)",
+            "５五角打",
+        ]
+        .iter()
+        .enumerate()
+        {
+            let fugo = fugo.replace("同　", "２二");
+            board.apply_move(&fugo, te % 2 == 0).unwrap();
+        }
+
+        assert_eq!(
+            board.to_sfen(false, 6),
+            "lnsgkg1nl/1r5s1/pppppp1pp/6p2/4B4/2P6/PP1PPPPPP/7R1/LNSGKGSNL w b 6"
+        );
+
+        let (parsed, sente_to_move) = Board::from_sfen(&board.to_sfen(false, 6)).unwrap();
+        assert_eq!(parsed, board);
+        assert!(!sente_to_move);
+
+        let err = board.apply_move("５四角打", true).unwrap_err();
+        assert_eq!(err.category, ErrorCategory::IllegalMove);
+    }
+
+    #[test]
+    fn test_from_sfen_rejects_huge_hands() {
+        const PLACEMENT: &str = "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b";
+        for hands in ["999P", "200P56P", "18446744073709551616p"] {
+            let err = Board::from_sfen(&format!("{} {} 1", PLACEMENT, hands)).unwrap_err();
+            assert_eq!(err.category, ErrorCategory::Query);
+        }
+        let (board, _) = Board::from_sfen(&format!("{} 18P2p 1", PLACEMENT)).unwrap();
+        assert_eq!(board.hands, [[18, 0, 0, 0, 0, 0, 0], [2, 0, 0, 0, 0, 0, 0]]);
+    }
+}
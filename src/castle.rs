@@ -0,0 +1,173 @@
+use crate::board::Board;
+use crate::error::{Error, Result};
+
+/// 囲いの形
+///
+/// 筋・段は自分から見た値（後手は盤を 180 度回して先手と同じ向きにする）。
+/// 玉は king の位置にいなければならず、pieces のうち tolerance 個までは
+/// 違う位置にあってもよい（組み上げる途中や、少し崩れた形も同じ囲いとみなす）
+pub struct Template {
+    pub label: &'static str,
+    pub groups: &'static [&'static str], // 検索でまとめて指定できる名前（例: "穴熊"）
+    pub king: (u8, u8),
+    pub pieces: &'static [(char, u8, u8)], // (成っていない自分の駒, 筋, 段)
+    pub tolerance: usize,
+}
+
+/// 囲いの形の一覧（合った形のうち、違う位置の駒が最も少ないものをその局面の囲いとする）
+///
+/// 似た形は、駒の多い（より組み上がった）ものを先に書く（同じ数ならこの順で先のもの）。ラベルに "," は使えない
+pub static TEMPLATES: &[Template] = &[
+    Template {
+        label: "居飛車穴熊",
+        groups: &["穴熊"],
+        king: (9, 9),
+        pieces: &[('L', 9, 8), ('N', 8, 9), ('S', 8, 8), ('G', 7, 8)],
+        tolerance: 1,
+    },
+    Template {
+        label: "振り飛車穴熊",
+        groups: &["穴熊"],
+        king: (1, 9),
+        pieces: &[('L', 1, 8), ('N', 2, 9), ('S', 2, 8), ('G', 3, 9)],
+        tolerance: 1,
+    },
+    Template {
+        label: "銀冠",
+        groups: &["美濃"],
+        king: (2, 8),
+        pieces: &[('S', 2, 7), ('G', 3, 8), ('P', 2, 6), ('P', 1, 6)],
+        tolerance: 1,
+    },
+    Template {
+        label: "高美濃",
+        groups: &["美濃"],
+        king: (2, 8),
+        pieces: &[('S', 3, 8), ('G', 4, 7), ('G', 4, 9), ('P', 4, 6)],
+        tolerance: 1,
+    },
+    Template {
+        label: "美濃囲い",
+        groups: &["美濃"],
+        king: (2, 8),
+        pieces: &[('S', 3, 8), ('G', 4, 9), ('G', 5, 8)],
+        tolerance: 1,
+    },
+    Template {
+        label: "矢倉囲い",
+        groups: &["矢倉"],
+        king: (8, 8),
+        pieces: &[('S', 7, 7), ('G', 7, 8), ('G', 6, 7), ('P', 7, 6)],
+        tolerance: 1,
+    },
+    Template {
+        label: "左美濃",
+        groups: &["美濃"],
+        king: (8, 8),
+        pieces: &[('S', 7, 8), ('G', 6, 9), ('G', 5, 8)],
+        tolerance: 1,
+    },
+    Template {
+        label: "エルモ囲い",
+        groups: &[],
+        king: (7, 9),
+        pieces: &[('S', 8, 8), ('G', 7, 8)],
+        tolerance: 0,
+    },
+    Template {
+        label: "舟囲い",
+        groups: &[],
+        king: (7, 8),
+        pieces: &[('G', 6, 9), ('G', 5, 8)],
+        tolerance: 0,
+    },
+    Template {
+        label: "雁木囲い",
+        groups: &[],
+        king: (6, 9),
+        pieces: &[('S', 6, 7), ('S', 4, 7), ('G', 7, 8), ('G', 5, 8)],
+        tolerance: 1,
+    },
+    Template {
+        label: "中住まい",
+        groups: &[],
+        king: (5, 8),
+        pieces: &[('G', 7, 8), ('G', 3, 8)],
+        tolerance: 0,
+    },
+];
+
+/// 局面で is_sente の側が組んでいる囲い（どの形にも合わなければ None）
+pub fn recognize(board: &Board, is_sente: bool) -> Option<&'static str> {
+    // 許容範囲内でも他の形の駒がそろっているだけのこともあるので、最も近い形を選ぶ
+    TEMPLATES
+        .iter()
+        .filter_map(|t| missing_pieces(t, board, is_sente).map(|missing| (t, missing)))
+        .min_by_key(|&(_, missing)| missing)
+        .map(|(t, _)| t.label)
+}
+
+/// 囲いの名前（ラベルかグループ名）を、当てはまるラベルの一覧にする
+pub fn expand(name: &str) -> Result<Vec<&'static str>> {
+    let labels: Vec<&'static str> = TEMPLATES
+        .iter()
+        .filter(|t| t.label == name || t.groups.contains(&name))
+        .map(|t| t.label)
+        .collect();
+    if labels.is_empty() {
+        return Err(Error::query("囲いの名前が正しくありません").with_text(name));
+    }
+    Ok(labels)
+}
+
+// 形に合っていれば違う位置にある駒の数、合っていなければ None
+fn missing_pieces(template: &Template, board: &Board, is_sente: bool) -> Option<usize> {
+    let (file, rank) = template.king;
+    if board.own_piece(is_sente, file, rank) != Some('K') {
+        return None;
+    }
+    let missing = template
+        .pieces
+        .iter()
+        .filter(|&&(piece, file, rank)| board.own_piece(is_sente, file, rank) != Some(piece))
+        .count();
+    (missing <= template.tolerance).then_some(missing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recognize_castles() {
+        // 先手は美濃囲い（6九の金が 5八に上がる前の片美濃の形）、後手は居飛車穴熊
+        let (board, _) =
+            Board::from_sfen("lnsg3nk/1r4gsl/ppppppppp/9/9/9/PPPPPPPPP/1BR3SK1/LNSG1G1NL b B 1")
+                .unwrap();
+        assert_eq!(recognize(&board, true), Some("美濃囲い"));
+        assert_eq!(recognize(&board, false), Some("居飛車穴熊"));
+
+        // 初期局面は囲いなし
+        let board = Board::new();
+        assert_eq!(recognize(&board, true), None);
+        assert_eq!(recognize(&board, false), None);
+    }
+
+    #[test]
+    fn test_recognize_closest_castle() {
+        // 本美濃から 4六歩と突いた形は、4七の金が足りない高美濃よりも美濃囲いに近い
+        let (board, _) = Board::from_sfen(
+            "lnsgkgsnl/1r5b1/ppppppppp/9/9/5P3/PPPPP1PPP/1B1RG1SK1/LNS2G1NL b - 1",
+        )
+        .unwrap();
+        assert_eq!(recognize(&board, true), Some("美濃囲い"));
+    }
+
+    #[test]
+    fn test_expand_names() {
+        assert_eq!(expand("穴熊").unwrap(), vec!["居飛車穴熊", "振り飛車穴熊"]);
+        assert_eq!(expand("高美濃").unwrap(), vec!["高美濃"]);
+        assert_eq!(expand("矢倉").unwrap(), vec!["矢倉囲い"]);
+        assert!(expand("ミレニアム").is_err());
+    }
+}
@@ -0,0 +1,636 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use kifquery::filter::{GameFilter, GameResult, Side};
+use kifquery::import::ImportReport;
+use kifquery::profile::{self, PlayerProfile};
+use kifquery::repertoire::{self, Deviation, RepertoireKey};
+use kifquery::result_set::{self, Page, ResultSet, SortKey, SortOrder};
+use kifquery::search::{
+    self, ConditionOp, PositionQuery, Query, SearchCondition, SearchOptions, Transform,
+};
+use kifquery::{db, export, import, migrate, parser, stats};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser)]
+#[command(about = "こんな感じの局面が登場した棋譜を検索するツール")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>, // 省略時は serve
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// 棋譜ファイル（またはディレクトリ内の棋譜）を取り込む
+    Import {
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// 局面の条件で棋譜を検索する（--square と --sfen を同じ順番で指定）
+    Search {
+        /// マス番号（1〜81、9一が1・1九が81）
+        #[arg(long)]
+        square: Vec<String>,
+        /// 駒（先手は大文字、後手は小文字、成り駒は + を付ける。例: R, p, +B）。
+        /// empty（空きマス）, any（何かの駒）, mine（自分の駒）, theirs（相手の駒）, !P（歩以外）も指定できる
+        #[arg(long)]
+        sfen: Vec<String>,
+        /// AND / OR / NOT を組み合わせた検索条件（JSON）。--square / --sfen の条件と AND でつなぐ
+        #[arg(long)]
+        query: Option<String>,
+        #[command(flatten)]
+        options: Box<SearchArgs>,
+        #[command(flatten)]
+        page: PageArgs,
+        /// 場所・持ち時間・月・次の一手ごとの勝敗も表示する
+        #[arg(long)]
+        stats: bool,
+    },
+    /// 盤面・持ち駒・手番が完全に一致する局面を検索する
+    Position {
+        /// 局面の SFEN（例: "lnsgkgsnl/1r5b1/ppppppppp/9/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL w - 2"）
+        sfen: String,
+    },
+    /// 保存済みの検索結果の棋譜を COLLECTED_DIR/{id} にコピーする
+    Collect {
+        /// 検索結果の ID（/api/search の result_id）
+        id: u64,
+        /// ディレクトリではなく、この zip ファイルに書き出す
+        #[arg(long)]
+        zip: Option<PathBuf>,
+    },
+    /// DB のテーブルを現在の定義に合わせる（列の追加・既存局面の再計算）
+    Migrate {
+        /// 変換後に旧形式の c1〜c81 列を削除する
+        #[arg(long)]
+        drop_legacy: bool,
+    },
+    /// 取り込み済みの棋譜を出力する
+    Export {
+        id: u64,
+        #[arg(long, value_enum, default_value_t = ExportFormat::Kif)]
+        format: ExportFormat,
+    },
+    /// 取り込み済み棋譜の件数と勝敗を表示する
+    Stats {
+        /// 勝敗を集計する対局者のプロファイル名（省略時は MY_USERNAMES）
+        #[arg(long)]
+        profile: Option<String>,
+    },
+    /// 対局者のプロファイル（アカウント名のまとまり）を管理する
+    Profile {
+        #[command(subcommand)]
+        action: ProfileAction,
+    },
+    /// レパートリー（局面ごとの自分の手）を変化つきの KIF で読み書きする
+    Repertoire {
+        #[command(subcommand)]
+        action: RepertoireAction,
+    },
+    /// HTTPサーバーを起動する
+    Serve {
+        #[arg(long, default_value_t = 3000)]
+        port: u16,
+        /// KIF_PATH を監視して自動インポートする（環境変数 KIF_WATCH と同じ）
+        #[arg(long)]
+        watch: bool,
+    },
+}
+
+/// 検索のオプション（対局情報・手数による絞り込み）
+#[derive(Args)]
+pub struct SearchArgs {
+    /// 左右反転（1筋⇔9筋）した条件でも検索する
+    #[arg(long)]
+    mirror: bool,
+    /// 自分の対局に限らず、全ての棋譜を先手・後手両方の視点で検索する（MY_USERNAMES が未設定なら常にこの動作）
+    #[arg(long)]
+    everyone: bool,
+    /// 視点にする対局者のプロファイル名（省略時は MY_USERNAMES）
+    #[arg(long)]
+    profile: Option<String>,
+    /// 対戦相手の名前（* は任意の文字列。例: "Ringo*"）
+    #[arg(long)]
+    opponent: Option<String>,
+    /// この日時以降に開始した対局（例: 2025-07-01）
+    #[arg(long)]
+    from: Option<String>,
+    /// この日時までに開始した対局（日付だけならその日を含む）
+    #[arg(long)]
+    to: Option<String>,
+    /// 場所（例: 将棋ウォーズ, 81Dojo）
+    #[arg(long)]
+    site: Option<String>,
+    /// 持ち時間（例: 15分+60秒）
+    #[arg(long)]
+    time_control: Option<String>,
+    /// 自分から見た勝敗
+    #[arg(long, value_enum)]
+    result: Option<ResultArg>,
+    /// 自分の手番
+    #[arg(long, value_enum)]
+    side: Option<SideArg>,
+    /// 自分の戦型（例: 四間飛車, 居飛車, 角換わり）
+    #[arg(long)]
+    opening: Option<String>,
+    /// 相手の戦型
+    #[arg(long)]
+    opponent_opening: Option<String>,
+    /// 対局中に自分が組んだ囲い（例: 美濃囲い。穴熊・美濃・矢倉はまとめて指定できる）
+    #[arg(long)]
+    castle: Option<String>,
+    /// 対局中に相手が組んだ囲い
+    #[arg(long)]
+    opponent_castle: Option<String>,
+    /// この手数以降の局面だけを探す
+    #[arg(long)]
+    te_from: Option<i32>,
+    /// この手数までの局面だけを探す
+    #[arg(long)]
+    te_to: Option<i32>,
+}
+
+/// 検索結果の並べ替えと表示する範囲
+#[derive(Args)]
+pub struct PageArgs {
+    /// 並べ替えの基準
+    #[arg(long, value_enum, default_value_t = SortArg::StartedAt)]
+    sort: SortArg,
+    /// 昇順に並べる（既定は降順）
+    #[arg(long)]
+    asc: bool,
+    /// 表示する件数
+    #[arg(long)]
+    limit: Option<usize>,
+    /// 先頭から読み飛ばす件数
+    #[arg(long, default_value_t = 0)]
+    offset: usize,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SortArg {
+    StartedAt,
+    Te,
+    Length,
+    Result,
+    Opponent,
+}
+
+impl PageArgs {
+    fn into_page(self) -> Page {
+        Page {
+            offset: self.offset,
+            limit: self.limit,
+            sort: match self.sort {
+                SortArg::StartedAt => SortKey::StartedAt,
+                SortArg::Te => SortKey::Te,
+                SortArg::Length => SortKey::Length,
+                SortArg::Result => SortKey::Result,
+                SortArg::Opponent => SortKey::Opponent,
+            },
+            order: if self.asc {
+                SortOrder::Asc
+            } else {
+                SortOrder::Desc
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ResultArg {
+    Win,
+    Loss,
+    Draw,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SideArg {
+    Sente,
+    Gote,
+}
+
+impl From<SideArg> for Side {
+    fn from(side: SideArg) -> Side {
+        match side {
+            SideArg::Sente => Side::Sente,
+            SideArg::Gote => Side::Gote,
+        }
+    }
+}
+
+impl SearchArgs {
+    fn into_options(self) -> SearchOptions {
+        SearchOptions {
+            mirror: self.mirror,
+            everyone: self.everyone,
+            profile: self.profile,
+            filter: GameFilter {
+                opponent: self.opponent,
+                started_from: self.from,
+                started_to: self.to,
+                site: self.site,
+                time_control: self.time_control,
+                result: self.result.map(|r| match r {
+                    ResultArg::Win => GameResult::Win,
+                    ResultArg::Loss => GameResult::Loss,
+                    ResultArg::Draw => GameResult::Draw,
+                }),
+                side: self.side.map(Side::from),
+                opening: self.opening,
+                opponent_opening: self.opponent_opening,
+                castle: self.castle,
+                opponent_castle: self.opponent_castle,
+                te_from: self.te_from,
+                te_to: self.te_to,
+            },
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum ProfileAction {
+    /// 保存済みのプロファイルを表示する
+    List,
+    /// プロファイルを保存する（同じ名前があれば置き換える）
+    Set {
+        name: String,
+        /// 将棋ウォーズ・81道場などのアカウント名
+        #[arg(required = true)]
+        aliases: Vec<String>,
+    },
+    /// プロファイルを削除する
+    Delete { name: String },
+}
+
+#[derive(Subcommand)]
+pub enum RepertoireAction {
+    /// 変化つきの KIF からレパートリーを作り直す（保存済みの手は置き換える）
+    Import {
+        path: PathBuf,
+        #[command(flatten)]
+        key: RepertoireArgs,
+    },
+    /// レパートリーを変化つきの KIF で出力する
+    Export {
+        #[command(flatten)]
+        key: RepertoireArgs,
+        /// 出力先のファイル（省略時は標準出力）
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+/// どのレパートリーか
+#[derive(Args)]
+pub struct RepertoireArgs {
+    /// レパートリーでの自分の手番
+    #[arg(long, value_enum)]
+    side: SideArg,
+    /// 対局者のプロファイル名（省略時は MY_USERNAMES のレパートリー）
+    #[arg(long)]
+    profile: Option<String>,
+}
+
+impl RepertoireArgs {
+    fn into_key(self) -> RepertoireKey {
+        RepertoireKey {
+            profile: self.profile,
+            side: self.side.into(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ExportFormat {
+    Kif,
+    Csa,
+    Jkf,
+}
+
+pub fn run_import(paths: &[PathBuf]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut conn = db::get_conn()?;
+    let mut report = ImportReport::default();
+
+    for path in paths {
+        if path.is_dir() {
+            let dir_report = import::import_dir(&mut conn, path)?;
+            report.imported.extend(dir_report.imported);
+            report.failed.extend(dir_report.failed);
+            report.deviations.extend(dir_report.deviations);
+            continue;
+        }
+
+        println!("\n=== 処理中: {} ===", path.display());
+        let result = import::import_kif_file(&mut conn, &path.to_string_lossy());
+        report.record(path, result);
+    }
+
+    println!(
+        "取り込み: {}件, 失敗: {}件",
+        report.imported.len(),
+        report.failed.len()
+    );
+    for e in &report.failed {
+        println!("  {}", e);
+    }
+    for d in &report.deviations {
+        print_deviation(d);
+    }
+
+    // スクリプトから失敗を検知できるよう、1件でも失敗したらエラー終了にする
+    if !report.failed.is_empty() {
+        return Err(format!("{}件の棋譜を取り込めませんでした", report.failed.len()).into());
+    }
+
+    Ok(())
+}
+
+pub fn run_search(
+    squares: &[String],
+    sfens: &[String],
+    query: Option<&str>,
+    options: SearchArgs,
+    page: PageArgs,
+    show_stats: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if squares.len() != sfens.len() {
+        return Err("--square と --sfen の数が一致しません".into());
+    }
+
+    let mut queries: Vec<Query> = squares
+        .iter()
+        .zip(sfens)
+        .map(|(c, sfen)| Query::Condition(parse_condition(c, sfen)))
+        .collect();
+    if let Some(json) = query {
+        queries.push(serde_json::from_str(json)?);
+    }
+
+    let options = options.into_options();
+
+    let mut conn = db::get_conn()?;
+    let links = search::find_games(&mut conn, &Query::All(queries), &options)?;
+
+    // collect で書き出せるように、HTTP での検索と同じく結果を保存する
+    let request = serde_json::json!({ "square": squares, "sfen": sfens, "query": query });
+    let id = result_set::save(&mut conn, &request.to_string(), &links)?;
+    let result = ResultSet {
+        id,
+        created_at: String::new(),
+        links,
+    };
+    let search_stats = if show_stats {
+        Some(stats::for_result_set(&mut conn, &result)?)
+    } else {
+        None
+    };
+    let result = result.into_page(&mut conn, &page.into_page())?;
+
+    for link in &result.links {
+        println!(
+            "{}\t{}手目\t{}\t{}\t{}\t{}\t{}\t{}",
+            link.started_at.as_deref().unwrap_or("-"),
+            link.te,
+            link.fugo,
+            link.player,
+            if link.is_sente { "先手" } else { "後手" },
+            if link.is_draw {
+                "引き分け"
+            } else if link.is_win {
+                "勝ち"
+            } else {
+                "負け"
+            },
+            match link.transform {
+                Transform::AsIs | Transform::Flipped => "-",
+                Transform::Mirrored | Transform::FlippedMirrored => "左右反転",
+            },
+            link.link
+        );
+    }
+    let all = result.summary.all;
+    println!(
+        "検索結果: {}件（ID: {}）{}勝 {}敗 {}分",
+        result.total, result.result_id, all.wins, all.losses, all.draws
+    );
+
+    if let Some(search_stats) = search_stats {
+        for (title, buckets) in [
+            ("場所", &search_stats.by_site),
+            ("持ち時間", &search_stats.by_time_control),
+            ("月", &search_stats.by_month),
+            ("次の一手", &search_stats.by_next_move),
+        ] {
+            println!("\n{}", title);
+            for bucket in buckets {
+                let r = bucket.record;
+                println!(
+                    "  {}\t{}局\t{}勝 {}敗 {}分\t勝率 {:.1}%",
+                    bucket.key,
+                    r.games,
+                    r.wins,
+                    r.losses,
+                    r.draws,
+                    bucket.win_rate * 100.0
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// --sfen の値を条件に変換する
+fn parse_condition(c: &str, sfen: &str) -> SearchCondition {
+    let (op, sfen) = match sfen {
+        "empty" => (ConditionOp::Empty, ""),
+        "any" => (ConditionOp::Occupied, ""),
+        "mine" => (ConditionOp::Mine, ""),
+        "theirs" => (ConditionOp::Theirs, ""),
+        _ => match sfen.strip_prefix('!') {
+            Some(piece) => (ConditionOp::Not, piece),
+            None => (ConditionOp::Eq, sfen),
+        },
+    };
+
+    SearchCondition {
+        c: c.to_string(),
+        sfen: sfen.to_string(),
+        op,
+    }
+}
+
+pub fn run_position(sfen: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut conn = db::get_conn()?;
+    let query = PositionQuery::Sfen {
+        sfen: sfen.to_string(),
+    };
+    let hits = search::find_position(&mut conn, &query)?;
+
+    for hit in &hits {
+        println!(
+            "{}\t{}手目\t{}\t{} vs {}\t{}",
+            hit.started_at.as_deref().unwrap_or("-"),
+            hit.te,
+            hit.fugo,
+            hit.sente_player,
+            hit.gote_player,
+            hit.link
+        );
+    }
+    println!("検索結果: {}件", hits.len());
+
+    Ok(())
+}
+
+pub fn run_collect(id: u64, zip: Option<&Path>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut conn = db::get_conn()?;
+    let result = result_set::fetch(&mut conn, id)?;
+
+    if let Some(path) = zip {
+        std::fs::write(path, result_set::to_zip(&result)?)?;
+        println!("書き出しました: {}", path.display());
+        return Ok(());
+    }
+
+    let report = result_set::collect_to_dir(&result)?;
+    println!(
+        "書き出しました: {}（{}件）",
+        report.dir,
+        report.copied.len()
+    );
+    for filename in &report.missing {
+        println!("  見つかりません: {}", filename);
+    }
+    Ok(())
+}
+
+pub fn run_migrate(drop_legacy: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let mut conn = db::get_conn()?;
+    migrate::run(&mut conn)?;
+    if drop_legacy {
+        migrate::drop_legacy_columns(&mut conn)?;
+    }
+    println!("DB の更新が完了しました");
+    Ok(())
+}
+
+pub fn run_export(id: u64, format: ExportFormat) -> Result<(), Box<dyn std::error::Error>> {
+    let mut conn = db::get_conn()?;
+    let header = db::fetch_kif_header(&mut conn, id)?
+        .ok_or_else(|| format!("棋譜が見つかりません: id={}", id))?;
+    let moves = db::fetch_kif_moves(&mut conn, id)?;
+
+    let output = match format {
+        ExportFormat::Kif => export::to_kif(&header, &moves),
+        ExportFormat::Csa => export::to_csa(&header, &moves)?,
+        ExportFormat::Jkf => serde_json::to_string_pretty(&export::to_jkf(&header, &moves)?)?,
+    };
+    print!("{}", output);
+
+    Ok(())
+}
+
+pub fn run_stats(profile: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut conn = db::get_conn()?;
+    let Some(usernames) = profile::resolve(&mut conn, profile)? else {
+        let summary = db::fetch_game_summary(&mut conn, &[])?;
+        println!(
+            "棋譜数: {}件, 局面数: {}件（対局者が未指定のため勝敗は表示しません）",
+            summary.games, summary.positions
+        );
+        return Ok(());
+    };
+    let summary = db::fetch_game_summary(&mut conn, &usernames)?;
+
+    let games = summary.sente_games + summary.gote_games;
+    let wins = summary.sente_wins + summary.gote_wins;
+    println!(
+        "棋譜数: {}件, 局面数: {}件",
+        summary.games, summary.positions
+    );
+    println!("全て： {}", format_record(wins, games));
+    println!(
+        "先手： {}",
+        format_record(summary.sente_wins, summary.sente_games)
+    );
+    println!(
+        "後手： {}",
+        format_record(summary.gote_wins, summary.gote_games)
+    );
+
+    Ok(())
+}
+
+pub fn run_profile(action: ProfileAction) -> Result<(), Box<dyn std::error::Error>> {
+    let mut conn = db::get_conn()?;
+    match action {
+        ProfileAction::List => {
+            for p in profile::list(&mut conn)? {
+                println!("{}\t{}", p.name, p.aliases.join(", "));
+            }
+        }
+        ProfileAction::Set { name, aliases } => {
+            let p = PlayerProfile::new(&name, &aliases)?;
+            profile::save(&mut conn, &p)?;
+            println!("保存しました: {}（{}）", p.name, p.aliases.join(", "));
+        }
+        ProfileAction::Delete { name } => {
+            if !profile::delete(&mut conn, &name)? {
+                return Err(format!("プロファイルが見つかりません: {}", name).into());
+            }
+            println!("削除しました: {}", name);
+        }
+    }
+    Ok(())
+}
+
+pub fn run_repertoire(action: RepertoireAction) -> Result<(), Box<dyn std::error::Error>> {
+    let mut conn = db::get_conn()?;
+    match action {
+        RepertoireAction::Import { path, key } => {
+            let (contents, _) = parser::read_kif_file(&path.to_string_lossy())?;
+            let result = repertoire::import_kif(&mut conn, &key.into_key(), &contents)?;
+            println!("レパートリーを保存しました: {}手", result.moves);
+            if result.skipped > 0 {
+                println!(
+                    "  自分の手番で別の手が先に出てきたため読まなかった変化: {}件",
+                    result.skipped
+                );
+            }
+        }
+        RepertoireAction::Export { key, output } => {
+            let kif = repertoire::to_kif(&mut conn, &key.into_key())?;
+            match output {
+                Some(path) => {
+                    std::fs::write(&path, kif)?;
+                    println!("書き出しました: {}", path.display());
+                }
+                None => print!("{}", kif),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// レパートリーから外れた手を表示する（取り込み時）
+pub fn print_deviation(d: &Deviation) {
+    println!(
+        "  レパートリーから外れました: {} {}手目 {}（{}、レパートリー: {}）{}",
+        d.kif_filename,
+        d.te,
+        d.fugo,
+        if d.by_me { "自分" } else { "相手" },
+        d.expected.join(" / "),
+        d.kif_url
+    );
+}
+
+/// "{勝}勝 {敗}敗 (勝率: xx.xx%)" の形式にする
+fn format_record(wins: u64, games: u64) -> String {
+    let rate = if games > 0 {
+        wins as f64 / games as f64 * 100.0
+    } else {
+        0.0
+    };
+    format!("{}勝 {}敗 (勝率: {:.2}%)", wins, games - wins, rate)
+}
@@ -25,9 +25,39 @@ pub static COLLECTED_DIR: Lazy<PathBuf> = Lazy::new(|| {
 });
 
 lazy_static! {
-    pub static ref MY_USERNAMES: Vec<String> = std::env::var("MY_USERNAMES")
-        .unwrap_or_default()
-        .split(',')
-        .map(|s| s.trim().to_string())
-        .collect();
+    // 未設定なら空（検索は全員の棋譜が対象になる）
+    pub static ref MY_USERNAMES: Vec<String> = {
+        once_cell::sync::Lazy::force(&_INIT);
+        std::env::var("MY_USERNAMES")
+            .unwrap_or_default()
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect()
+    };
 }
+
+// KIF_PATH を監視して自動インポートするか（"true" または "1" で有効）
+pub static KIF_WATCH: Lazy<bool> = Lazy::new(|| {
+    once_cell::sync::Lazy::force(&_INIT);
+    env::var("KIF_WATCH")
+        .map(|v| matches!(v.trim(), "true" | "1"))
+        .unwrap_or(false)
+});
+
+// 書き込みが落ち着くまで待つ時間（ミリ秒）
+pub static KIF_WATCH_DEBOUNCE_MS: Lazy<u64> = Lazy::new(|| {
+    once_cell::sync::Lazy::force(&_INIT);
+    env::var("KIF_WATCH_DEBOUNCE_MS")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(2000)
+});
+
+// 全局面をメモリ上の索引に読み込んで検索するか（"true" または "1" で有効）
+pub static POSITION_INDEX: Lazy<bool> = Lazy::new(|| {
+    once_cell::sync::Lazy::force(&_INIT);
+    env::var("POSITION_INDEX")
+        .map(|v| matches!(v.trim(), "true" | "1"))
+        .unwrap_or(false)
+});
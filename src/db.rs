@@ -2,11 +2,13 @@ use dotenvy::dotenv;
 use mysql::prelude::*;
 use mysql::*;
 use mysql::{Opts, Pool};
+
+use crate::error::{Error, ErrorCategory, Result};
 use std::env;
-pub fn get_conn() -> Result<PooledConn, Box<dyn std::error::Error>> {
+pub fn get_conn() -> Result<PooledConn> {
     dotenv().ok();
 
-    let url = env::var("DATABASE_URL")?;
+    let url = env::var("DATABASE_URL").map_err(|_| Error::config("DATABASE_URL must be set"))?;
     let opts = Opts::from_url(&url)?;
     let pool = Pool::new(opts)?;
     let conn = pool.get_conn()?;
@@ -15,80 +17,100 @@ pub fn get_conn() -> Result<PooledConn, Box<dyn std::error::Error>> {
 }
 
 use crate::models::KifHeader;
-pub fn insert_kif_header(
-    conn: &mut PooledConn,
-    header: &KifHeader,
-) -> Result<u64, Box<dyn std::error::Error>> {
+use crate::opening;
+pub fn insert_kif_header(conn: &mut PooledConn, header: &KifHeader) -> Result<u64> {
     conn.exec_drop(
         "DELETE FROM kif_headers WHERE kif_filename = ?",
         (&header.kif_filename,),
     )?;
 
+    // 列が多くタプルでは渡せないので、値の並びで渡す
+    let params: Vec<Value> = vec![
+        header.kif_filename.clone().into(),
+        header.sente_player.clone().into(),
+        header.gote_player.clone().into(),
+        header.is_sente_win.into(),
+        header.is_draw.into(),
+        header.started_at.clone().into(),
+        header.ended_at.clone().into(),
+        // 不明な場合は空文字（NULL は未設定として migrate で埋める）
+        header.site.clone().unwrap_or_default().into(),
+        header.time_control.clone().unwrap_or_default().into(),
+        header.end_reason.clone().unwrap_or_default().into(),
+        opening::join_labels(&header.sente_openings).into(),
+        opening::join_labels(&header.gote_openings).into(),
+        header.created_at.clone().into(),
+        header.created_by.clone().into(),
+    ];
+
     // INSERT
     conn.exec_drop(
         r"INSERT INTO kif_headers (
-            kif_filename, sente_player, gote_player, is_sente_win,
-            started_at, ended_at, created_at, created_by
-        ) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
-        (
-            &header.kif_filename,
-            &header.sente_player,
-            &header.gote_player,
-            header.is_sente_win,
-            &header.started_at,
-            &header.ended_at,
-            &header.created_at,
-            &header.created_by,
-        ),
+            kif_filename, sente_player, gote_player, is_sente_win, is_draw,
+            started_at, ended_at, site, time_control, end_reason, sente_openings, gote_openings,
+            created_at, created_by
+        ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        params,
     )?;
 
     Ok(conn.last_insert_id())
 }
 
 use crate::models::KifBody;
-pub fn insert_kif_bodies(
-    conn: &mut PooledConn,
-    bodies: &[KifBody],
-) -> Result<(), Box<dyn std::error::Error>> {
+use crate::packed::{self, BITBOARD_PIECES, Bitboards};
+pub fn insert_kif_bodies(conn: &mut PooledConn, bodies: &[KifBody]) -> Result<()> {
     // 削除（kif_id で）
     // conn.exec_drop("DELETE FROM kif_bodies WHERE kif_id = ?", (kif_id,))?;
 
-    let stmt = r"INSERT INTO kif_bodies (
-        kif_id, te, fugo,
-        c1, c2, c3, c4, c5, c6, c7, c8, c9,
-        c10, c11, c12, c13, c14, c15, c16, c17, c18,
-        c19, c20, c21, c22, c23, c24, c25, c26, c27,
-        c28, c29, c30, c31, c32, c33, c34, c35, c36,
-        c37, c38, c39, c40, c41, c42, c43, c44, c45,
-        c46, c47, c48, c49, c50, c51, c52, c53, c54,
-        c55, c56, c57, c58, c59, c60, c61, c62, c63,
-        c64, c65, c66, c67, c68, c69, c70, c71, c72,
-        c73, c74, c75, c76, c77, c78, c79, c80, c81
-    ) VALUES (
-        ?, ?, ?,
-        ?, ?, ?, ?, ?, ?, ?, ?, ?,
-        ?, ?, ?, ?, ?, ?, ?, ?, ?,
-        ?, ?, ?, ?, ?, ?, ?, ?, ?,
-        ?, ?, ?, ?, ?, ?, ?, ?, ?,
-        ?, ?, ?, ?, ?, ?, ?, ?, ?,
-        ?, ?, ?, ?, ?, ?, ?, ?, ?,
-        ?, ?, ?, ?, ?, ?, ?, ?, ?,
-        ?, ?, ?, ?, ?, ?, ?, ?, ?,
-        ?, ?, ?, ?, ?, ?, ?, ?, ? 
-    )";
-
-    let params_vec: Vec<_> = bodies
+    let mut columns: Vec<String> = [
+        "kif_id",
+        "te",
+        "fugo",
+        "eval",
+        "position_hash",
+        "packed",
+        "sente_castle",
+        "gote_castle",
+    ]
+    .iter()
+    .map(|c| c.to_string())
+    .chain(bitboard_columns())
+    .collect();
+
+    // 旧形式の c1〜c81 列が残っている間は、そちらにも書き込む
+    let legacy = has_legacy_columns(conn)?;
+    if legacy {
+        columns.extend((1..=81).map(|c| format!("c{}", c)));
+    }
+
+    let stmt = format!(
+        "INSERT INTO kif_bodies ({}) VALUES ({})",
+        columns.join(", "),
+        vec!["?"; columns.len()].join(", ")
+    );
+
+    let params_vec: Vec<Vec<Value>> = bodies
         .iter()
         .map(|b| {
-            let flat: Vec<_> = b
-                .board
-                .iter()
-                .map(|c| c.map(|x| x.to_string()).unwrap_or_default())
-                .collect();
-            let flat_str: Vec<&str> = flat.iter().map(|s| s.as_str()).collect();
-
-            let mut v: Vec<Value> = vec![b.kif_id.into(), b.te.into(), b.fugo.clone().into()];
-            v.extend(flat_str.iter().map(|s| s.to_string().into()));
+            let mut v: Vec<Value> = vec![
+                b.kif_id.into(),
+                b.te.into(),
+                b.fugo.clone().into(),
+                b.eval.into(),
+                b.position_hash.into(),
+                b.packed.clone().into(),
+                // 囲いがなければ空文字（NULL は未判定として migrate で埋める）
+                b.sente_castle.unwrap_or_default().into(),
+                b.gote_castle.unwrap_or_default().into(),
+            ];
+            v.extend(bitboard_params(&b.bitboards));
+            if legacy {
+                v.extend(
+                    b.board
+                        .iter()
+                        .map(|c| c.map(|x| x.to_string()).unwrap_or_default().into()),
+                );
+            }
             v
         })
         .collect();
@@ -97,3 +119,148 @@ pub fn insert_kif_bodies(
 
     Ok(())
 }
+
+/// ビットボードの列名（BITBOARD_PIECES の順 → 先手全体 → 後手全体 → 成り駒）
+pub fn bitboard_columns() -> Vec<String> {
+    BITBOARD_PIECES
+        .iter()
+        .filter_map(|&p| packed::column_name(p))
+        .chain(["bb_sente".to_string(), "bb_gote".to_string()])
+        .chain([packed::PROMOTED_COLUMN.to_string()])
+        .collect()
+}
+
+/// bitboard_columns と同じ順番のビットボードの値
+pub fn bitboard_params(bb: &Bitboards) -> Vec<Value> {
+    bb.pieces
+        .iter()
+        .chain([&bb.sente, &bb.gote, &bb.promoted])
+        .map(|&b| packed::to_bytes(b).into())
+        .collect()
+}
+
+/// kif_bodies に旧形式（1マス1列）の c1〜c81 列が残っているか
+pub fn has_legacy_columns(conn: &mut PooledConn) -> Result<bool> {
+    let count: Option<u64> = conn.query_first(
+        "SELECT COUNT(*) FROM information_schema.COLUMNS
+         WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = 'kif_bodies' AND COLUMN_NAME = 'c1'",
+    )?;
+    Ok(count.unwrap_or(0) > 0)
+}
+
+/// c1〜c81 列が残っていて、まだビットボードを計算していない局面があるか（migrate の途中など）
+pub fn has_pending_positions(conn: &mut PooledConn) -> Result<bool> {
+    if !has_legacy_columns(conn)? {
+        return Ok(false);
+    }
+    let pending: Option<bool> =
+        conn.query_first("SELECT EXISTS (SELECT 1 FROM kif_bodies WHERE packed IS NULL)")?;
+    Ok(pending.unwrap_or(false))
+}
+
+/// kif_headers から 1 件取得する
+pub fn fetch_kif_header(conn: &mut PooledConn, kif_id: u64) -> Result<Option<KifHeader>> {
+    // 列が多く 1 つのタプルに入らないので、行から列名で取り出す
+    let row: Option<Row> = conn.exec_first(
+        r"SELECT kif_filename, sente_player, gote_player, is_sente_win, is_draw,
+                DATE_FORMAT(started_at, '%Y-%m-%d %H:%i:%s') AS started_at,
+                DATE_FORMAT(ended_at, '%Y-%m-%d %H:%i:%s') AS ended_at,
+                site, time_control, end_reason, sente_openings, gote_openings,
+                DATE_FORMAT(created_at, '%Y-%m-%d %H:%i:%s') AS created_at, created_by
+            FROM kif_headers WHERE id = ?",
+        (kif_id,),
+    )?;
+    let Some(mut row) = row else {
+        return Ok(None);
+    };
+
+    let sente_openings: Option<String> = take_column(&mut row, "sente_openings")?;
+    let gote_openings: Option<String> = take_column(&mut row, "gote_openings")?;
+    Ok(Some(KifHeader {
+        kif_filename: take_column(&mut row, "kif_filename")?,
+        sente_player: take_column(&mut row, "sente_player")?,
+        gote_player: take_column(&mut row, "gote_player")?,
+        is_sente_win: take_column(&mut row, "is_sente_win")?,
+        is_draw: take_column(&mut row, "is_draw")?,
+        started_at: take_column(&mut row, "started_at")?,
+        ended_at: take_column(&mut row, "ended_at")?,
+        site: take_column::<Option<String>>(&mut row, "site")?.filter(|s| !s.is_empty()),
+        time_control: take_column::<Option<String>>(&mut row, "time_control")?
+            .filter(|s| !s.is_empty()),
+        end_reason: take_column::<Option<String>>(&mut row, "end_reason")?
+            .filter(|s| !s.is_empty()),
+        sente_openings: opening::split_labels(sente_openings.as_deref()),
+        gote_openings: opening::split_labels(gote_openings.as_deref()),
+        created_at: take_column(&mut row, "created_at")?,
+        created_by: take_column(&mut row, "created_by")?,
+    }))
+}
+
+// 行から列を 1 つ取り出す（列がない・型が合わないときは DB のエラー）
+fn take_column<T: FromValue>(row: &mut Row, name: &str) -> Result<T> {
+    match row.take_opt(name) {
+        Some(value) => Ok(value.map_err(|e| mysql::Error::FromValueError(e.0))?),
+        None => Err(Error::new(
+            ErrorCategory::Db,
+            format!("DBエラー: {} 列がありません", name),
+        )),
+    }
+}
+
+use crate::parser::Move;
+/// kif_bodies から指し手を手数順に取得する
+pub fn fetch_kif_moves(conn: &mut PooledConn, kif_id: u64) -> Result<Vec<Move>> {
+    let moves = conn.exec_map(
+        "SELECT te, fugo, eval FROM kif_bodies WHERE kif_id = ? ORDER BY te",
+        (kif_id,),
+        |(te, fugo, eval): (usize, String, Option<i32>)| Move {
+            te,
+            fugo,
+            eval,
+            line: None,
+        },
+    )?;
+
+    Ok(moves)
+}
+
+use crate::models::GameSummary;
+/// 棋譜数・局面数と、自分の先手/後手別の勝敗数を集計する
+pub fn fetch_game_summary(conn: &mut PooledConn, usernames: &[String]) -> Result<GameSummary> {
+    let games: Option<u64> = conn.query_first("SELECT COUNT(*) FROM kif_headers")?;
+    let positions: Option<u64> = conn.query_first("SELECT COUNT(*) FROM kif_bodies")?;
+
+    // IN () は構文エラーになるので、対局者の指定がなければ勝敗は 0 のまま返す
+    if usernames.is_empty() {
+        return Ok(GameSummary {
+            games: games.unwrap_or(0),
+            positions: positions.unwrap_or(0),
+            ..Default::default()
+        });
+    }
+
+    let placeholders = usernames.iter().map(|_| "?").collect::<Vec<_>>().join(", ");
+    let sql = format!(
+        r"SELECT
+            COALESCE(SUM(sente_player IN ({0})), 0),
+            COALESCE(SUM(sente_player IN ({0}) AND is_sente_win AND NOT is_draw), 0),
+            COALESCE(SUM(gote_player IN ({0})), 0),
+            COALESCE(SUM(gote_player IN ({0}) AND NOT is_sente_win AND NOT is_draw), 0)
+        FROM kif_headers",
+        placeholders
+    );
+    let params: Vec<Value> = (0..4)
+        .flat_map(|_| usernames.iter().cloned().map(Value::from))
+        .collect();
+    let (sente_games, sente_wins, gote_games, gote_wins): (u64, u64, u64, u64) =
+        conn.exec_first(sql, params)?.unwrap_or_default();
+
+    Ok(GameSummary {
+        games: games.unwrap_or(0),
+        positions: positions.unwrap_or(0),
+        sente_games,
+        sente_wins,
+        gote_games,
+        gote_wins,
+    })
+}
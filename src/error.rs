@@ -0,0 +1,165 @@
+use serde::Serialize;
+use std::fmt;
+
+/// エラーの分類（HTTP レスポンスなどで機械的に判別するためのもの）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCategory {
+    Encoding,    // 文字コードの判別・変換
+    Header,      // 対局情報（先手・後手など）の不備
+    MoveSyntax,  // 符号として解釈できない
+    IllegalMove, // 符号は読めるが盤面に適用できない
+    Query,       // 検索条件の不備
+    NotFound,    // 指定されたプロファイル・棋譜などが存在しない
+    Db,          // DB アクセス
+    Io,          // ファイルの読み書き
+    Config,      // 環境変数などの設定不備
+}
+
+/// エラーが起きた場所（分かる範囲で埋める）
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SourceLocation {
+    pub file: Option<String>, // 棋譜ファイル名
+    pub line: Option<usize>,  // 行番号（1始まり）
+    pub te: Option<usize>,    // 手数
+    pub text: Option<String>, // 問題のあった文字列
+}
+
+/// KifQuery のライブラリ API が返すエラー
+#[derive(Debug, Serialize)]
+pub struct Error {
+    pub category: ErrorCategory,
+    pub message: String,
+    #[serde(flatten)]
+    pub location: Box<SourceLocation>,
+    #[serde(skip)]
+    source: Option<Box<dyn std::error::Error + Send + Sync>>,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn new(category: ErrorCategory, message: impl Into<String>) -> Self {
+        Error {
+            category,
+            message: message.into(),
+            location: Box::default(),
+            source: None,
+        }
+    }
+
+    pub fn encoding(message: impl Into<String>) -> Self {
+        Self::new(ErrorCategory::Encoding, message)
+    }
+
+    pub fn header(message: impl Into<String>) -> Self {
+        Self::new(ErrorCategory::Header, message)
+    }
+
+    pub fn move_syntax(message: impl Into<String>) -> Self {
+        Self::new(ErrorCategory::MoveSyntax, message)
+    }
+
+    pub fn illegal_move(message: impl Into<String>) -> Self {
+        Self::new(ErrorCategory::IllegalMove, message)
+    }
+
+    pub fn query(message: impl Into<String>) -> Self {
+        Self::new(ErrorCategory::Query, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(ErrorCategory::NotFound, message)
+    }
+
+    pub fn config(message: impl Into<String>) -> Self {
+        Self::new(ErrorCategory::Config, message)
+    }
+
+    /// 棋譜ファイル名を付ける（既に付いていれば上書きしない）
+    pub fn with_file(mut self, file: impl Into<String>) -> Self {
+        self.location.file.get_or_insert_with(|| file.into());
+        self
+    }
+
+    pub fn with_line(mut self, line: usize) -> Self {
+        self.location.line = Some(line);
+        self
+    }
+
+    pub fn with_te(mut self, te: usize) -> Self {
+        self.location.te = Some(te);
+        self
+    }
+
+    pub fn with_text(mut self, text: impl Into<String>) -> Self {
+        self.location.text = Some(text.into());
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+
+        let loc = &self.location;
+        if let Some(file) = &loc.file {
+            write!(f, " [{}", file)?;
+            if let Some(line) = loc.line {
+                write!(f, ":{}", line)?;
+            }
+            write!(f, "]")?;
+        }
+        if let Some(te) = loc.te {
+            write!(f, " {}手目", te)?;
+        }
+        if let Some(text) = &loc.text {
+            write!(f, " 「{}」", text)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source
+            .as_deref()
+            .map(|e| e as &(dyn std::error::Error + 'static))
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        let mut err = Self::new(
+            ErrorCategory::Io,
+            format!("ファイル操作に失敗しました: {}", e),
+        );
+        err.source = Some(Box::new(e));
+        err
+    }
+}
+
+impl From<zip::result::ZipError> for Error {
+    fn from(e: zip::result::ZipError) -> Self {
+        let mut err = Self::new(
+            ErrorCategory::Io,
+            format!("zip の作成に失敗しました: {}", e),
+        );
+        err.source = Some(Box::new(e));
+        err
+    }
+}
+
+impl From<mysql::Error> for Error {
+    fn from(e: mysql::Error) -> Self {
+        let mut err = Self::new(ErrorCategory::Db, format!("DBエラー: {}", e));
+        err.source = Some(Box::new(e));
+        err
+    }
+}
+
+impl From<mysql::UrlError> for Error {
+    fn from(e: mysql::UrlError) -> Self {
+        mysql::Error::UrlError(e).into()
+    }
+}
@@ -0,0 +1,298 @@
+use crate::board::Board;
+use crate::error::{Error, Result};
+use crate::result_set::Record;
+use crate::search::PositionQuery;
+use crate::{packed, parser, profile, zobrist};
+use mysql::prelude::Queryable;
+use mysql::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// 定跡（オープニング）エクスプローラーの条件
+#[derive(Deserialize, Debug)]
+pub struct ExplorerRequest {
+    #[serde(flatten)]
+    pub position: PositionQuery,
+    #[serde(default)]
+    pub everyone: bool, // 全員の棋譜を、指した側から見た勝敗で集計する
+    #[serde(default)]
+    pub profile: Option<String>, // 勝敗を見る対局者（省略時は MY_USERNAMES）
+}
+
+/// 局面から指された手の一覧
+#[derive(Serialize, Debug)]
+pub struct Explorer {
+    #[serde(serialize_with = "zobrist::serialize_hash")]
+    pub hash: u64,
+    pub sfen: Option<String>,
+    pub games: u64, // この局面から次の手が指された対局数
+    pub moves: Vec<ExplorerMove>,
+}
+
+/// 局面から指された 1 つの手
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ExplorerMove {
+    pub fugo: String, // "同" は移動先に置き換えた符号
+    #[serde(serialize_with = "zobrist::serialize_hash")]
+    pub hash: u64, // 指した後の局面ハッシュ（次の問い合わせに使う）
+    pub sfen: Option<String>,
+    pub games: u64,            // この手が指された対局数（全員）
+    pub mine: Record,          // そのうち自分の対局の、自分から見た勝敗
+    pub win_rate: f64,         // mine の勝率（引き分けを除く）
+    pub avg_eval: Option<f64>, // 指した後の評価値の平均（先手から見た値。解析コメントがある棋譜のみ）
+}
+
+// 局面から次の手が指された 1 か所
+struct NextMove {
+    kif_id: u64,
+    prev_fugo: Option<String>, // 局面になった手（"同" の解決用。初期局面では None）
+    fugo: String,
+    hash: u64,
+    eval: Option<i32>,
+    te: i32,
+    sente_player: String,
+    gote_player: String,
+    is_sente_win: bool,
+    is_draw: bool,
+}
+
+// SQL の結果の 1 行（kif_id, 局面になった手, 次の手, 次の局面ハッシュ, 評価値, 次の手の手数,
+// sente_player, gote_player, is_sente_win, is_draw）
+type NextMoveRow = (
+    u64,
+    Option<String>,
+    String,
+    u64,
+    Option<i32>,
+    i32,
+    String,
+    String,
+    bool,
+    bool,
+);
+
+/// 局面から指された手を、指した後の局面ごとにまとめて返す
+///
+/// 局面ハッシュで探すので、手順の違いで合流した対局もまとめて数える
+pub fn explore(conn: &mut PooledConn, request: &ExplorerRequest) -> Result<Explorer> {
+    let players = if request.everyone {
+        None
+    } else {
+        profile::resolve(conn, request.profile.as_deref())?
+    };
+
+    let hash = request
+        .position
+        .position_hash(conn)?
+        .ok_or_else(|| Error::not_found("局面が見つかりません"))?;
+
+    // kif_bodies には 1 手目以降の局面しかないので、初期局面からは 1 手目を集める
+    let is_initial = hash == zobrist::initial_hash();
+    let sql = if is_initial {
+        r"SELECT n.kif_id, NULL, n.fugo, n.position_hash, n.eval, n.te,
+                h.sente_player, h.gote_player, h.is_sente_win, h.is_draw
+            FROM kif_bodies n
+            JOIN kif_headers h ON h.id = n.kif_id
+            WHERE n.te = 1 AND n.position_hash IS NOT NULL"
+    } else {
+        r"SELECT n.kif_id, b.fugo, n.fugo, n.position_hash, n.eval, n.te,
+                h.sente_player, h.gote_player, h.is_sente_win, h.is_draw
+            FROM kif_bodies b
+            JOIN kif_bodies n ON n.kif_id = b.kif_id AND n.te = b.te + 1
+            JOIN kif_headers h ON h.id = b.kif_id
+            WHERE b.position_hash = ? AND n.position_hash IS NOT NULL"
+    };
+    let params = if is_initial {
+        Params::Empty
+    } else {
+        Params::from((hash,))
+    };
+    let rows = conn.exec_map(
+        sql,
+        params,
+        |(
+            kif_id,
+            prev_fugo,
+            fugo,
+            hash,
+            eval,
+            te,
+            sente_player,
+            gote_player,
+            is_sente_win,
+            is_draw,
+        ): NextMoveRow| NextMove {
+            kif_id,
+            prev_fugo,
+            fugo,
+            hash,
+            eval,
+            te,
+            sente_player,
+            gote_player,
+            is_sente_win,
+            is_draw,
+        },
+    )?;
+
+    let games = rows.iter().map(|r| r.kif_id).collect::<HashSet<_>>().len() as u64;
+    let mut moves = tally(&rows, players.as_deref());
+    for m in &mut moves {
+        m.sfen = stored_sfen(conn, m.hash)?;
+    }
+
+    let sfen = if is_initial {
+        Some(Board::new().to_sfen(true, 1))
+    } else {
+        stored_sfen(conn, hash)?
+    };
+    Ok(Explorer {
+        hash,
+        sfen,
+        games,
+        moves,
+    })
+}
+
+// 指した後の局面ごとに対局数・勝敗・評価値を集計する（対局数の多い順）
+//
+// 同じ対局で同じ局面から同じ手を指した（千日手など）場合は 1 局として数える
+fn tally(rows: &[NextMove], players: Option<&[String]>) -> Vec<ExplorerMove> {
+    struct Entry {
+        fugo: String,
+        games: u64,
+        mine: Record,
+        eval_sum: i64,
+        eval_count: i64,
+    }
+
+    let mut seen = HashSet::new();
+    let mut entries: HashMap<u64, Entry> = HashMap::new();
+    for row in rows {
+        if !seen.insert((row.kif_id, row.hash)) {
+            continue;
+        }
+        let entry = entries.entry(row.hash).or_insert_with(|| Entry {
+            fugo: parser::normalize_fugo(&row.fugo, row.prev_fugo.as_deref()),
+            games: 0,
+            mine: Record::default(),
+            eval_sum: 0,
+            eval_count: 0,
+        });
+        entry.games += 1;
+        if let Some(eval) = row.eval {
+            entry.eval_sum += eval as i64;
+            entry.eval_count += 1;
+        }
+        if let Some(is_sente) = my_side(row, players) {
+            let is_win = !row.is_draw && row.is_sente_win == is_sente;
+            entry.mine.add(is_win, row.is_draw);
+        }
+    }
+
+    let mut moves: Vec<ExplorerMove> = entries
+        .into_iter()
+        .map(|(hash, e)| ExplorerMove {
+            fugo: e.fugo,
+            hash,
+            sfen: None,
+            games: e.games,
+            win_rate: e.mine.win_rate(),
+            mine: e.mine,
+            avg_eval: (e.eval_count > 0).then(|| e.eval_sum as f64 / e.eval_count as f64),
+        })
+        .collect();
+    moves.sort_by(|a, b| b.games.cmp(&a.games).then(a.fugo.cmp(&b.fugo)));
+    moves
+}
+
+// 自分がどちらの手番で対局したか（自分の対局でなければ None、全員なら指した側）
+fn my_side(row: &NextMove, players: Option<&[String]>) -> Option<bool> {
+    let mover_is_sente = row.te % 2 == 1;
+    let Some(players) = players else {
+        return Some(mover_is_sente);
+    };
+    let sente = players.contains(&row.sente_player);
+    let gote = players.contains(&row.gote_player);
+    match (sente, gote) {
+        (true, true) => Some(mover_is_sente),
+        (true, false) => Some(true),
+        (false, true) => Some(false),
+        (false, false) => None,
+    }
+}
+
+// 局面ハッシュの局面を、取り込み済みの棋譜から 1 つ取り出して SFEN にする
+fn stored_sfen(conn: &mut PooledConn, hash: u64) -> Result<Option<String>> {
+    let row: Option<(i32, Option<Vec<u8>>)> = conn.exec_first(
+        "SELECT te, packed FROM kif_bodies WHERE position_hash = ? LIMIT 1",
+        (hash,),
+    )?;
+    match row {
+        Some((te, Some(bytes))) => {
+            let (board, sente_to_move) = packed::unpack(&bytes)?;
+            Ok(Some(board.to_sfen(sente_to_move, te as usize + 1)))
+        }
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn next_move(kif_id: u64, fugo: &str, hash: u64, eval: Option<i32>) -> NextMove {
+        NextMove {
+            kif_id,
+            prev_fugo: Some("３四歩(33)".to_string()),
+            fugo: fugo.to_string(),
+            hash,
+            eval,
+            te: 3,
+            sente_player: "Ringosky".to_string(),
+            gote_player: "zzz208".to_string(),
+            is_sente_win: kif_id != 2,
+            is_draw: false,
+        }
+    }
+
+    #[test]
+    fn test_tally_next_moves() {
+        let rows = vec![
+            next_move(1, "２六歩(27)", 10, Some(100)),
+            next_move(2, "２六歩(27)", 10, None),
+            next_move(2, "２六歩(27)", 10, None), // 同じ対局で同じ局面に戻った
+            next_move(3, "同　角(88)", 20, Some(-50)),
+        ];
+        let players = vec!["Ringosky".to_string()];
+        let moves = tally(&rows, Some(&players));
+
+        assert_eq!(moves.len(), 2);
+        assert_eq!(moves[0].fugo, "２六歩(27)");
+        assert_eq!(moves[0].games, 2);
+        assert_eq!((moves[0].mine.wins, moves[0].mine.losses), (1, 1));
+        assert_eq!(moves[0].avg_eval, Some(100.0));
+        assert_eq!(moves[1].fugo, "３四角(88)");
+        assert_eq!(moves[1].hash, 20);
+
+        // 自分が指していない対局は勝敗に含めない
+        let others = vec!["someone".to_string()];
+        let moves = tally(&rows, Some(&others));
+        assert_eq!(moves[0].mine.games, 0);
+    }
+
+    #[test]
+    fn test_explorer_request() {
+        let request: ExplorerRequest =
+            serde_json::from_str(r#"{ "hash": "12345", "everyone": true }"#).unwrap();
+        assert!(matches!(request.position, PositionQuery::Hash { .. }));
+        assert!(request.everyone);
+
+        let request: ExplorerRequest =
+            serde_json::from_str(r#"{ "kif_id": 1, "te": 30, "profile": "ringo" }"#).unwrap();
+        assert!(matches!(
+            request.position,
+            PositionQuery::Stored { kif_id: 1, te: 30 }
+        ));
+    }
+}
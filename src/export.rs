@@ -0,0 +1,324 @@
+use crate::error::{Error, Result};
+use crate::models::KifHeader;
+use crate::parser::{self, Move, ParsedFugo};
+use serde_json::{Value, json};
+
+/// KIF形式で出力する
+pub fn to_kif(header: &KifHeader, moves: &[Move]) -> String {
+    let mut lines = Vec::new();
+
+    if let Some(started_at) = &header.started_at {
+        lines.push(format!("開始日時：{}", started_at.replace('-', "/")));
+    }
+    if let Some(ended_at) = &header.ended_at {
+        lines.push(format!("終了日時：{}", ended_at.replace('-', "/")));
+    }
+    if let Some(site) = &header.site {
+        lines.push(format!("場所：{}", site));
+    }
+    if let Some(time_control) = &header.time_control {
+        lines.push(format!("持ち時間：{}", time_control));
+    }
+    lines.push("手合割：平手".to_string());
+    lines.push(format!("先手：{}", header.sente_player));
+    lines.push(format!("後手：{}", header.gote_player));
+    lines.push("手数----指手---------消費時間--".to_string());
+
+    for m in moves {
+        lines.push(format!("{:>4} {}", m.te, m.fugo));
+    }
+
+    let last_te = moves.last().map(|m| m.te).unwrap_or(0);
+    if let Some((kif, _)) = end_special(header, last_te) {
+        lines.push(format!("{:>4} {}", last_te + 1, kif));
+    }
+    if header.is_draw {
+        lines.push(format!("まで{}手で引き分け", last_te));
+    } else {
+        lines.push(format!(
+            "まで{}手で{}の勝ち",
+            last_te,
+            if header.is_sente_win {
+                "先手"
+            } else {
+                "後手"
+            }
+        ));
+    }
+
+    lines.join("\n") + "\n"
+}
+
+/// 取り込み済みの棋譜ファイル（UTF-8 に変換済み）の文字コードの宣言を UTF-8 に書き換える
+pub fn to_utf8_kif(contents: &str) -> String {
+    let mut lines: Vec<String> = contents.lines().map(|l| l.to_string()).collect();
+    if let Some(first) = lines.first_mut()
+        && first.starts_with("#KIF")
+        && let Some(pos) = first.find("encoding=")
+    {
+        let end = first[pos..]
+            .find(char::is_whitespace)
+            .map_or(first.len(), |i| pos + i);
+        first.replace_range(pos..end, "encoding=UTF-8");
+    }
+    lines.join("\n") + "\n"
+}
+
+/// CSA形式で出力する
+pub fn to_csa(header: &KifHeader, moves: &[Move]) -> Result<String> {
+    let mut lines = vec![
+        "V2.2".to_string(),
+        format!("N+{}", header.sente_player),
+        format!("N-{}", header.gote_player),
+    ];
+    if let Some(started_at) = &header.started_at {
+        lines.push(format!("$START_TIME:{}", started_at.replace('-', "/")));
+    }
+    lines.push("PI".to_string());
+    lines.push("+".to_string());
+
+    for (m, parsed) in parse_moves(moves)? {
+        let (from_file, from_rank) = parsed.from.unwrap_or((0, 0));
+        let piece = csa_piece(&parsed.piece).ok_or_else(|| {
+            Error::move_syntax("CSAに変換できない駒です")
+                .with_te(m.te)
+                .with_text(&m.fugo)
+        })?;
+        let piece = if parsed.promote {
+            promote_csa_piece(piece)
+        } else {
+            piece
+        };
+
+        lines.push(format!(
+            "{}{}{}{}{}{}",
+            if m.te % 2 == 1 { '+' } else { '-' },
+            from_file,
+            from_rank,
+            parsed.to.0,
+            parsed.to.1,
+            piece
+        ));
+    }
+
+    let last_te = moves.last().map(|m| m.te).unwrap_or(0);
+    if let Some((_, csa)) = end_special(header, last_te) {
+        lines.push(format!("%{}", csa));
+    }
+
+    Ok(lines.join("\n") + "\n")
+}
+
+/// JSON棋譜フォーマット（JKF）で出力する
+pub fn to_jkf(header: &KifHeader, moves: &[Move]) -> Result<Value> {
+    let mut jkf_header = serde_json::Map::new();
+    jkf_header.insert("先手".to_string(), json!(header.sente_player));
+    jkf_header.insert("後手".to_string(), json!(header.gote_player));
+    if let Some(started_at) = &header.started_at {
+        jkf_header.insert("開始日時".to_string(), json!(started_at.replace('-', "/")));
+    }
+    if let Some(ended_at) = &header.ended_at {
+        jkf_header.insert("終了日時".to_string(), json!(ended_at.replace('-', "/")));
+    }
+
+    // JKF では先頭に初期局面用の空要素を置く
+    let mut jkf_moves = vec![json!({})];
+    let mut prev_to: Option<(usize, usize)> = None;
+
+    for (m, parsed) in parse_moves(moves)? {
+        let piece = csa_piece(&parsed.piece).ok_or_else(|| {
+            Error::move_syntax("JKFに変換できない駒です")
+                .with_te(m.te)
+                .with_text(&m.fugo)
+        })?;
+
+        let mut mv = json!({
+            "color": if m.te % 2 == 1 { 0 } else { 1 },
+            "to": { "x": parsed.to.0, "y": parsed.to.1 },
+            "piece": piece,
+        });
+        if let Some((x, y)) = parsed.from {
+            mv["from"] = json!({ "x": x, "y": y });
+        }
+        if parsed.promote {
+            mv["promote"] = json!(true);
+        }
+        if prev_to == Some(parsed.to) {
+            mv["same"] = json!(true);
+        }
+        prev_to = Some(parsed.to);
+
+        jkf_moves.push(json!({ "move": mv }));
+    }
+
+    let last_te = moves.last().map(|m| m.te).unwrap_or(0);
+    if let Some((_, csa)) = end_special(header, last_te) {
+        jkf_moves.push(json!({ "special": csa }));
+    }
+
+    Ok(json!({
+        "header": jkf_header,
+        "initial": { "preset": "HIRATE" },
+        "moves": jkf_moves,
+    }))
+}
+
+/// "同" を解決しながら全ての指し手を分解する
+fn parse_moves(moves: &[Move]) -> Result<Vec<(&Move, ParsedFugo)>> {
+    let mut parsed_moves = Vec::new();
+    let mut prev_fugo: Option<String> = None;
+
+    for m in moves {
+        let normalized_fugo = parser::normalize_fugo(&m.fugo, prev_fugo.as_deref());
+        let parsed = parser::parse_fugo(&normalized_fugo).ok_or_else(|| {
+            Error::move_syntax("不正な符号形式です")
+                .with_te(m.te)
+                .with_text(&m.fugo)
+        })?;
+        parsed_moves.push((m, parsed));
+        prev_fugo = Some(normalized_fugo);
+    }
+
+    Ok(parsed_moves)
+}
+
+/// 終局の指し手（KIF の表記, CSA・JKF の特殊な指し手）
+fn end_special(header: &KifHeader, last_te: usize) -> Option<(&'static str, &'static str)> {
+    match header.end_reason.as_deref().filter(|r| !r.is_empty()) {
+        Some("投了") => Some(("投了", "TORYO")),
+        Some("時間切れ") => Some(("切れ負け", "TIME_UP")),
+        Some("反則") => Some(("反則負け", "ILLEGAL_MOVE")),
+        Some("千日手") => Some(("千日手", "SENNICHITE")),
+        Some("持将棋") => Some(("持将棋", "JISHOGI")),
+        Some("詰み") => Some(("詰み", "TSUMI")),
+        Some("中断") => Some(("中断", "CHUDAN")),
+        Some(_) => None,
+        // 終局の理由がない棋譜は、勝った側が最後に指していれば投了とみなす
+        None => is_resigned(header, last_te).then_some(("投了", "TORYO")),
+    }
+}
+
+/// 勝った側が最後の手を指しているか（＝負けた側の投了で終わったか）
+fn is_resigned(header: &KifHeader, last_te: usize) -> bool {
+    !header.is_draw && last_te > 0 && (last_te % 2 == 1) == header.is_sente_win
+}
+
+/// 漢字の駒名を CSA の駒記号に変換する
+fn csa_piece(piece: &str) -> Option<&'static str> {
+    let code = match piece {
+        "歩" => "FU",
+        "香" => "KY",
+        "桂" => "KE",
+        "銀" => "GI",
+        "金" => "KI",
+        "角" => "KA",
+        "飛" => "HI",
+        "玉" | "王" => "OU",
+        "と" => "TO",
+        "成香" => "NY",
+        "成桂" => "NK",
+        "成銀" => "NG",
+        "馬" => "UM",
+        "龍" | "竜" => "RY",
+        _ => return None,
+    };
+    Some(code)
+}
+
+/// 成った後の CSA の駒記号
+fn promote_csa_piece(code: &'static str) -> &'static str {
+    match code {
+        "FU" => "TO",
+        "KY" => "NY",
+        "KE" => "NK",
+        "GI" => "NG",
+        "KA" => "UM",
+        "HI" => "RY",
+        _ => code,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> KifHeader {
+        KifHeader {
+            kif_filename: "test.kif".to_string(),
+            sente_player: "sente".to_string(),
+            gote_player: "gote".to_string(),
+            is_sente_win: true,
+            is_draw: false,
+            started_at: Some("2025-07-10 11:28:32".to_string()),
+            ended_at: None,
+            site: None,
+            time_control: None,
+            end_reason: Some("投了".to_string()),
+            sente_openings: Vec::new(),
+            gote_openings: Vec::new(),
+            created_at: "2025-07-10 12:00:00".to_string(),
+            created_by: "system".to_string(),
+        }
+    }
+
+    fn moves() -> Vec<Move> {
+        [
+            "７六歩(77)",
+            "３四歩(33)",
+            "２二角成(88)",
+            "同　銀(31)",
+            "５五角打",
+        ]
+        .iter()
+        .enumerate()
+        .map(|(i, fugo)| Move {
+            te: i + 1,
+            fugo: fugo.to_string(),
+            eval: None,
+            line: None,
+        })
+        .collect()
+    }
+
+    #[test]
+    fn test_to_csa() {
+        let csa = to_csa(&header(), &moves()).unwrap();
+        let body: Vec<&str> = csa.lines().skip(6).collect();
+        assert_eq!(
+            body,
+            vec![
+                "+7776FU", "-3334FU", "+8822UM", "-3122GI", "+0055KA", "%TORYO"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_end_reason() {
+        let mut header = header();
+        header.end_reason = Some("時間切れ".to_string());
+        assert!(to_kif(&header, &moves()).contains("   6 切れ負け\n"));
+        assert!(to_csa(&header, &moves()).unwrap().ends_with("%TIME_UP\n"));
+        let jkf = to_jkf(&header, &moves()).unwrap();
+        assert_eq!(jkf["moves"][6]["special"], json!("TIME_UP"));
+
+        // 終局の理由がない棋譜は、勝った側が最後に指していれば投了
+        header.end_reason = None;
+        assert!(to_kif(&header, &moves()).contains("   6 投了\n"));
+        header.is_sente_win = false;
+        assert!(!to_kif(&header, &moves()).contains("投了"));
+    }
+
+    #[test]
+    fn test_to_utf8_kif() {
+        let kif = to_utf8_kif("#KIF version=2.0 encoding=Shift_JIS\r\n先手：sente\r\n");
+        assert_eq!(kif, "#KIF version=2.0 encoding=UTF-8\n先手：sente\n");
+        assert_eq!(to_utf8_kif("先手：sente"), "先手：sente\n");
+    }
+
+    #[test]
+    fn test_to_jkf_marks_same_square() {
+        let jkf = to_jkf(&header(), &moves()).unwrap();
+        assert_eq!(jkf["moves"][4]["move"]["same"], json!(true));
+        assert_eq!(jkf["moves"][3]["move"]["promote"], json!(true));
+    }
+}
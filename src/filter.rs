@@ -0,0 +1,443 @@
+use crate::error::{Error, Result};
+use crate::index::IndexedGame;
+use crate::{castle, opening};
+use chrono::{NaiveDate, NaiveDateTime};
+use mysql::Value;
+use serde::Deserialize;
+
+/// 自分から見た勝敗
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GameResult {
+    Win,
+    Loss,
+    Draw,
+}
+
+/// 自分の手番
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Side {
+    Sente,
+    Gote,
+}
+
+/// 対局情報と手数による絞り込み（指定したものだけ、盤面の条件と AND でつなぐ）
+#[derive(Deserialize, Clone, Default, Debug)]
+#[serde(default)]
+pub struct GameFilter {
+    pub opponent: Option<String>,     // 対戦相手の名前（* は任意の文字列）
+    pub started_from: Option<String>, // 対局開始日時の範囲（"2025-07-01" または "2025-07-01 12:00:00"）
+    pub started_to: Option<String>,   // 日付だけならその日の終わりまで含む
+    pub site: Option<String>,         // 場所（例: 将棋ウォーズ, 81Dojo）
+    pub time_control: Option<String>, // 持ち時間（例: 15分+60秒）
+    pub result: Option<GameResult>,
+    pub side: Option<Side>,
+    pub opening: Option<String>, // 自分の戦型（opening::RULES のラベル。例: 四間飛車）
+    pub opponent_opening: Option<String>, // 相手の戦型
+    pub castle: Option<String>, // 対局中に自分が組んだ囲い（castle::TEMPLATES のラベルかグループ名。例: 穴熊）
+    pub opponent_castle: Option<String>, // 対局中に相手が組んだ囲い
+    pub te_from: Option<i32>,   // この手数以降の局面だけ
+    pub te_to: Option<i32>,     // この手数までの局面だけ
+}
+
+impl GameFilter {
+    /// 自分が先手（is_sente）の対局を検索対象にするか
+    pub fn includes_side(&self, is_sente: bool) -> bool {
+        match self.side {
+            Some(Side::Sente) => is_sente,
+            Some(Side::Gote) => !is_sente,
+            None => true,
+        }
+    }
+
+    /// 手数 te の局面を検索対象にするか
+    pub fn includes_te(&self, te: i32) -> bool {
+        self.te_from.is_none_or(|from| te >= from) && self.te_to.is_none_or(|to| te <= to)
+    }
+
+    /// WHERE 句の条件（kif_headers は h、kif_bodies は b）。値はプレースホルダで渡す
+    pub fn to_sql(&self, is_sente: bool, params: &mut Vec<Value>) -> Result<Vec<String>> {
+        let mut clauses = Vec::new();
+
+        if let Some(opponent) = &self.opponent {
+            clauses.push(format!(
+                "h.{} LIKE BINARY ?",
+                if is_sente {
+                    "gote_player"
+                } else {
+                    "sente_player"
+                }
+            ));
+            params.push(like_pattern(opponent).into());
+        }
+        if let Some(from) = self.started_from()? {
+            clauses.push("h.started_at >= ?".to_string());
+            params.push(from.into());
+        }
+        if let Some(to) = self.started_to()? {
+            clauses.push("h.started_at <= ?".to_string());
+            params.push(to.into());
+        }
+        // 索引（filter::matches）と同じく大文字・小文字を区別して比べる
+        if let Some(site) = &self.site {
+            clauses.push("BINARY h.site = ?".to_string());
+            params.push(site.clone().into());
+        }
+        if let Some(time_control) = &self.time_control {
+            clauses.push("BINARY h.time_control = ?".to_string());
+            params.push(time_control.clone().into());
+        }
+        if let Some(result) = self.result {
+            clauses.push(result_sql(result, is_sente).to_string());
+        }
+        for (label, is_mine) in self.openings()? {
+            clauses.push(format!(
+                "FIND_IN_SET(?, h.{}) > 0",
+                openings_column(is_sente == is_mine)
+            ));
+            params.push(label.to_string().into());
+        }
+        for (labels, is_mine) in self.castles()? {
+            clauses.push(format!(
+                "EXISTS (SELECT 1 FROM kif_bodies c WHERE c.kif_id = h.id AND c.{} IN ({}))",
+                castle_column(is_sente == is_mine),
+                vec!["?"; labels.len()].join(", ")
+            ));
+            params.extend(labels.into_iter().map(Value::from));
+        }
+        if let Some(from) = self.te_from {
+            clauses.push("b.te >= ?".to_string());
+            params.push(from.into());
+        }
+        if let Some(to) = self.te_to {
+            clauses.push("b.te <= ?".to_string());
+            params.push(to.into());
+        }
+
+        Ok(clauses)
+    }
+
+    /// to_sql と同じ絞り込みをメモリ上の索引の棋譜に対して行う（手数は includes_te で見る）
+    pub fn matches(&self, game: &IndexedGame, is_sente: bool) -> Result<bool> {
+        if let Some(opponent) = &self.opponent {
+            let name = if is_sente {
+                &game.gote_player
+            } else {
+                &game.sente_player
+            };
+            if !glob_match(opponent, name) {
+                return Ok(false);
+            }
+        }
+
+        // 日時は "YYYY-MM-DD HH:MM:SS" にそろえてあるので文字列のまま比べられる
+        let started_at = game.started_at.as_deref();
+        if let Some(from) = self.started_from()?
+            && started_at.is_none_or(|s| s < from.as_str())
+        {
+            return Ok(false);
+        }
+        if let Some(to) = self.started_to()?
+            && started_at.is_none_or(|s| s > to.as_str())
+        {
+            return Ok(false);
+        }
+
+        if self
+            .site
+            .as_ref()
+            .is_some_and(|site| game.site.as_ref() != Some(site))
+        {
+            return Ok(false);
+        }
+        if self
+            .time_control
+            .as_ref()
+            .is_some_and(|tc| game.time_control.as_ref() != Some(tc))
+        {
+            return Ok(false);
+        }
+
+        if let Some(result) = self.result {
+            let actual = if game.is_draw {
+                GameResult::Draw
+            } else if game.is_sente_win == is_sente {
+                GameResult::Win
+            } else {
+                GameResult::Loss
+            };
+            if actual != result {
+                return Ok(false);
+            }
+        }
+
+        for (label, is_mine) in self.openings()? {
+            let openings = if is_sente == is_mine {
+                &game.sente_openings
+            } else {
+                &game.gote_openings
+            };
+            if !openings.iter().any(|o| o == label) {
+                return Ok(false);
+            }
+        }
+
+        for (labels, is_mine) in self.castles()? {
+            let castles = if is_sente == is_mine {
+                &game.sente_castles
+            } else {
+                &game.gote_castles
+            };
+            if !labels.iter().any(|l| castles.contains(l)) {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    // 指定された戦型と、それが自分の戦型か（false なら相手）
+    fn openings(&self) -> Result<Vec<(&str, bool)>> {
+        let openings: Vec<(&str, bool)> = [(&self.opening, true), (&self.opponent_opening, false)]
+            .into_iter()
+            .filter_map(|(label, is_mine)| label.as_deref().map(|l| (l, is_mine)))
+            .collect();
+        for (label, _) in &openings {
+            opening::check_label(label)?;
+        }
+        Ok(openings)
+    }
+
+    // 指定された囲いに当てはまるラベルと、それが自分の囲いか（false なら相手）
+    fn castles(&self) -> Result<Vec<(Vec<&'static str>, bool)>> {
+        [(&self.castle, true), (&self.opponent_castle, false)]
+            .into_iter()
+            .filter_map(|(name, is_mine)| {
+                name.as_deref()
+                    .map(|n| castle::expand(n).map(|labels| (labels, is_mine)))
+            })
+            .collect()
+    }
+
+    fn started_from(&self) -> Result<Option<String>> {
+        self.started_from
+            .as_deref()
+            .map(|s| normalize_datetime(s, false))
+            .transpose()
+    }
+
+    fn started_to(&self) -> Result<Option<String>> {
+        self.started_to
+            .as_deref()
+            .map(|s| normalize_datetime(s, true))
+            .transpose()
+    }
+}
+
+/// 自分が勝った（is_sente は自分が先手か）ことを表す SQL の式
+///
+/// migrate で is_draw だけを埋めた引き分けは is_sente_win が残っていることがあるので、どちらも引き分けを除く
+pub fn is_win_sql(is_sente: bool) -> &'static str {
+    if is_sente {
+        "(h.is_sente_win AND NOT h.is_draw)"
+    } else {
+        "(NOT h.is_sente_win AND NOT h.is_draw)"
+    }
+}
+
+// 先手（is_sente）の戦型の列
+fn openings_column(is_sente: bool) -> &'static str {
+    if is_sente {
+        "sente_openings"
+    } else {
+        "gote_openings"
+    }
+}
+
+// 先手（is_sente）の囲いの列（kif_bodies）
+fn castle_column(is_sente: bool) -> &'static str {
+    if is_sente {
+        "sente_castle"
+    } else {
+        "gote_castle"
+    }
+}
+
+fn result_sql(result: GameResult, is_sente: bool) -> &'static str {
+    match (result, is_sente) {
+        (GameResult::Win, _) => is_win_sql(is_sente),
+        // 負けは相手の勝ち
+        (GameResult::Loss, _) => is_win_sql(!is_sente),
+        (GameResult::Draw, _) => "h.is_draw",
+    }
+}
+
+/// 日付・日時を "YYYY-MM-DD HH:MM:SS" にそろえる（日付だけなら end_of_day で 0 時か 23:59:59 にする）
+fn normalize_datetime(value: &str, end_of_day: bool) -> Result<String> {
+    let value = value.trim().replace('/', "-");
+    if let Ok(dt) = NaiveDateTime::parse_from_str(&value, "%Y-%m-%d %H:%M:%S") {
+        return Ok(dt.format("%Y-%m-%d %H:%M:%S").to_string());
+    }
+    let date = NaiveDate::parse_from_str(&value, "%Y-%m-%d").map_err(|_| {
+        Error::query("日付は YYYY-MM-DD または YYYY-MM-DD HH:MM:SS で指定してください")
+            .with_text(&value)
+    })?;
+    Ok(format!(
+        "{} {}",
+        date.format("%Y-%m-%d"),
+        if end_of_day { "23:59:59" } else { "00:00:00" }
+    ))
+}
+
+/// * を任意の文字列とみなすパターンを LIKE 用に変換する（% と _ はそのままの文字として扱う）
+fn like_pattern(pattern: &str) -> String {
+    pattern
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+        .replace('*', "%")
+}
+
+/// like_pattern と同じ意味で、メモリ上で照合する
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let (first, rest) = parts.split_first().unwrap_or((&"", &[]));
+    let Some(mut remaining) = text.strip_prefix(first) else {
+        return false;
+    };
+    let Some((last, middle)) = rest.split_last() else {
+        return remaining.is_empty();
+    };
+
+    for part in middle {
+        match remaining.find(part) {
+            Some(pos) => remaining = &remaining[pos + part.len()..],
+            None => return false,
+        }
+    }
+    remaining.len() >= last.len() && remaining.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opponent_pattern() {
+        assert!(glob_match("Ringo*", "Ringosky"));
+        assert!(glob_match("*sky", "Ringosky"));
+        assert!(glob_match("R*g*y", "Ringosky"));
+        assert!(!glob_match("Ringo", "Ringosky"));
+        assert!(!glob_match("a*a", "a"));
+        assert_eq!(like_pattern("user_1*"), "user\\_1%");
+    }
+
+    #[test]
+    fn test_filter_sql() {
+        let filter: GameFilter = serde_json::from_str(
+            r#"{"opponent": "Ringo*", "started_to": "2025/07/31", "result": "loss", "te_from": 20}"#,
+        )
+        .unwrap();
+
+        let mut params = Vec::new();
+        assert_eq!(
+            filter.to_sql(false, &mut params).unwrap(),
+            vec![
+                "h.sente_player LIKE BINARY ?",
+                "h.started_at <= ?",
+                "(h.is_sente_win AND NOT h.is_draw)",
+                "b.te >= ?",
+            ]
+        );
+        assert_eq!(params[1], Value::from("2025-07-31 23:59:59"));
+        assert!(filter.includes_te(20) && !filter.includes_te(19));
+
+        let filter = GameFilter {
+            started_from: Some("7月1日".to_string()),
+            ..Default::default()
+        };
+        assert!(filter.to_sql(true, &mut params).is_err());
+    }
+
+    #[test]
+    fn test_filter_draw() {
+        // migrate で is_draw だけを埋めた、is_sente_win が残っている引き分け
+        let game = IndexedGame {
+            kif_id: 1,
+            kif_filename: "draw.kif".to_string(),
+            sente_player: "sente".to_string(),
+            gote_player: "gote".to_string(),
+            is_sente_win: true,
+            is_draw: true,
+            started_at: None,
+            site: None,
+            time_control: None,
+            sente_openings: Vec::new(),
+            gote_openings: Vec::new(),
+            sente_castles: Default::default(),
+            gote_castles: Default::default(),
+            positions: Vec::new(),
+        };
+        let filter = |result| GameFilter {
+            result: Some(result),
+            ..Default::default()
+        };
+
+        // SQL でも索引でも、先手の勝ち・後手の負けにはならない
+        for is_sente in [true, false] {
+            let sql = filter(GameResult::Win)
+                .to_sql(is_sente, &mut Vec::new())
+                .unwrap();
+            assert!(sql[0].contains("NOT h.is_draw"));
+            let sql = filter(GameResult::Loss)
+                .to_sql(is_sente, &mut Vec::new())
+                .unwrap();
+            assert!(sql[0].contains("NOT h.is_draw"));
+
+            assert!(!filter(GameResult::Win).matches(&game, is_sente).unwrap());
+            assert!(!filter(GameResult::Loss).matches(&game, is_sente).unwrap());
+            assert!(filter(GameResult::Draw).matches(&game, is_sente).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_filter_openings() {
+        let filter: GameFilter =
+            serde_json::from_str(r#"{"opening": "四間飛車", "opponent_opening": "居飛車"}"#)
+                .unwrap();
+
+        let mut params = Vec::new();
+        assert_eq!(
+            filter.to_sql(false, &mut params).unwrap(),
+            vec![
+                "FIND_IN_SET(?, h.gote_openings) > 0",
+                "FIND_IN_SET(?, h.sente_openings) > 0",
+            ]
+        );
+        assert_eq!(params, vec![Value::from("四間飛車"), Value::from("居飛車")]);
+
+        let filter = GameFilter {
+            opening: Some("鬼殺し".to_string()),
+            ..Default::default()
+        };
+        assert!(filter.to_sql(true, &mut params).is_err());
+    }
+
+    #[test]
+    fn test_filter_castles() {
+        let filter: GameFilter = serde_json::from_str(r#"{"opponent_castle": "穴熊"}"#).unwrap();
+
+        let mut params = Vec::new();
+        assert_eq!(
+            filter.to_sql(true, &mut params).unwrap(),
+            vec![
+                "EXISTS (SELECT 1 FROM kif_bodies c WHERE c.kif_id = h.id AND c.gote_castle IN (?, ?))"
+            ]
+        );
+        assert_eq!(
+            params,
+            vec![Value::from("居飛車穴熊"), Value::from("振り飛車穴熊")]
+        );
+    }
+}
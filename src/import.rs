@@ -1,75 +1,233 @@
 use crate::board::Board;
 use crate::config::IMPORTED_DIR;
-use crate::models::KifBody;
-use crate::{db, parser};
+use crate::error::{Error, Result};
+use crate::models::{KifBody, KifHeader};
+use crate::packed::Bitboards;
+use crate::parser::Move;
+use crate::repertoire::{self, Deviation};
+use crate::{castle, db, index, opening, packed, parser, zobrist};
 use mysql::PooledConn;
+use serde::Serialize;
 
-use crate::config::KIF_PATH;
+use crate::config::{COLLECTED_DIR, KIF_PATH};
 use std::fs;
+use std::path::Path;
 
-pub fn import_kif_file(
-    conn: &mut PooledConn,
-    filepath: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
+/// インポート対象の棋譜ファイルかどうか（取り込み済み・検索結果ディレクトリ配下は除く）
+pub fn is_import_target(path: &Path) -> bool {
+    if path.starts_with(IMPORTED_DIR.as_path()) || path.starts_with(COLLECTED_DIR.as_path()) {
+        return false;
+    }
+
+    path.is_file() && is_kif_file_name(path)
+}
+
+// 拡張子が .kif / .kifu で、エディタや同期ツールの一時ファイル（".foo.kif" や "~foo.kif"）でないもの。
+// ダウンロード途中の ".kif.part" などは拡張子で除かれる
+fn is_kif_file_name(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return false;
+    };
+    if name.starts_with('.') || name.starts_with('~') || name.ends_with('~') {
+        return false;
+    }
+
+    // .kifu は UTF-8 の KIF
+    path.extension()
+        .map(|ext| ext.eq_ignore_ascii_case("kif") || ext.eq_ignore_ascii_case("kifu"))
+        .unwrap_or(false)
+}
+
+/// 棋譜を取り込み、レパートリーから外れた手があれば返す
+pub fn import_kif_file(conn: &mut PooledConn, filepath: &str) -> Result<Vec<Deviation>> {
     let (contents, filename) = parser::read_kif_file(filepath)?;
-    let header = parser::parse_header_and_result(&contents, &filename);
+    let (header, mut bodies) = parse_game(&contents, &filename)?;
+
+    let kif_id = db::insert_kif_header(conn, &header).map_err(|e| e.with_file(&filename))?;
+    for body in &mut bodies {
+        body.kif_id = kif_id as i32;
+    }
+    db::insert_kif_bodies(conn, &bodies).map_err(|e| e.with_file(&filename))?;
+    index::add_game(conn, kif_id, &header, &bodies);
+
+    // レパートリーとの照合に失敗しても、取り込み自体は成功とする
+    let deviations = repertoire::check_game(conn, kif_id, &header, &bodies).unwrap_or_else(|e| {
+        eprintln!("レパートリーと照合できませんでした: {}", e);
+        Vec::new()
+    });
+
+    // 読み込んだファイルを移動
+    let destination = IMPORTED_DIR.join(&filename);
+
+    if !IMPORTED_DIR.exists() {
+        fs::create_dir_all(IMPORTED_DIR.as_path())?;
+    }
+
+    fs::rename(filepath, &destination).map_err(|e| Error::from(e).with_file(&filename))?;
+    println!("✅ ファイル移動: {} → {}", filepath, destination.display());
+
+    Ok(deviations)
+}
+
+/// 棋譜の内容から対局情報と各手の後の局面を作る（DB には書き込まない）
+fn parse_game(contents: &str, filename: &str) -> Result<(KifHeader, Vec<KifBody>)> {
+    let mut header = parser::parse_header_and_result(contents, filename)?;
     let lines: Vec<String> = contents.lines().map(|line| line.to_string()).collect();
     let moves = parser::parse_kif_moves(&lines);
-    let mut board = Board::new();
-    let kif_id = db::insert_kif_header(conn, &header)?;
 
+    // 途中で不正な手が見つかったときに中途半端な棋譜が残らないよう、先に最後まで再現する
+    let bodies = replay_moves(&moves, filename)?;
+    let openings = opening::classify_bodies(&bodies).map_err(|e| e.with_file(filename))?;
+    header.sente_openings = openings.sente;
+    header.gote_openings = openings.gote;
+    Ok((header, bodies))
+}
+
+/// 指し手を初期局面から順に適用し、各手の後の局面を作る（kif_id は 0 のまま）
+pub fn replay_moves(moves: &[Move], filename: &str) -> Result<Vec<KifBody>> {
+    let mut board = Board::new();
+    let mut bodies = Vec::new();
     let mut prev_fugo: Option<String> = None;
 
-    for m in &moves {
-        // 投了などの終局を検出してループ終了
-        if m.fugo.contains("投了") {
+    for m in moves {
+        // 投了・詰み・千日手などの終局を検出してループ終了
+        if parser::is_end_move(&m.fugo) {
             break;
         }
 
         let normalized_fugo = parser::normalize_fugo(&m.fugo, prev_fugo.as_deref());
-        board.apply_move(&normalized_fugo, m.te % 2 == 1)?;
-        prev_fugo = Some(normalized_fugo.clone());
-
-        let body = KifBody {
-            kif_id: kif_id as i32,
+        board
+            .apply_move(&normalized_fugo, m.te % 2 == 1)
+            .map_err(|e| {
+                let e = e.with_file(filename).with_te(m.te).with_text(&m.fugo);
+                match m.line {
+                    Some(line) => e.with_line(line),
+                    None => e,
+                }
+            })?;
+        prev_fugo = Some(normalized_fugo);
+
+        bodies.push(KifBody {
+            kif_id: 0,
             te: m.te as i32,
             fugo: m.fugo.clone(),
+            eval: m.eval,
             board: board.to_verbose_sfen(),
-        };
-        db::insert_kif_bodies(conn, &[body])?;
+            bitboards: Bitboards::from_board(&board),
+            // 奇数手の後は後手番
+            position_hash: zobrist::hash(&board, m.te % 2 == 0),
+            packed: packed::pack(&board, m.te % 2 == 0),
+            sente_castle: castle::recognize(&board, true),
+            gote_castle: castle::recognize(&board, false),
+        });
     }
 
-    // 読み込んだファイルを移動
-    let destination = IMPORTED_DIR.join(filename);
-
-    if !IMPORTED_DIR.exists() {
-        fs::create_dir_all(IMPORTED_DIR.as_path())?;
-    }
+    Ok(bodies)
+}
 
-    fs::rename(filepath, &destination)?;
-    println!("✅ ファイル移動: {} → {}", filepath, destination.display());
+/// まとめて取り込んだ結果
+#[derive(Default, Serialize)]
+pub struct ImportReport {
+    pub imported: Vec<String>,      // 取り込めた棋譜ファイル名
+    pub failed: Vec<Error>,         // 取り込めなかった棋譜とその理由
+    pub deviations: Vec<Deviation>, // レパートリーから外れた手
+}
 
-    Ok(())
+impl ImportReport {
+    /// 1 ファイル分の取り込み結果を記録する
+    pub fn record(&mut self, path: &Path, result: Result<Vec<Deviation>>) {
+        let filename = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+
+        match result {
+            Ok(deviations) => {
+                self.imported.push(filename);
+                self.deviations.extend(deviations);
+            }
+            Err(e) => {
+                eprintln!("棋譜取り込み失敗: {}", e);
+                self.failed.push(e.with_file(filename));
+            }
+        }
+    }
 }
 
-pub fn import_all_kif_files() -> Result<(), Box<dyn std::error::Error>> {
-    let paths = fs::read_dir(KIF_PATH.as_path())?;
+pub fn import_all_kif_files() -> Result<ImportReport> {
     let mut conn = db::get_conn()?;
+    import_dir(&mut conn, KIF_PATH.as_path())
+}
+
+/// ディレクトリ直下の棋譜ファイルを全て取り込む
+pub fn import_dir(conn: &mut PooledConn, dir: &Path) -> Result<ImportReport> {
+    let paths = fs::read_dir(dir)?;
+    let mut report = ImportReport::default();
 
     for entry in paths {
         let entry = entry?;
         let path = entry.path();
 
-        if path.extension().map(|ext| ext != "kif").unwrap_or(true) {
+        if !is_import_target(&path) {
             continue;
         }
 
         println!("\n=== 処理中: {} ===", path.to_string_lossy());
 
-        if let Err(e) = import_kif_file(&mut conn, path.to_str().unwrap()) {
-            eprintln!("棋譜取り込み失敗: {}", e);
-        }
+        let result = import_kif_file(conn, &path.to_string_lossy());
+        report.record(&path, result);
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_kif_file_name() {
+        assert!(is_kif_file_name(Path::new(
+            "/kif/20240101_ringo_vs_mikan.kif"
+        )));
+        assert!(is_kif_file_name(Path::new("/kif/wars.kifu")));
+        assert!(is_kif_file_name(Path::new("/kif/OLD.KIF")));
+
+        assert!(!is_kif_file_name(Path::new("/kif/game.csa")));
+        assert!(!is_kif_file_name(Path::new("/kif/notes.txt")));
+        assert!(!is_kif_file_name(Path::new("/kif/kif")));
+
+        // 一時ファイル・ダウンロード途中のファイル
+        assert!(!is_kif_file_name(Path::new("/kif/.game.kif")));
+        assert!(!is_kif_file_name(Path::new("/kif/~game.kif")));
+        assert!(!is_kif_file_name(Path::new("/kif/game.kif~")));
+        assert!(!is_kif_file_name(Path::new("/kif/game.kif.part")));
+        assert!(!is_kif_file_name(Path::new("/kif/game.kif.crdownload")));
+        assert!(!is_kif_file_name(Path::new("/kif/.game.kif.swp")));
     }
 
-    Ok(())
+    #[test]
+    fn test_parse_game_end_moves() {
+        let kif = |last: &str| {
+            format!(
+                "先手：sente\n後手：gote\n手数----指手---------消費時間--\n   1 ７六歩(77)\n   2 ３四歩(33)\n{}\n",
+                last
+            )
+        };
+
+        // 投了以外の終局でも、終局の行の前までを取り込む
+        let (header, bodies) = parse_game(&kif("   3 詰み"), "test.kif").unwrap();
+        assert_eq!(bodies.len(), 2);
+        assert_eq!(header.end_reason.as_deref(), Some("詰み"));
+
+        let (header, bodies) = parse_game(&kif("   3 千日手"), "test.kif").unwrap();
+        assert_eq!(bodies.len(), 2);
+        assert_eq!(header.end_reason.as_deref(), Some("千日手"));
+        assert!(header.is_draw);
+
+        for last in ["   3 切れ負け", "   3 持将棋", "   3 中断", "   3 反則勝ち"] {
+            assert_eq!(parse_game(&kif(last), "test.kif").unwrap().1.len(), 2);
+        }
+    }
 }
@@ -0,0 +1,245 @@
+use crate::error::Result;
+use crate::models::{KifBody, KifHeader};
+use crate::packed::{self, Bitboards};
+use crate::{castle, db, opening};
+use mysql::prelude::Queryable;
+use mysql::*;
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+use std::thread;
+
+/// メモリ上の索引に載せた 1 局分の棋譜
+pub struct IndexedGame {
+    pub kif_id: u64,
+    pub kif_filename: String,
+    pub sente_player: String,
+    pub gote_player: String,
+    pub is_sente_win: bool,
+    pub is_draw: bool,
+    pub started_at: Option<String>,
+    pub site: Option<String>,
+    pub time_control: Option<String>,
+    pub sente_openings: Vec<String>, // 戦型（opening::RULES のラベル）
+    pub gote_openings: Vec<String>,
+    pub sente_castles: HashSet<&'static str>, // 対局中に組んだ囲い（castle::TEMPLATES のラベル）
+    pub gote_castles: HashSet<&'static str>,
+    pub positions: Vec<(i32, Bitboards)>, // (手数, その手の後の局面)
+}
+
+// 索引を作ったときの DB の状態。別のプロセスが棋譜を取り込んだり局面を再計算したりすると変わる
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct DbVersion {
+    games: u64,    // kif_headers の行数
+    max_id: u64,   // kif_headers の最大 ID（取り込み直しで行数が変わらなくても増える）
+    pending: bool, // ビットボードを計算し終えていない局面があるか（db::has_pending_positions）
+}
+
+struct Index {
+    version: DbVersion,
+    games: Vec<IndexedGame>,
+}
+
+// 読み込み前は None（その間は DB で検索する）
+static INDEX: Lazy<RwLock<Option<Index>>> = Lazy::new(|| RwLock::new(None));
+
+fn fetch_version(conn: &mut PooledConn) -> Result<DbVersion> {
+    let (games, max_id): (u64, u64) = conn
+        .query_first("SELECT COUNT(*), COALESCE(MAX(id), 0) FROM kif_headers")?
+        .unwrap_or_default();
+    Ok(DbVersion {
+        games,
+        max_id,
+        pending: db::has_pending_positions(conn)?,
+    })
+}
+
+// IndexedGame の元になる kif_headers の 1 行（id, kif_filename, sente_player, gote_player,
+// is_sente_win, is_draw, started_at, site, time_control, sente_openings, gote_openings）
+type HeaderRow = (
+    u64,
+    String,
+    String,
+    String,
+    bool,
+    bool,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+);
+
+/// DB から全局面を読み込んで索引を作り直す（戻り値は局面数）
+pub fn load(conn: &mut PooledConn) -> Result<usize> {
+    // 読み込み中に DB が変わっても次の検索で読み直されるよう、状態は先に取っておく
+    let version = fetch_version(conn)?;
+    let headers: Vec<HeaderRow> = conn.query(
+        "SELECT id, kif_filename, sente_player, gote_player, is_sente_win, is_draw,
+                DATE_FORMAT(started_at, '%Y-%m-%d %H:%i:%s'), site, time_control,
+                sente_openings, gote_openings
+            FROM kif_headers",
+    )?;
+
+    let mut positions: HashMap<u64, Vec<(i32, Bitboards)>> = HashMap::new();
+    let mut castles: HashMap<u64, [HashSet<&'static str>; 2]> = HashMap::new();
+    let mut count = 0;
+    for row in conn.query_iter(
+        "SELECT kif_id, te, packed FROM kif_bodies WHERE packed IS NOT NULL ORDER BY kif_id, te",
+    )? {
+        let (kif_id, te, bytes): (u64, i32, Vec<u8>) = from_row(row?);
+        let (board, _) = packed::unpack(&bytes)?;
+        positions
+            .entry(kif_id)
+            .or_default()
+            .push((te, Bitboards::from_board(&board)));
+        let [sente, gote] = castles.entry(kif_id).or_default();
+        sente.extend(castle::recognize(&board, true));
+        gote.extend(castle::recognize(&board, false));
+        count += 1;
+    }
+
+    let games = headers
+        .into_iter()
+        .map(
+            |(
+                kif_id,
+                kif_filename,
+                sente_player,
+                gote_player,
+                is_sente_win,
+                is_draw,
+                started_at,
+                site,
+                time_control,
+                sente_openings,
+                gote_openings,
+            )| {
+                let [sente_castles, gote_castles] = castles.remove(&kif_id).unwrap_or_default();
+                IndexedGame {
+                    kif_id,
+                    kif_filename,
+                    sente_player,
+                    gote_player,
+                    is_sente_win,
+                    is_draw,
+                    started_at,
+                    site: site.filter(|s| !s.is_empty()),
+                    time_control: time_control.filter(|s| !s.is_empty()),
+                    sente_openings: opening::split_labels(sente_openings.as_deref()),
+                    gote_openings: opening::split_labels(gote_openings.as_deref()),
+                    sente_castles,
+                    gote_castles,
+                    positions: positions.remove(&kif_id).unwrap_or_default(),
+                }
+            },
+        )
+        .collect();
+
+    *INDEX.write().unwrap() = Some(Index { version, games });
+    Ok(count)
+}
+
+/// 索引を作ったときから DB が変わっていれば読み直す（索引を使っていなければ何もしない）
+pub fn refresh(conn: &mut PooledConn) -> Result<()> {
+    let Some(version) = INDEX.read().unwrap().as_ref().map(|index| index.version) else {
+        return Ok(());
+    };
+    if fetch_version(conn)? != version {
+        let count = load(conn)?;
+        println!(
+            "DB が更新されたため局面の索引を読み直しました: {}局面",
+            count
+        );
+    }
+    Ok(())
+}
+
+/// 取り込んだ棋譜を索引に追加する（索引を使っていなければ何もしない）
+pub fn add_game(conn: &mut PooledConn, kif_id: u64, header: &KifHeader, bodies: &[KifBody]) {
+    if INDEX.read().unwrap().is_none() {
+        return;
+    }
+
+    // 検索を待たせないよう、DB の状態は書き込みロックを取る前に確認する
+    let version = fetch_version(conn)
+        .map_err(|e| {
+            eprintln!(
+                "DB の状態を確認できませんでした（次の検索で索引を読み直します）: {}",
+                e
+            )
+        })
+        .ok();
+    let game = IndexedGame {
+        kif_id,
+        kif_filename: header.kif_filename.clone(),
+        sente_player: header.sente_player.clone(),
+        gote_player: header.gote_player.clone(),
+        is_sente_win: header.is_sente_win,
+        is_draw: header.is_draw,
+        started_at: header.started_at.clone(),
+        site: header.site.clone(),
+        time_control: header.time_control.clone(),
+        sente_openings: header.sente_openings.clone(),
+        gote_openings: header.gote_openings.clone(),
+        sente_castles: bodies.iter().filter_map(|b| b.sente_castle).collect(),
+        gote_castles: bodies.iter().filter_map(|b| b.gote_castle).collect(),
+        positions: bodies.iter().map(|b| (b.te, b.bitboards)).collect(),
+    };
+
+    let mut index = INDEX.write().unwrap();
+    let Some(index) = index.as_mut() else {
+        return;
+    };
+
+    // この取り込みだけで DB が変わったのなら、追加後の索引は DB と揃っている
+    let replaced = index
+        .games
+        .iter()
+        .any(|g| g.kif_filename == header.kif_filename);
+    let expected = DbVersion {
+        games: index.version.games + 1 - replaced as u64,
+        max_id: kif_id,
+        pending: index.version.pending,
+    };
+
+    // 同じファイルを取り込み直したときは DB と同じく古い方を消す
+    index
+        .games
+        .retain(|g| g.kif_filename != header.kif_filename);
+    index.games.push(game);
+
+    // 他のプロセスや別のスレッドも取り込んでいた場合などは古い状態のままにして、次の検索で読み直させる
+    if version == Some(expected) {
+        index.version = expected;
+    }
+}
+
+/// 全ての棋譜に f を並列に適用し、Some を返したものを集める
+///
+/// 索引を読み込んでいないときと、ビットボードを計算し終えていない局面があるときは None
+/// （そうした局面は索引に載らず、DB の c1〜c81 列でしか見つからないため）
+pub fn scan<T, F>(f: F) -> Option<Vec<T>>
+where
+    T: Send,
+    F: Fn(&IndexedGame) -> Option<T> + Sync,
+{
+    let index = INDEX.read().unwrap();
+    let index = index.as_ref().filter(|index| !index.version.pending)?;
+    let games = &index.games;
+
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = games.len().div_ceil(threads).max(1);
+
+    let results = thread::scope(|scope| {
+        let handles: Vec<_> = games
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(|| chunk.iter().filter_map(&f).collect::<Vec<_>>()))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect()
+    });
+    Some(results)
+}
@@ -0,0 +1,34 @@
+//! KifQuery: 「こんな感じの局面」が登場した棋譜を検索するためのライブラリ
+//!
+//! KIF の読み込み・盤面の再現・局面検索を他のツールからも使えるように公開している。
+//! HTTP サーバー（`routes`）とコマンドラインはこのライブラリの利用者の一つ。
+
+pub mod board;
+pub mod castle;
+pub mod config;
+pub mod db;
+pub mod error;
+pub mod explorer;
+pub mod export;
+pub mod filter;
+pub mod import;
+pub mod index;
+pub mod migrate;
+pub mod models;
+pub mod opening;
+pub mod packed;
+pub mod parser;
+pub mod profile;
+pub mod repertoire;
+pub mod result_set;
+pub mod routes;
+pub mod search;
+pub mod stats;
+pub mod watcher;
+pub mod zobrist;
+
+pub use board::Board;
+pub use error::{Error, Result};
+pub use models::{KifBody, KifHeader};
+pub use parser::{Move, ParsedFugo};
+pub use search::{KifLink, SearchCondition};
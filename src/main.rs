@@ -1,37 +1,73 @@
-mod board;
-mod config;
-mod db;
-mod import;
-mod models;
-mod parser;
-mod routes;
-
-use axum::{Router, routing::post};
+mod cli;
+
+use clap::Parser;
+use cli::{Cli, Command};
+use kifquery::{config, db, index, migrate, routes, watcher};
 use std::net::SocketAddr;
-use tower_http::cors::CorsLayer;
 
 #[tokio::main]
 async fn main() {
-    let app = Router::new()
-        .route("/api/admin/import", post(import_all_handler))
-        .route("/api/search", post(routes::search_games))
-        .layer(CorsLayer::permissive());
+    let cli = Cli::parse();
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
-    println!("Listening on {}", addr);
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+    let result = match cli.command.unwrap_or(Command::Serve {
+        port: 3000,
+        watch: false,
+    }) {
+        Command::Import { paths } => cli::run_import(&paths),
+        Command::Search {
+            square,
+            sfen,
+            query,
+            options,
+            page,
+            stats,
+        } => cli::run_search(&square, &sfen, query.as_deref(), *options, page, stats),
+        Command::Position { sfen } => cli::run_position(&sfen),
+        Command::Collect { id, zip } => cli::run_collect(id, zip.as_deref()),
+        Command::Migrate { drop_legacy } => cli::run_migrate(drop_legacy),
+        Command::Export { id, format } => cli::run_export(id, format),
+        Command::Stats { profile } => cli::run_stats(profile.as_deref()),
+        Command::Profile { action } => cli::run_profile(action),
+        Command::Repertoire { action } => cli::run_repertoire(action),
+        Command::Serve { port, watch } => {
+            serve(port, watch).await;
+            Ok(())
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("エラー: {}", e);
+        std::process::exit(1);
+    }
 }
 
-// ハンドラ関数
-async fn import_all_handler() -> &'static str {
-    match import::import_all_kif_files() {
-        Ok(_) => "全ての棋譜ファイルをインポートしました",
-        Err(e) => {
-            eprintln!("エラー: {}", e);
-            "エラーが発生しました"
+async fn serve(port: u16, watch: bool) {
+    // 古いテーブル定義のままでも動くように、起動時に列の追加などを行う
+    match db::get_conn().and_then(|mut conn| migrate::run(&mut conn)) {
+        Ok(()) => {}
+        Err(e) => eprintln!("DB の更新に失敗しました: {}", e),
+    }
+
+    if *config::POSITION_INDEX {
+        let started = std::time::Instant::now();
+        match db::get_conn().and_then(|mut conn| index::load(&mut conn)) {
+            Ok(count) => println!(
+                "局面の索引を読み込みました: {}局面 ({:?})",
+                count,
+                started.elapsed()
+            ),
+            Err(e) => eprintln!("局面の索引を読み込めませんでした（DB で検索します）: {}", e),
         }
     }
+
+    if watch || *config::KIF_WATCH {
+        watcher::spawn_kif_watcher();
+    }
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    println!("Listening on {}", addr);
+    axum::Server::bind(&addr)
+        .serve(routes::router().into_make_service())
+        .await
+        .unwrap();
 }
@@ -0,0 +1,323 @@
+use crate::config::IMPORTED_DIR;
+use crate::error::{Error, Result};
+use crate::packed::BITBOARD_BYTES;
+use crate::{castle, db, import, opening, packed, parser};
+use mysql::prelude::Queryable;
+use mysql::*;
+
+/// 既存の DB を現在のテーブル定義に合わせる（何度実行してもよい）
+///
+/// 列とテーブルを先にすべて作り、その後で取り込み済みの棋譜から値を埋める
+/// （埋められない棋譜があっても、テーブルがなくて検索などが失敗することはない）
+pub fn run(conn: &mut PooledConn) -> Result<()> {
+    // 局面ハッシュ（完全一致検索・合流の検出用）
+    add_column_if_missing(conn, "kif_bodies", "position_hash", "BIGINT UNSIGNED NULL")?;
+    add_index_if_missing(conn, "kif_bodies", "idx_position_hash", "position_hash")?;
+
+    // 詰めた局面とビットボード（c1〜c81 の代わり）
+    add_column_if_missing(conn, "kif_bodies", "packed", "VARBINARY(48) NULL")?;
+    for column in db::bitboard_columns() {
+        let definition = format!("BINARY({}) NULL", BITBOARD_BYTES);
+        add_column_if_missing(conn, "kif_bodies", &column, &definition)?;
+    }
+
+    // 解析コメントの評価値（これより前に取り込んだ棋譜は NULL のまま）
+    add_column_if_missing(conn, "kif_bodies", "eval", "INT NULL")?;
+
+    // 局面ごとの囲い（空文字は囲いなし、NULL は未判定）
+    add_column_if_missing(conn, "kif_bodies", "sente_castle", "VARCHAR(32) NULL")?;
+    add_column_if_missing(conn, "kif_bodies", "gote_castle", "VARCHAR(32) NULL")?;
+
+    // 対局情報（検索の絞り込み用）
+    add_column_if_missing(
+        conn,
+        "kif_headers",
+        "is_draw",
+        "BOOLEAN NOT NULL DEFAULT FALSE",
+    )?;
+    add_column_if_missing(conn, "kif_headers", "site", "VARCHAR(64) NULL")?;
+    add_column_if_missing(conn, "kif_headers", "time_control", "VARCHAR(64) NULL")?;
+    add_column_if_missing(conn, "kif_headers", "end_reason", "VARCHAR(32) NULL")?;
+
+    // 戦型（カンマ区切りのラベル。NULL は未判定）
+    add_column_if_missing(conn, "kif_headers", "sente_openings", "VARCHAR(255) NULL")?;
+    add_column_if_missing(conn, "kif_headers", "gote_openings", "VARCHAR(255) NULL")?;
+
+    // 対局者のプロファイル（プロファイル名ごとのアカウント名）
+    conn.query_drop(
+        "CREATE TABLE IF NOT EXISTS player_aliases (
+            profile VARCHAR(64) NOT NULL,
+            username VARCHAR(255) NOT NULL,
+            PRIMARY KEY (profile, username)
+        )",
+    )?;
+
+    // 検索結果（ID を指定して後から取得・書き出しできるように）
+    conn.query_drop(
+        "CREATE TABLE IF NOT EXISTS search_result_sets (
+            id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+            created_at DATETIME NOT NULL,
+            request JSON NOT NULL,
+            links JSON NOT NULL,
+            INDEX idx_created_at (created_at)
+        )",
+    )?;
+
+    // レパートリー（局面ごとの自分の手と、想定している相手の手）
+    conn.query_drop(
+        "CREATE TABLE IF NOT EXISTS repertoire_moves (
+            profile VARCHAR(64) NOT NULL DEFAULT '',
+            is_sente BOOLEAN NOT NULL,
+            position_hash BIGINT UNSIGNED NOT NULL,
+            next_hash BIGINT UNSIGNED NOT NULL,
+            fugo VARCHAR(32) NOT NULL,
+            is_mine BOOLEAN NOT NULL,
+            PRIMARY KEY (profile, is_sente, position_hash, next_hash)
+        )",
+    )?;
+
+    backfill_positions(conn)?;
+    backfill_castles(conn)?;
+    backfill_headers(conn)?;
+    backfill_openings(conn)?;
+
+    Ok(())
+}
+
+/// 旧形式の c1〜c81 列を削除する（run で新しい列を埋めた後に、明示的に実行する）
+pub fn drop_legacy_columns(conn: &mut PooledConn) -> Result<()> {
+    if !db::has_legacy_columns(conn)? {
+        return Ok(());
+    }
+
+    let missing: Option<u64> =
+        conn.query_first("SELECT COUNT(*) FROM kif_bodies WHERE packed IS NULL")?;
+    if missing.unwrap_or(0) > 0 {
+        return Err(Error::config(
+            "新しい形式に変換できていない局面があるため、c1〜c81 列を削除できません",
+        ));
+    }
+
+    println!("旧形式の列を削除: kif_bodies.c1〜c81");
+    let drops = (1..=81)
+        .map(|c| format!("DROP COLUMN c{}", c))
+        .collect::<Vec<_>>()
+        .join(", ");
+    conn.query_drop(format!("ALTER TABLE kif_bodies {}", drops))?;
+    Ok(())
+}
+
+fn add_column_if_missing(
+    conn: &mut PooledConn,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<()> {
+    let exists: Option<u64> = conn.exec_first(
+        "SELECT COUNT(*) FROM information_schema.COLUMNS
+         WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = ? AND COLUMN_NAME = ?",
+        (table, column),
+    )?;
+    if exists.unwrap_or(0) == 0 {
+        println!("列を追加: {}.{}", table, column);
+        conn.query_drop(format!(
+            "ALTER TABLE {} ADD COLUMN {} {}",
+            table, column, definition
+        ))?;
+    }
+    Ok(())
+}
+
+fn add_index_if_missing(
+    conn: &mut PooledConn,
+    table: &str,
+    index: &str,
+    columns: &str,
+) -> Result<()> {
+    let exists: Option<u64> = conn.exec_first(
+        "SELECT COUNT(*) FROM information_schema.STATISTICS
+         WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = ? AND INDEX_NAME = ?",
+        (table, index),
+    )?;
+    if exists.unwrap_or(0) == 0 {
+        println!("インデックスを追加: {}.{}", table, index);
+        conn.query_drop(format!("CREATE INDEX {} ON {} ({})", index, table, columns))?;
+    }
+    Ok(())
+}
+
+/// 局面ハッシュ・詰めた局面・ビットボードが未計算の棋譜を、保存済みの指し手から再現して埋める
+fn backfill_positions(conn: &mut PooledConn) -> Result<()> {
+    // 成り駒のビットボードは後から追加したので、それだけ NULL の局面も計算し直す
+    let kif_ids: Vec<u64> = conn.query(format!(
+        "SELECT DISTINCT kif_id FROM kif_bodies
+            WHERE position_hash IS NULL OR packed IS NULL OR {} IS NULL",
+        packed::PROMOTED_COLUMN
+    ))?;
+    if !kif_ids.is_empty() {
+        println!("局面を再計算: {}局", kif_ids.len());
+    }
+
+    let columns = db::bitboard_columns();
+    let stmt = format!(
+        "UPDATE kif_bodies SET position_hash = ?, packed = ?, {} WHERE kif_id = ? AND te = ?",
+        columns
+            .iter()
+            .map(|c| format!("{} = ?", c))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    // 再現できない棋譜は NULL のまま残し（次回また試す）、他の棋譜を先に進める
+    for kif_id in kif_ids {
+        if let Err(e) = backfill_game_positions(conn, &stmt, kif_id) {
+            eprintln!("局面を再計算できませんでした: kif_id={}: {}", kif_id, e);
+        }
+    }
+
+    Ok(())
+}
+
+fn backfill_game_positions(conn: &mut PooledConn, stmt: &str, kif_id: u64) -> Result<()> {
+    let moves = db::fetch_kif_moves(conn, kif_id)?;
+    let bodies = import::replay_moves(&moves, &format!("kif_id={}", kif_id))?;
+
+    let params: Vec<Vec<Value>> = bodies
+        .iter()
+        .map(|b| {
+            let mut v: Vec<Value> = vec![b.position_hash.into(), b.packed.clone().into()];
+            v.extend(db::bitboard_params(&b.bitboards));
+            v.push(kif_id.into());
+            v.push(b.te.into());
+            v
+        })
+        .collect();
+    conn.exec_batch(stmt, params)?;
+    Ok(())
+}
+
+/// 囲いが未判定の局面を、詰めた局面から判定して埋める
+fn backfill_castles(conn: &mut PooledConn) -> Result<()> {
+    let kif_ids: Vec<u64> = conn.query(
+        "SELECT DISTINCT kif_id FROM kif_bodies WHERE sente_castle IS NULL AND packed IS NOT NULL",
+    )?;
+    if !kif_ids.is_empty() {
+        println!("囲いを判定: {}局", kif_ids.len());
+    }
+
+    // 局面を読めない棋譜は NULL のまま残し（次回また試す）、他の棋譜を先に進める
+    for kif_id in kif_ids {
+        if let Err(e) = backfill_game_castles(conn, kif_id) {
+            eprintln!("囲いを判定できませんでした: kif_id={}: {}", kif_id, e);
+        }
+    }
+
+    Ok(())
+}
+
+fn backfill_game_castles(conn: &mut PooledConn, kif_id: u64) -> Result<()> {
+    let rows: Vec<(i32, Vec<u8>)> = conn.exec(
+        "SELECT te, packed FROM kif_bodies WHERE kif_id = ? AND packed IS NOT NULL",
+        (kif_id,),
+    )?;
+    let mut params: Vec<(&str, &str, u64, i32)> = Vec::new();
+    for (te, bytes) in rows {
+        let (board, _) = packed::unpack(&bytes)?;
+        params.push((
+            castle::recognize(&board, true).unwrap_or_default(),
+            castle::recognize(&board, false).unwrap_or_default(),
+            kif_id,
+            te,
+        ));
+    }
+    conn.exec_batch(
+        "UPDATE kif_bodies SET sente_castle = ?, gote_castle = ? WHERE kif_id = ? AND te = ?",
+        params,
+    )?;
+    Ok(())
+}
+
+/// 場所・持ち時間・引き分け・終局の理由が未設定の対局情報を、取り込み済みの棋譜ファイルから読み直して埋める
+fn backfill_headers(conn: &mut PooledConn) -> Result<()> {
+    let rows: Vec<(u64, String)> = conn.query(
+        "SELECT id, kif_filename FROM kif_headers WHERE site IS NULL OR end_reason IS NULL",
+    )?;
+    if !rows.is_empty() {
+        println!("対局情報を読み直し: {}局", rows.len());
+    }
+
+    for (id, filename) in rows {
+        let path = IMPORTED_DIR.join(&filename);
+        let header = parser::read_kif_file(&path.to_string_lossy())
+            .and_then(|(contents, filename)| parser::parse_header_and_result(&contents, &filename));
+
+        // 読み直せなかった棋譜は NULL のままにしておく（次回また読み直す）
+        let header = match header {
+            Ok(header) => header,
+            Err(e) => {
+                eprintln!("対局情報を読み直せませんでした: {}", e);
+                continue;
+            }
+        };
+
+        // 既に入っている値と勝敗（is_sente_win）は変えない
+        // （end_reason だけが NULL の対局は end_reason だけが埋まる）。
+        // is_draw は列を追加したばかりの（site が NULL の）対局だけ埋める（site より先に評価される）
+        conn.exec_drop(
+            "UPDATE kif_headers
+                SET is_draw = IF(site IS NULL, ?, is_draw), site = COALESCE(site, ?),
+                    time_control = COALESCE(time_control, ?), end_reason = COALESCE(end_reason, ?)
+                WHERE id = ?",
+            (
+                header.is_draw,
+                header.site.unwrap_or_default(),
+                header.time_control.unwrap_or_default(),
+                header.end_reason.unwrap_or_default(),
+                id,
+            ),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// 戦型が未判定の対局を、保存済みの局面から判定して埋める
+fn backfill_openings(conn: &mut PooledConn) -> Result<()> {
+    let kif_ids: Vec<u64> =
+        conn.query("SELECT id FROM kif_headers WHERE sente_openings IS NULL")?;
+    if !kif_ids.is_empty() {
+        println!("戦型を判定: {}局", kif_ids.len());
+    }
+
+    // 局面を読めない棋譜は NULL のまま残し（次回また試す）、他の棋譜を先に進める
+    for kif_id in kif_ids {
+        if let Err(e) = backfill_game_openings(conn, kif_id) {
+            eprintln!("戦型を判定できませんでした: kif_id={}: {}", kif_id, e);
+        }
+    }
+
+    Ok(())
+}
+
+fn backfill_game_openings(conn: &mut PooledConn, kif_id: u64) -> Result<()> {
+    let packed: Vec<Vec<u8>> = conn.exec(
+        "SELECT packed FROM kif_bodies WHERE kif_id = ? AND packed IS NOT NULL
+            ORDER BY te LIMIT ?",
+        (kif_id, opening::CLASSIFY_PLIES),
+    )?;
+    let boards = packed
+        .iter()
+        .map(|bytes| packed::unpack(bytes).map(|(board, _)| board))
+        .collect::<Result<Vec<_>>>()?;
+    let openings = opening::classify(&boards);
+
+    conn.exec_drop(
+        "UPDATE kif_headers SET sente_openings = ?, gote_openings = ? WHERE id = ?",
+        (
+            opening::join_labels(&openings.sente),
+            opening::join_labels(&openings.gote),
+            kif_id,
+        ),
+    )?;
+    Ok(())
+}
@@ -1,17 +1,46 @@
+use crate::packed::Bitboards;
+use serde::Serialize;
+
+#[derive(Serialize)]
 pub struct KifHeader {
     pub kif_filename: String,
     pub sente_player: String,
     pub gote_player: String,
     pub is_sente_win: bool,
+    pub is_draw: bool, // 千日手・持将棋（is_sente_win は false）
     pub started_at: Option<String>,
     pub ended_at: Option<String>,
+    pub site: Option<String>,         // 場所（例: 将棋ウォーズ, 81Dojo）
+    pub time_control: Option<String>, // 持ち時間（例: 15分+60秒）
+    // 終局の理由（投了, 時間切れ, 詰み, 反則, 千日手, 持将棋, 中断）
+    pub end_reason: Option<String>,
+    pub sente_openings: Vec<String>, // 先手の戦型（opening::RULES のラベル）
+    pub gote_openings: Vec<String>,  // 後手の戦型
     pub created_at: String,
     pub created_by: String,
 }
 
 pub struct KifBody {
-    pub kif_id: i32,               // 外部キー
-    pub te: i32,                   // 手数（何手目）
-    pub fugo: String,              // 例: "7六歩"
-    pub board: [Option<char>; 81], // 盤面（9x9 = 81マス）
+    pub kif_id: i32,  // 外部キー
+    pub te: i32,      // 手数（何手目）
+    pub fugo: String, // 例: "7六歩"
+    // この手の後の評価値（先手から見た値。解析コメントがある場合のみ）
+    pub eval: Option<i32>,
+    pub board: [Option<char>; 81],          // 盤面（9x9 = 81マス）
+    pub bitboards: Bitboards,               // 成りを含めた盤面のビットボード
+    pub position_hash: u64,                 // 盤面・持ち駒・手番の Zobrist ハッシュ
+    pub packed: Vec<u8>,                    // 盤面・持ち駒・手番を詰めたもの（packed::pack）
+    pub sente_castle: Option<&'static str>, // 先手の囲い（castle::TEMPLATES のラベル）
+    pub gote_castle: Option<&'static str>,  // 後手の囲い
+}
+
+/// 取り込み済み棋譜の集計（CLI の stats 用）
+#[derive(Default)]
+pub struct GameSummary {
+    pub games: u64,       // 棋譜数
+    pub positions: u64,   // 局面数（kif_bodies の行数）
+    pub sente_games: u64, // 自分が先手の対局数
+    pub sente_wins: u64,  // 自分が先手で勝った数
+    pub gote_games: u64,  // 自分が後手の対局数
+    pub gote_wins: u64,   // 自分が後手で勝った数
 }
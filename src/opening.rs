@@ -0,0 +1,402 @@
+use crate::board::{Board, HAND_PIECES};
+use crate::error::{Error, Result};
+use crate::models::KifBody;
+use crate::packed;
+use serde::Serialize;
+
+/// 戦型を判定するのに見る手数（RULES の by の最大値）
+pub const CLASSIFY_PLIES: usize = 60;
+
+/// これより短い対局は戦型を判定しない（居飛車ばかりになるため）
+pub const MIN_PLIES: usize = 16;
+
+// 持ち駒の角の添字
+const BISHOP: usize = 5;
+const _: () = assert!(HAND_PIECES[BISHOP] == 'B');
+
+/// 戦型の判定条件
+///
+/// 筋・段はすべて判定する側から見た値（後手は盤を 180 度回して先手と同じ向きにする）。
+/// by は「何手目（両者の手を合わせた手数）までの局面を見るか」
+pub enum Condition {
+    /// 先に判定した戦型がついている
+    With(&'static str),
+    /// 先に判定した戦型がついていない
+    Without(&'static str),
+    /// 飛車を 2 筋から最初に動かした先の筋（自陣の 6〜9 段にいるときだけ数える）
+    RookFile { files: &'static [u8], by: usize },
+    /// 駒（成っていない自分の駒, 筋, 段）がすべてそろった局面がある
+    Shape {
+        pieces: &'static [(char, u8, u8)],
+        by: usize,
+    },
+    /// 先手・後手とも角を持ち駒にした局面がある
+    BishopsExchanged { by: usize },
+    /// 相手について判定する
+    Opponent(&'static Condition),
+    /// 自分か相手のどちらかについて成り立つ
+    Either(&'static Condition),
+}
+
+/// 戦型の規則（conditions をすべて満たす側に label をつける）
+pub struct Rule {
+    pub label: &'static str,
+    pub conditions: &'static [Condition],
+}
+
+use Condition::*;
+
+/// 戦型の規則表（上から順に判定するので、With / Without で使う戦型はそれより前に書く）
+///
+/// 新しい戦型はここに行を足す。ラベルに "," は使えない（DB にはカンマ区切りで保存する）
+pub static RULES: &[Rule] = &[
+    Rule {
+        label: "振り飛車",
+        conditions: &[RookFile {
+            files: &[5, 6, 7, 8, 9],
+            by: 30,
+        }],
+    },
+    Rule {
+        label: "居飛車",
+        conditions: &[Without("振り飛車")],
+    },
+    Rule {
+        label: "中飛車",
+        conditions: &[RookFile {
+            files: &[5],
+            by: 30,
+        }],
+    },
+    Rule {
+        label: "四間飛車",
+        conditions: &[RookFile {
+            files: &[6],
+            by: 30,
+        }],
+    },
+    Rule {
+        label: "三間飛車",
+        conditions: &[RookFile {
+            files: &[7],
+            by: 30,
+        }],
+    },
+    Rule {
+        label: "向かい飛車",
+        conditions: &[RookFile {
+            files: &[8],
+            by: 30,
+        }],
+    },
+    Rule {
+        label: "相振り飛車",
+        conditions: &[With("振り飛車"), Opponent(&With("振り飛車"))],
+    },
+    Rule {
+        label: "角換わり",
+        conditions: &[
+            With("居飛車"),
+            Opponent(&With("居飛車")),
+            BishopsExchanged { by: 30 },
+        ],
+    },
+    Rule {
+        label: "横歩取り",
+        conditions: &[
+            With("居飛車"),
+            Opponent(&With("居飛車")),
+            Either(&Shape {
+                pieces: &[('R', 3, 4)],
+                by: 30,
+            }),
+        ],
+    },
+    Rule {
+        label: "相掛かり",
+        conditions: &[
+            With("居飛車"),
+            Opponent(&With("居飛車")),
+            Without("角換わり"),
+            Without("横歩取り"),
+            // 角道を開けずに飛車先の歩を伸ばし合う
+            Shape {
+                pieces: &[('P', 2, 5), ('P', 7, 7)],
+                by: 12,
+            },
+            Opponent(&Shape {
+                pieces: &[('P', 2, 5), ('P', 7, 7)],
+                by: 12,
+            }),
+        ],
+    },
+    Rule {
+        label: "右四間飛車",
+        conditions: &[
+            With("居飛車"),
+            RookFile {
+                files: &[4],
+                by: 30,
+            },
+        ],
+    },
+    Rule {
+        label: "袖飛車",
+        conditions: &[
+            With("居飛車"),
+            Without("横歩取り"),
+            RookFile {
+                files: &[3],
+                by: 30,
+            },
+        ],
+    },
+    Rule {
+        label: "ゴキゲン中飛車",
+        // 角道を開けたまま 5 筋の歩を突いて中飛車にする
+        conditions: &[
+            With("中飛車"),
+            Shape {
+                pieces: &[('P', 7, 6), ('P', 5, 6), ('B', 8, 8), ('R', 5, 8)],
+                by: 16,
+            },
+        ],
+    },
+    Rule {
+        label: "石田流",
+        conditions: &[
+            With("三間飛車"),
+            Shape {
+                pieces: &[('P', 7, 5), ('R', 7, 6)],
+                by: 40,
+            },
+        ],
+    },
+    Rule {
+        label: "矢倉",
+        conditions: &[
+            With("居飛車"),
+            Shape {
+                pieces: &[('S', 7, 7), ('G', 7, 8), ('G', 6, 7)],
+                by: 60,
+            },
+        ],
+    },
+    Rule {
+        label: "雁木",
+        conditions: &[
+            With("居飛車"),
+            Without("矢倉"),
+            Shape {
+                pieces: &[('S', 6, 7), ('S', 4, 7)],
+                by: 50,
+            },
+        ],
+    },
+];
+
+/// 先手・後手それぞれの戦型（RULES の順）
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct Openings {
+    pub sente: Vec<String>,
+    pub gote: Vec<String>,
+}
+
+/// 各手の後の局面（boards[0] が 1 手目の後）から両者の戦型を判定する
+pub fn classify(boards: &[Board]) -> Openings {
+    if boards.len() < MIN_PLIES {
+        return Openings::default();
+    }
+
+    // [先手, 後手]
+    let mut labels: [Vec<&'static str>; 2] = [Vec::new(), Vec::new()];
+    for rule in RULES {
+        // 両者とも、この規則より前の判定結果だけを見る
+        let matched = [true, false].map(|is_sente| {
+            rule.conditions
+                .iter()
+                .all(|c| holds(c, boards, &labels, is_sente))
+        });
+        for (side, matched) in matched.into_iter().enumerate() {
+            if matched {
+                labels[side].push(rule.label);
+            }
+        }
+    }
+
+    let [sente, gote] = labels.map(|l| l.into_iter().map(String::from).collect());
+    Openings { sente, gote }
+}
+
+/// 取り込む棋譜の局面（packed）から戦型を判定する
+pub fn classify_bodies(bodies: &[KifBody]) -> Result<Openings> {
+    let boards = bodies
+        .iter()
+        .take(CLASSIFY_PLIES)
+        .map(|b| packed::unpack(&b.packed).map(|(board, _)| board))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(classify(&boards))
+}
+
+/// 戦型の名前が RULES にあるか確かめる
+pub fn check_label(label: &str) -> Result<()> {
+    if RULES.iter().any(|r| r.label == label) {
+        Ok(())
+    } else {
+        Err(Error::query("戦型の名前が正しくありません").with_text(label))
+    }
+}
+
+/// DB に保存するカンマ区切りの文字列にする
+pub fn join_labels(labels: &[String]) -> String {
+    labels.join(",")
+}
+
+/// join_labels の逆（NULL や空文字は戦型なし）
+pub fn split_labels(value: Option<&str>) -> Vec<String> {
+    value
+        .unwrap_or_default()
+        .split(',')
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+fn holds(
+    condition: &Condition,
+    boards: &[Board],
+    labels: &[Vec<&'static str>; 2],
+    is_sente: bool,
+) -> bool {
+    let own = &labels[if is_sente { 0 } else { 1 }];
+    match condition {
+        With(label) => own.contains(label),
+        Without(label) => !own.contains(label),
+        RookFile { files, by } => rook_file(&boards[..boards.len().min(*by)], is_sente)
+            .is_some_and(|f| files.contains(&f)),
+        Shape { pieces, by } => boards[..boards.len().min(*by)].iter().any(|board| {
+            pieces
+                .iter()
+                .all(|&(piece, file, rank)| board.own_piece(is_sente, file, rank) == Some(piece))
+        }),
+        BishopsExchanged { by } => boards[..boards.len().min(*by)]
+            .iter()
+            .any(|board| board.hands.iter().all(|hand| hand[BISHOP] > 0)),
+        Opponent(c) => holds(c, boards, labels, !is_sente),
+        Either(c) => holds(c, boards, labels, is_sente) || holds(c, boards, labels, !is_sente),
+    }
+}
+
+// 飛車を最初に 2 筋以外の自陣（6〜9 段）に動かした筋
+fn rook_file(boards: &[Board], is_sente: bool) -> Option<u8> {
+    boards.iter().find_map(|board| {
+        (1..=9u8)
+            .filter(|&file| file != 2)
+            .find(|&file| (6..=9).any(|rank| board.own_piece(is_sente, file, rank) == Some('R')))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import;
+    use crate::parser::Move;
+
+    fn openings(fugos: &[&str]) -> Openings {
+        let moves: Vec<Move> = fugos
+            .iter()
+            .enumerate()
+            .map(|(i, fugo)| Move {
+                te: i + 1,
+                fugo: fugo.to_string(),
+                eval: None,
+                line: None,
+            })
+            .collect();
+        let bodies = import::replay_moves(&moves, "test.kif").unwrap();
+        classify_bodies(&bodies).unwrap()
+    }
+
+    #[test]
+    fn test_classify_ranging_rook() {
+        let result = openings(&[
+            "７六歩(77)",
+            "３四歩(33)",
+            "６六歩(67)",
+            "８四歩(83)",
+            "６八飛(28)",
+            "８五歩(84)",
+            "７七角(88)",
+            "６二銀(71)",
+            "４八玉(59)",
+            "４二玉(51)",
+            "３八玉(48)",
+            "３二玉(42)",
+            "２八玉(38)",
+            "５二金(41)",
+            "３八銀(39)",
+            "７四歩(73)",
+        ]);
+        assert_eq!(result.sente, vec!["振り飛車", "四間飛車"]);
+        assert_eq!(result.gote, vec!["居飛車"]);
+
+        // 後手のゴキゲン中飛車（後手は盤を回して判定する）
+        let result = openings(&[
+            "７六歩(77)",
+            "３四歩(33)",
+            "２六歩(27)",
+            "５四歩(53)",
+            "２五歩(26)",
+            "５二飛(82)",
+            "４八銀(39)",
+            "６二玉(51)",
+            "６八玉(59)",
+            "７二玉(62)",
+            "７八玉(68)",
+            "８二玉(72)",
+            "５八金(69)",
+            "７二銀(71)",
+            "３六歩(37)",
+            "５五歩(54)",
+        ]);
+        assert_eq!(result.sente, vec!["居飛車"]);
+        assert_eq!(result.gote, vec!["振り飛車", "中飛車", "ゴキゲン中飛車"]);
+    }
+
+    #[test]
+    fn test_classify_bishop_exchange() {
+        let result = openings(&[
+            "７六歩(77)",
+            "８四歩(83)",
+            "２六歩(27)",
+            "３二金(41)",
+            "７七角(88)",
+            "３四歩(33)",
+            "８八銀(79)",
+            "７七角成(22)",
+            "同　銀(88)",
+            "２二銀(31)",
+            "７八金(69)",
+            "３三銀(22)",
+            "４八銀(39)",
+            "６二銀(71)",
+            "４六歩(47)",
+            "６四歩(63)",
+        ]);
+        assert_eq!(result.sente, vec!["居飛車", "角換わり"]);
+        assert_eq!(result.gote, vec!["居飛車", "角換わり"]);
+
+        // 短い対局は判定しない
+        assert_eq!(openings(&["７六歩(77)", "３四歩(33)"]), Openings::default());
+    }
+
+    #[test]
+    fn test_labels() {
+        let labels = vec!["振り飛車".to_string(), "四間飛車".to_string()];
+        assert_eq!(split_labels(Some(&join_labels(&labels))), labels);
+        assert!(split_labels(Some("")).is_empty());
+        assert!(split_labels(None).is_empty());
+        assert!(check_label("石田流").is_ok());
+        assert!(check_label("鬼殺し").is_err());
+    }
+}
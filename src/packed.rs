@@ -0,0 +1,238 @@
+use crate::board::{Board, HAND_PIECES};
+use crate::error::{Error, ErrorCategory, Result};
+
+// 盤上の駒の種類（3bit で表す）
+const KINDS: [char; 8] = ['P', 'L', 'N', 'S', 'G', 'B', 'R', 'K'];
+// 持ち駒の枚数に使う bit 数（HAND_PIECES の順。歩は18枚まで、飛角は2枚まで）
+const HAND_BITS: [usize; 7] = [5, 3, 3, 3, 3, 2, 2];
+
+/// ビットボードを保存する列の駒（大文字が先手、小文字が後手）
+pub const BITBOARD_PIECES: [char; 16] = [
+    'P', 'L', 'N', 'S', 'G', 'B', 'R', 'K', 'p', 'l', 'n', 's', 'g', 'b', 'r', 'k',
+];
+/// ビットボード 1 枚のバイト数（81マス → 88bit）
+pub const BITBOARD_BYTES: usize = 11;
+
+/// 局面をバイト列に詰める（最大 41 バイト）
+///
+/// 手番 1bit → 81マス（空き 0 / 駒 1 + 後手 1bit + 成り 1bit + 種類 3bit）→ 先手・後手の持ち駒の枚数
+pub fn pack(board: &Board, sente_to_move: bool) -> Vec<u8> {
+    let mut w = BitWriter::default();
+    w.write(sente_to_move as u32, 1);
+
+    for (y, rank) in board.squares.iter().enumerate() {
+        for (x, square) in rank.iter().enumerate() {
+            let Some(piece) = square else {
+                w.write(0, 1);
+                continue;
+            };
+            let kind = KINDS
+                .iter()
+                .position(|&k| k == piece.to_ascii_uppercase())
+                .unwrap_or(0);
+            w.write(1, 1);
+            w.write(piece.is_ascii_lowercase() as u32, 1);
+            w.write(board.promoted[y][x] as u32, 1);
+            w.write(kind as u32, 3);
+        }
+    }
+
+    for hand in &board.hands {
+        for (&count, &bits) in hand.iter().zip(&HAND_BITS) {
+            w.write(count as u32, bits);
+        }
+    }
+
+    w.bytes
+}
+
+/// pack で詰めたバイト列から盤面と手番（先手番なら true）を取り出す
+pub fn unpack(bytes: &[u8]) -> Result<(Board, bool)> {
+    let mut r = BitReader { bytes, pos: 0 };
+    let sente_to_move = r.read(1)? == 1;
+
+    let mut board = Board::new();
+    board.squares = [[None; 9]; 9];
+    for y in 0..9 {
+        for x in 0..9 {
+            if r.read(1)? == 0 {
+                continue;
+            }
+            let is_gote = r.read(1)? == 1;
+            let promoted = r.read(1)? == 1;
+            let kind = KINDS[r.read(3)? as usize];
+            board.squares[y][x] = Some(if is_gote {
+                kind.to_ascii_lowercase()
+            } else {
+                kind
+            });
+            board.promoted[y][x] = promoted;
+        }
+    }
+
+    for hand in board.hands.iter_mut() {
+        for (count, &bits) in hand.iter_mut().zip(&HAND_BITS) {
+            *count = r.read(bits)? as u8;
+        }
+    }
+
+    Ok((board, sente_to_move))
+}
+
+/// 成り駒の配置を保存する列（駒の種類は成る前の駒の列で表す）
+pub const PROMOTED_COLUMN: &str = "bb_promoted";
+
+/// 駒ごとの配置（bit c-1 がマス c）と、先手・後手それぞれの駒の配置
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Bitboards {
+    pub pieces: [u128; 16], // BITBOARD_PIECES の順（成り駒は成る前の駒に含める）
+    pub sente: u128,
+    pub gote: u128,
+    pub promoted: u128, // 先後問わず成っている駒
+}
+
+impl Bitboards {
+    /// 盤面から作る（マス c は squares[(c-1)/9][(c-1)%9]、c1〜c81 と同じ並び）
+    pub fn from_board(board: &Board) -> Bitboards {
+        let mut bb = Bitboards::default();
+        let squares = board.squares.iter().flatten();
+        let promoted = board.promoted.iter().flatten();
+        for (i, (square, &promoted)) in squares.zip(promoted).enumerate() {
+            let Some(piece) = square else {
+                continue;
+            };
+            let bit = 1u128 << i;
+            if let Some(index) = BITBOARD_PIECES.iter().position(|p| p == piece) {
+                bb.pieces[index] |= bit;
+            }
+            if piece.is_ascii_uppercase() {
+                bb.sente |= bit;
+            } else {
+                bb.gote |= bit;
+            }
+            if promoted {
+                bb.promoted |= bit;
+            }
+        }
+        bb
+    }
+
+    /// 駒 piece の配置（ビットボードの列がない駒は None）
+    pub fn piece(&self, piece: char) -> Option<u128> {
+        let index = BITBOARD_PIECES.iter().position(|&p| p == piece)?;
+        Some(self.pieces[index])
+    }
+}
+
+/// マス c（1〜81）だけが立ったビットボード
+pub fn square_mask(c: u8) -> u128 {
+    1u128 << (c - 1)
+}
+
+/// ビットボードを保存する列名（MySQL の列名は大文字小文字を区別しないので先後を名前に入れる）
+pub fn column_name(piece: char) -> Option<String> {
+    if !BITBOARD_PIECES.contains(&piece) {
+        return None;
+    }
+    let side = if piece.is_ascii_uppercase() {
+        "sente"
+    } else {
+        "gote"
+    };
+    Some(format!("bb_{}_{}", side, piece.to_ascii_lowercase()))
+}
+
+/// DB に保存するバイト列（上位バイトから）
+pub fn to_bytes(bb: u128) -> Vec<u8> {
+    bb.to_be_bytes()[16 - BITBOARD_BYTES..].to_vec()
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    len: usize, // 書き込んだ bit 数
+}
+
+impl BitWriter {
+    /// value の下位 bits ビットを上位から書く
+    fn write(&mut self, value: u32, bits: usize) {
+        for i in (0..bits).rev() {
+            if self.len.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            if (value >> i) & 1 == 1 {
+                let last = self.bytes.len() - 1;
+                self.bytes[last] |= 0x80 >> (self.len % 8);
+            }
+            self.len += 1;
+        }
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl BitReader<'_> {
+    fn read(&mut self, bits: usize) -> Result<u32> {
+        let mut value = 0;
+        for _ in 0..bits {
+            let byte = self
+                .bytes
+                .get(self.pos / 8)
+                .ok_or_else(|| Error::new(ErrorCategory::Db, "局面データが途中で終わっています"))?;
+            value = (value << 1) | ((byte >> (7 - self.pos % 8)) & 1) as u32;
+            self.pos += 1;
+        }
+        Ok(value)
+    }
+}
+
+// HAND_PIECES と HAND_BITS の並びがずれないように
+const _: () = assert!(HAND_PIECES.len() == HAND_BITS.len());
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_roundtrip() {
+        let mut board = Board::new();
+        for (te, fugo) in ["７六歩(77)", "３四歩(33)", "２二角成(88)", "２二銀(31)"]
+            .iter()
+            .enumerate()
+        {
+            board.apply_move(fugo, te % 2 == 0).unwrap();
+        }
+
+        let packed = pack(&board, true);
+        assert!(packed.len() <= 41, "{} バイト", packed.len());
+        assert_eq!(unpack(&packed).unwrap(), (board, true));
+        assert!(unpack(&packed[..10]).is_err());
+    }
+
+    #[test]
+    fn test_bitboards() {
+        let bb = Bitboards::from_board(&Board::new());
+        // 5九玉（77）・5一玉（5）
+        assert_eq!(bb.piece('K'), Some(square_mask(77)));
+        assert_eq!(bb.piece('k'), Some(square_mask(5)));
+        assert_eq!(bb.sente.count_ones(), 20);
+        assert_eq!(bb.sente & bb.gote, 0);
+
+        assert_eq!(column_name('P').as_deref(), Some("bb_sente_p"));
+        assert_eq!(column_name('?'), None);
+        assert_eq!(to_bytes(square_mask(1)), [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(to_bytes(square_mask(81)).len(), BITBOARD_BYTES);
+
+        // 2二角成の後は、2二（17）が角のビットボードと成り駒のビットボードの両方に立つ
+        let mut board = Board::new();
+        board.apply_move("７六歩(77)", true).unwrap();
+        board.apply_move("３四歩(33)", false).unwrap();
+        board.apply_move("２二角成(88)", true).unwrap();
+        let bb = Bitboards::from_board(&board);
+        assert_eq!(bb.piece('B'), Some(square_mask(17)));
+        assert_eq!(bb.promoted, square_mask(17));
+    }
+}
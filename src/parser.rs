@@ -1,22 +1,68 @@
+use crate::error::{Error, Result};
 use crate::models::KifHeader;
-use encoding_rs::EUC_JP;
-use encoding_rs::SHIFT_JIS;
+use encoding_rs::{EUC_JP, Encoding, SHIFT_JIS, UTF_8, UTF_16BE, UTF_16LE};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Serialize;
 use std::fs::File;
 use std::io::{BufReader, Read};
 use std::path::Path;
 
+#[derive(Serialize, Clone)]
 pub struct Move {
     pub te: usize,    // 何手目
     pub fugo: String, // ７六歩(77) など
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eval: Option<i32>, // この手の後の評価値（先手から見た値。解析コメントがある場合のみ）
+    #[serde(skip)]
+    pub line: Option<usize>, // 棋譜ファイル中の行番号（1始まり、DBから読んだ場合は None）
 }
 
-pub fn parse_header_and_result(kif_text: &str, filename: &str) -> KifHeader {
+/// 符号を分解したもの（"同" は normalize_fugo で解決済みであること）
+pub struct ParsedFugo {
+    pub to: (usize, usize),           // 移動先（筋, 段）
+    pub from: Option<(usize, usize)>, // 移動元（筋, 段）。打ち駒は None
+    pub piece: String,                // 動かす駒（"歩", "成銀", "龍" など）
+    pub promote: bool,                // 成る手かどうか
+}
+
+static FUGO_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"^(?P<to_file>[１２３４５６７８９])(?P<to_rank>[一二三四五六七八九])(?P<piece>成[銀桂香]|[歩香桂銀金角飛玉王と馬龍竜])(?P<promote>不成|成)?(?P<drop>打)?(?:\((?P<from>[1-9]{2})\))?",
+    )
+    .unwrap()
+});
+
+// 指し手の後のコメントにある評価値（"**解析 0 ○ ... 評価値 123 読み筋 ..." など）
+static EVAL_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"評価値\s*[:：=]?\s*(?P<value>[+-]?\d+)").unwrap());
+
+// 最後の行に書かれる終局の言葉と、end_reason に保存する名前
+const END_WORDS: [(&str, &str); 5] = [
+    ("切れ負け", "時間切れ"),
+    ("時間切れ", "時間切れ"),
+    ("詰み", "詰み"),
+    ("反則", "反則"),
+    ("中断", "中断"),
+];
+
+/// 終局を表す指し手の行（"投了" "詰み" "千日手" など）か
+pub fn is_end_move(fugo: &str) -> bool {
+    ["投了", "千日手", "持将棋"]
+        .iter()
+        .chain(END_WORDS.iter().map(|(word, _)| word))
+        .any(|word| fugo.contains(word))
+}
+
+pub fn parse_header_and_result(kif_text: &str, filename: &str) -> Result<KifHeader> {
     use chrono::Local;
 
     let mut sente_player = String::new();
     let mut gote_player = String::new();
     let mut started_at: Option<String> = None;
     let mut ended_at: Option<String> = None;
+    let mut site: Option<String> = None;
+    let mut time_control: Option<String> = None;
 
     for line in kif_text.lines() {
         if line.starts_with("先手：") {
@@ -27,15 +73,21 @@ pub fn parse_header_and_result(kif_text: &str, filename: &str) -> KifHeader {
             if let Some(dt) = line.strip_prefix("開始日時：") {
                 started_at = Some(dt.trim().replace('/', "-")); // "2025-07-10 11:28:32"
             }
-        } else if line.starts_with("終了日時：") {
-            if let Some(dt) = line.strip_prefix("終了日時：") {
-                ended_at = Some(dt.trim().replace('/', "-"));
-            }
+        } else if line.starts_with("終了日時：")
+            && let Some(dt) = line.strip_prefix("終了日時：")
+        {
+            ended_at = Some(dt.trim().replace('/', "-"));
+        } else if let Some(value) = line.strip_prefix("場所：") {
+            site = Some(value.trim().to_string()).filter(|v| !v.is_empty());
+        } else if let Some(value) = line.strip_prefix("持ち時間：") {
+            time_control = Some(value.trim().to_string()).filter(|v| !v.is_empty());
         }
     }
 
     // 終局情報の検出
     let mut is_sente_win = true;
+    let mut is_draw = false;
+    let mut end_reason: Option<&str> = None;
     let mut is_resutl_found = false;
     let mut prev_line: Option<String> = None;
 
@@ -47,33 +99,50 @@ pub fn parse_header_and_result(kif_text: &str, filename: &str) -> KifHeader {
                 "反則手や時間切れの直前の行: {}",
                 prev_line.as_deref().unwrap_or("なし")
             );
-            if let Some(last) = prev_line.as_ref() {
-                if let Some((num_str, _)) = last.trim().split_once(char::is_whitespace) {
-                    if let Ok(num) = num_str.parse::<u32>() {
-                        print!("終局: {} {}手目", line, num);
-                        // 時間切れの場合、直前の行を指した方が勝ち
-                        if line.contains("*時間切れ") {
-                            is_sente_win = num % 2 == 1;
-                        } else {
-                            is_sente_win = num % 2 == 0;
-                        }
-                        is_resutl_found = true;
-                        break;
-                    }
+            if let Some(last) = prev_line.as_ref()
+                && let Some((num_str, _)) = last.trim().split_once(char::is_whitespace)
+                && let Ok(num) = num_str.parse::<u32>()
+            {
+                print!("終局: {} {}手目", line, num);
+                // 時間切れの場合、直前の行を指した方が勝ち
+                if line.contains("*時間切れ") {
+                    is_sente_win = num % 2 == 1;
+                    end_reason = Some("時間切れ");
+                } else {
+                    is_sente_win = num % 2 == 0;
+                    end_reason = Some("反則");
                 }
+                is_resutl_found = true;
+                break;
             }
         }
 
-        if line.contains("投了") {
-            if let Some((num_str, rest)) = line.trim().split_once(char::is_whitespace) {
-                if let Ok(num) = num_str.parse::<u32>() {
-                    // 偶数手で投了なら、先手の勝ち
-                    print!("投了手: {} ", rest);
-                    is_sente_win = num % 2 == 0;
-                    is_resutl_found = true;
-                    break;
-                }
-            }
+        // 千日手・持将棋は引き分け
+        if (line.contains("千日手") || line.contains("持将棋"))
+            && let Some((num_str, _)) = line.trim().split_once(char::is_whitespace)
+            && num_str.parse::<u32>().is_ok()
+        {
+            is_sente_win = false;
+            is_draw = true;
+            end_reason = Some(if line.contains("千日手") {
+                "千日手"
+            } else {
+                "持将棋"
+            });
+            is_resutl_found = true;
+            break;
+        }
+
+        if line.contains("投了")
+            && let Some((num_str, rest)) = line.trim().split_once(char::is_whitespace)
+            && let Ok(num) = num_str.parse::<u32>()
+        {
+            // 偶数手で投了なら、先手の勝ち
+            print!("投了手: {} ", rest);
+            is_sente_win = num % 2 == 0;
+            end_reason = Some("投了");
+            is_resutl_found = true;
+            break;
         }
 
         // 直前のlineを保管
@@ -81,34 +150,44 @@ pub fn parse_header_and_result(kif_text: &str, filename: &str) -> KifHeader {
     }
 
     // この時点で勝敗が不明である場合は、最後の手を指した方を勝ちとする
-    if is_resutl_found == false {
-        if let Some(last) = prev_line.as_ref() {
-            if let Some((num_str, _)) = last.trim().split_once(char::is_whitespace) {
-                if let Ok(num) = num_str.parse::<u32>() {
-                    print!(
-                        "投了/反則等の終局情報が見つからないため、最後の手を指した方を勝ちとします： 最終手: {} ",
-                        last,
-                    );
-                    is_sente_win = num % 2 == 1;
-                }
-            }
-        }
+    if !is_resutl_found
+        && let Some(last) = prev_line.as_ref()
+        && let Some((num_str, _)) = last.trim().split_once(char::is_whitespace)
+        && let Ok(num) = num_str.parse::<u32>()
+    {
+        print!(
+            "投了/反則等の終局情報が見つからないため、最後の手を指した方を勝ちとします： 最終手: {} ",
+            last,
+        );
+        is_sente_win = num % 2 == 1;
+        // 勝敗の判定は変えないが、最後の行に終局の理由があれば残す
+        end_reason = END_WORDS
+            .iter()
+            .find(|(word, _)| last.contains(word))
+            .map(|&(_, reason)| reason);
     }
 
-    KifHeader {
+    Ok(KifHeader {
         kif_filename: filename.to_string(),
         sente_player,
         gote_player,
         is_sente_win,
+        is_draw,
         started_at,
         ended_at,
+        site,
+        time_control,
+        end_reason: end_reason.map(String::from),
+        // 戦型は指し手を再現してから判定する（import::import_kif_file）
+        sente_openings: Vec::new(),
+        gote_openings: Vec::new(),
         created_at: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
         created_by: "system".to_string(),
-    }
+    })
 }
 
-pub fn read_kif_file(path: &str) -> Result<(String, String), Box<dyn std::error::Error>> {
-    let file = File::open(path)?;
+pub fn read_kif_file(path: &str) -> Result<(String, String)> {
+    let file = File::open(path).map_err(|e| Error::from(e).with_text(path))?;
     let filename = Path::new(path)
         .file_name()
         .unwrap_or_default()
@@ -119,79 +198,339 @@ pub fn read_kif_file(path: &str) -> Result<(String, String), Box<dyn std::error:
     let mut buffer = Vec::new();
     reader.read_to_end(&mut buffer)?;
 
-    if let Ok(s) = String::from_utf8(buffer.clone()) {
-        return Ok((s, filename));
+    let contents = decode_kif_bytes(&buffer, &filename)?;
+    Ok((contents, filename))
+}
+
+/// 棋譜ファイルのバイト列を文字列に変換する
+///
+/// BOM → 先頭行の `encoding=` 宣言 → 拡張子 `.kifu`（UTF-8）→ 候補の文字コードの中で最も不自然な文字が少なく読めたもの、の順で判定する。
+/// どの文字コードでも変換エラーが出る場合は、文字化けしたまま保存しないようエラーにする。
+pub fn decode_kif_bytes(bytes: &[u8], filename: &str) -> Result<String> {
+    // BOM があればそれに従う（UTF-8 / UTF-16LE / UTF-16BE）
+    if let Some((encoding, bom_len)) = Encoding::for_bom(bytes) {
+        return decode_strict(encoding, &bytes[bom_len..]).ok_or_else(|| {
+            Error::encoding(format!("{} として読み込めませんでした", encoding.name()))
+                .with_file(filename)
+        });
     }
 
-    let (s, _, had_errors) = SHIFT_JIS.decode(&buffer);
-    if !had_errors {
-        return Ok((s.into_owned(), filename));
+    // "#KIF version=2.0 encoding=UTF-8" のような明示的な宣言
+    if let Some(encoding) = declared_encoding(bytes) {
+        if let Some(s) = decode_strict(encoding, bytes) {
+            return Ok(s);
+        }
+        println!(
+            "警告: 宣言された文字コード {} で読み込めないため自動判別します: {}",
+            encoding.name(),
+            filename
+        );
     }
 
-    let (s, _, had_errors) = EUC_JP.decode(&buffer);
-    if !had_errors {
-        return Ok((s.into_owned(), filename));
+    // .kifu は UTF-8 の KIF
+    if filename.to_lowercase().ends_with(".kifu")
+        && let Some(s) = decode_strict(UTF_8, bytes)
+        && !s.contains('\0')
+    {
+        return Ok(s);
     }
 
-    Err("文字コードの自動判別に失敗しました".into())
+    // 変換エラーが最も少なく、その中で棋譜に出てこない文字が最も少ないもの（同点なら先の候補）
+    let (errors, _, s) = CANDIDATES
+        .into_iter()
+        .map(|encoding| decode_score(encoding, bytes))
+        .min_by_key(|&(errors, uncommon, _)| (errors, uncommon))
+        .expect("候補の文字コードは空ではない");
+
+    if errors > 0 {
+        return Err(Error::encoding("文字コードの自動判別に失敗しました").with_file(filename));
+    }
+    Ok(s)
+}
+
+// 自動判別の候補（UTF-8 として正しく読めるものは UTF-8、それ以外は KIF の標準の Shift_JIS を優先する）
+const CANDIDATES: [&Encoding; 5] = [UTF_8, SHIFT_JIS, EUC_JP, UTF_16LE, UTF_16BE];
+
+/// エラー（置換文字）なしで変換できた場合だけ文字列を返す
+fn decode_strict(encoding: &'static Encoding, bytes: &[u8]) -> Option<String> {
+    let (s, had_errors) = encoding.decode_without_bom_handling(bytes);
+    if had_errors {
+        None
+    } else {
+        Some(s.into_owned())
+    }
+}
+
+/// 文字コード encoding で読んだ結果と、その不自然さ（変換エラーと制御文字の数, 棋譜に出てこない文字の数）
+///
+/// UTF-16 を 1 バイトの文字コードで読むと ASCII 部分が NUL になり、バイト順を逆に読むと改行や数字が
+/// 見慣れない文字になるので、BOM のない UTF-16 もこの数で見分けられる
+fn decode_score(encoding: &'static Encoding, bytes: &[u8]) -> (usize, usize, String) {
+    let (s, _) = encoding.decode_without_bom_handling(bytes);
+    let errors = s
+        .chars()
+        .filter(|&c| {
+            c == char::REPLACEMENT_CHARACTER || (c.is_control() && !c.is_ascii_whitespace())
+        })
+        .count();
+    let uncommon = s.chars().filter(|&c| !is_common_char(c)).count();
+    (errors, uncommon, s.into_owned())
+}
+
+/// 棋譜で普通に使う文字か
+fn is_common_char(c: char) -> bool {
+    matches!(c,
+        ' '..='~' | '\t' | '\r' | '\n'
+        | '\u{2010}'..='\u{266F}' // 記号（…、○、▲△、☗☖ など）
+        | '\u{3000}'..='\u{303F}' // 全角スペース・句読点
+        | '\u{3040}'..='\u{30FF}' // ひらがな・カタカナ
+        | '\u{4E00}'..='\u{9FFF}' // 漢字
+        | '\u{FF01}'..='\u{FF5E}') // 全角英数・記号
+}
+
+/// 先頭の数行から `encoding=...` の宣言を探す
+fn declared_encoding(bytes: &[u8]) -> Option<&'static Encoding> {
+    // 宣言部分は ASCII なので、文字コードに関係なく lossy に読めば十分
+    let head = String::from_utf8_lossy(&bytes[..bytes.len().min(512)]);
+
+    head.lines()
+        .take(3)
+        .filter(|line| line.starts_with('#'))
+        .find_map(|line| {
+            let label = line.split("encoding=").nth(1)?;
+            let label = label.split_whitespace().next()?;
+            Encoding::for_label(label.as_bytes())
+        })
 }
 
 pub fn parse_kif_moves(lines: &[String]) -> Vec<Move> {
-    let mut moves = vec![];
+    let mut moves: Vec<Move> = vec![];
 
-    for line in lines {
+    for (index, line) in lines.iter().enumerate() {
         let line = line.trim();
 
-        // 手数から始まる行だけを対象に
-        if let Some(first_char) = line.chars().next() {
-            if first_char.is_digit(10) {
-                // 手数と符号部分を抽出
-                if let Some(index) = line.find(' ') {
-                    let (te_str, rest) = line.split_at(index);
-                    if let Ok(te) = te_str.trim().parse::<usize>() {
-                        let fugo = if rest.contains("打") {
-                            // 打ち駒は () を含まない → "７四歩打" の部分を取り出す
-                            rest.split_whitespace().next().unwrap_or("").to_string()
-                        } else if let Some(start_of_time) = rest.find(')') {
-                            rest[..start_of_time + 1].trim().to_string()
-                        } else {
-                            rest.trim().to_string()
-                        };
-
-                        moves.push(Move { te, fugo });
-                    }
-                }
-            }
+        if line.starts_with('*') {
+            add_eval(&mut moves, line);
+        } else if let Some(m) = parse_move_line(line, index) {
+            moves.push(m);
         }
     }
 
     moves
 }
 
+/// 変化（分岐）を含めて読み、本譜と各変化をそれぞれ 1 手目からの手順にして返す（本譜が先頭）
+///
+/// 「変化：N手」は、それより前に書かれた手順のうち N 手目を含む最後のものから分岐したものとして読む
+pub fn parse_kif_variations(lines: &[String]) -> Vec<Vec<Move>> {
+    // (書かれている最初の手数, 1 手目からの手順)
+    let mut variations: Vec<(usize, Vec<Move>)> = Vec::new();
+    let mut start = 1;
+    let mut moves: Vec<Move> = vec![];
+
+    for (index, line) in lines.iter().enumerate() {
+        let line = line.trim();
+
+        if let Some(rest) = line.strip_prefix("変化：") {
+            let Ok(te) = rest.trim_end_matches('手').trim().parse::<usize>() else {
+                continue;
+            };
+            variations.push((start, std::mem::take(&mut moves)));
+            let Some((_, base)) = variations
+                .iter()
+                .rev()
+                .find(|(s, v)| *s <= te && v.iter().any(|m| m.te == te))
+            else {
+                // 分岐元が見つからない変化は読まない
+                start = usize::MAX;
+                continue;
+            };
+            start = te;
+            moves = base.iter().take_while(|m| m.te < te).cloned().collect();
+            continue;
+        }
+        if start == usize::MAX {
+            continue;
+        }
+
+        if line.starts_with('*') {
+            add_eval(&mut moves, line);
+        } else if let Some(m) = parse_move_line(line, index) {
+            moves.push(m);
+        }
+    }
+    if start != usize::MAX {
+        variations.push((start, moves));
+    }
+
+    variations
+        .into_iter()
+        .map(|(_, v)| v)
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+// 解析コメントの評価値は直前の指し手に付ける（複数の候補手があれば最初のもの）
+fn add_eval(moves: &mut [Move], line: &str) {
+    if let Some(last) = moves.last_mut()
+        && last.eval.is_none()
+        && let Some(caps) = EVAL_RE.captures(line)
+    {
+        last.eval = caps["value"].parse().ok();
+    }
+}
+
+// 手数から始まる行を指し手として読む（index は 0 始まりの行番号）
+fn parse_move_line(line: &str, index: usize) -> Option<Move> {
+    if !line.chars().next()?.is_ascii_digit() {
+        return None;
+    }
+
+    // 手数と符号部分を抽出
+    let space = line.find(' ')?;
+    let (te_str, rest) = line.split_at(space);
+    let te = te_str.trim().parse::<usize>().ok()?;
+    let fugo = if rest.contains("打") {
+        // 打ち駒は () を含まない → "７四歩打" の部分を取り出す
+        rest.split_whitespace().next().unwrap_or("").to_string()
+    } else if let Some(start_of_time) = rest.find(')') {
+        rest[..start_of_time + 1].trim().to_string()
+    } else {
+        rest.trim().to_string()
+    };
+
+    Some(Move {
+        te,
+        fugo,
+        eval: None,
+        line: Some(index + 1),
+    })
+}
+
 pub fn normalize_fugo(fugo: &str, prev_fugo: Option<&str>) -> String {
-    use regex::Regex;
     let fugo = fugo.replace(['\u{3000}', ' '], ""); // スペース削除
 
-    if fugo.starts_with("同") {
-        if let Some(prev) = prev_fugo {
-            let re =
-                Regex::new(r"(?P<to_file>[１２３４５６７８９])(?P<to_rank>[一二三四五六七八九])")
-                    .unwrap();
-            if let Some(caps) = re.captures(prev) {
-                let file = caps.name("to_file").unwrap().as_str();
-                let rank = caps.name("to_rank").unwrap().as_str();
-                // 置換: "同　銀(48)" → "３七銀(48)" など
-                return fugo.replacen("同", &format!("{}{}", file, rank), 1);
-            }
+    if fugo.starts_with("同")
+        && let Some(prev) = prev_fugo
+    {
+        let re = Regex::new(r"(?P<to_file>[１２３４５６７８９])(?P<to_rank>[一二三四五六七八九])")
+            .unwrap();
+        if let Some(caps) = re.captures(prev) {
+            let file = caps.name("to_file").unwrap().as_str();
+            let rank = caps.name("to_rank").unwrap().as_str();
+            // 置換: "同　銀(48)" → "３七銀(48)" など
+            return fugo.replacen("同", &format!("{}{}", file, rank), 1);
         }
     }
     fugo.to_string()
 }
 
+/// 符号（例: "７六歩(77)", "５五角打", "２二角成(88)"）を移動元・移動先・駒に分解する
+pub fn parse_fugo(fugo: &str) -> Option<ParsedFugo> {
+    let fugo = fugo.replace(['\u{3000}', ' '], "");
+    let caps = FUGO_RE.captures(&fugo)?;
+
+    let to_file = crate::board::convert_kanji_to_digit(caps.name("to_file")?.as_str())?;
+    let to_rank = crate::board::convert_kanji_to_rank(caps.name("to_rank")?.as_str())?;
+    let from = caps.name("from").map(|m| {
+        let digits: Vec<usize> = m
+            .as_str()
+            .chars()
+            .filter_map(|c| c.to_digit(10).map(|d| d as usize))
+            .collect();
+        (digits[0], digits[1])
+    });
+
+    // 移動元が書かれていない手は打ち駒のみ
+    if from.is_none() && caps.name("drop").is_none() {
+        return None;
+    }
+
+    Some(ParsedFugo {
+        to: (to_file, to_rank),
+        from,
+        piece: caps.name("piece")?.as_str().to_string(),
+        promote: caps
+            .name("promote")
+            .map(|m| m.as_str() == "成")
+            .unwrap_or(false),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parse_kif_moves_with_eval() {
+        let lines: Vec<String> = [
+            "手数----指手---------消費時間--",
+            "   1 ７六歩(77)   ( 0:01/00:00:01)",
+            "**解析 0 ○ 候補1 時間 00:01.0 深さ 20/32 評価値 54 読み筋 △３四歩(33)",
+            "**解析 0 候補2 評価値 -30 読み筋 △８四歩(83)",
+            "   2 ３四歩(33)   ( 0:02/00:00:02)",
+        ]
+        .iter()
+        .map(|l| l.to_string())
+        .collect();
+
+        let moves = parse_kif_moves(&lines);
+        assert_eq!(moves.len(), 2);
+        assert_eq!(moves[0].eval, Some(54));
+        assert_eq!(moves[1].eval, None);
+    }
+
+    #[test]
+    fn test_parse_lenient_kif() {
+        // 平手以外の手合割や、対局者名のない棋譜も読める
+        let header =
+            parse_header_and_result("手合割：香落ち\n先手：\n   1 ３四歩(33)\n", "test.kif")
+                .unwrap();
+        assert_eq!(header.sente_player, "");
+        assert_eq!(header.gote_player, "");
+
+        // 変化の手もそのまま読む（取り込みは本譜の「投了」で止まる）
+        let lines: Vec<String> = [
+            "   1 ７六歩(77)",
+            "   2 投了",
+            "変化：1手",
+            "   1 ２六歩(27)",
+        ]
+        .iter()
+        .map(|l| l.to_string())
+        .collect();
+        let fugos: Vec<String> = parse_kif_moves(&lines)
+            .into_iter()
+            .map(|m| m.fugo)
+            .collect();
+        assert_eq!(fugos, vec!["７六歩(77)", "投了", "２六歩(27)"]);
+    }
+
+    #[test]
+    fn test_parse_end_reason() {
+        let kif = |last: &str| {
+            format!(
+                "先手：sente\n後手：gote\n手数----指手---------消費時間--\n   1 ７六歩(77)\n   2 ３四歩(33)\n{}\n",
+                last
+            )
+        };
+
+        let header = parse_header_and_result(&kif("   3 投了"), "test.kif").unwrap();
+        assert_eq!(header.end_reason.as_deref(), Some("投了"));
+        assert!(!header.is_sente_win);
+
+        let header = parse_header_and_result(&kif("   3 千日手"), "test.kif").unwrap();
+        assert_eq!(header.end_reason.as_deref(), Some("千日手"));
+        assert!(header.is_draw);
+
+        // 勝敗は最後の手を指した方の勝ちのまま、理由だけ残す
+        let header = parse_header_and_result(&kif("   3 切れ負け"), "test.kif").unwrap();
+        assert_eq!(header.end_reason.as_deref(), Some("時間切れ"));
+
+        let header = parse_header_and_result(&kif(""), "test.kif").unwrap();
+        assert_eq!(header.end_reason, None);
+    }
+
     #[test]
     fn test_normalize_fugo_with_doh_doublebyte() {
         let prev = "３七歩成(36)";
@@ -220,4 +559,98 @@ mod tests {
         let current = "同　銀(48)";
         assert_eq!(normalize_fugo(current, None), "同銀(48)");
     }
+
+    #[test]
+    fn test_parse_fugo_move_drop_and_promotion() {
+        let m = parse_fugo("７六歩(77)").unwrap();
+        assert_eq!(
+            (m.to, m.from, m.piece.as_str(), m.promote),
+            ((7, 6), Some((7, 7)), "歩", false)
+        );
+
+        let m = parse_fugo("５五角打").unwrap();
+        assert_eq!((m.to, m.from, m.piece.as_str()), ((5, 5), None, "角"));
+
+        let m = parse_fugo("２二角成(88)").unwrap();
+        assert_eq!((m.piece.as_str(), m.promote), ("角", true));
+
+        let m = parse_fugo("５二成銀(63)").unwrap();
+        assert_eq!((m.piece.as_str(), m.promote), ("成銀", false));
+
+        let m = parse_fugo("３三桂不成(45)").unwrap();
+        assert_eq!((m.piece.as_str(), m.promote), ("桂", false));
+
+        assert!(parse_fugo("投了").is_none());
+    }
+
+    const SAMPLE_KIF: &str = "手合割：平手\n先手：sente\n後手：gote\n手数----指手---------消費時間--\n   1 ７六歩(77)\n   2 投了\n";
+
+    #[test]
+    fn test_decode_kif_bytes_shift_jis() {
+        let (bytes, _, _) = SHIFT_JIS.encode(SAMPLE_KIF);
+        assert_eq!(decode_kif_bytes(&bytes, "a.kif").unwrap(), SAMPLE_KIF);
+    }
+
+    #[test]
+    fn test_decode_kif_bytes_utf8_bom_and_kifu() {
+        let mut bytes = vec![0xEF, 0xBB, 0xBF];
+        bytes.extend_from_slice(SAMPLE_KIF.as_bytes());
+        assert_eq!(decode_kif_bytes(&bytes, "a.kif").unwrap(), SAMPLE_KIF);
+        assert_eq!(
+            decode_kif_bytes(SAMPLE_KIF.as_bytes(), "a.kifu").unwrap(),
+            SAMPLE_KIF
+        );
+    }
+
+    #[test]
+    fn test_decode_kif_bytes_utf16() {
+        let le: Vec<u8> = SAMPLE_KIF
+            .encode_utf16()
+            .flat_map(|u| u.to_le_bytes())
+            .collect();
+        assert_eq!(decode_kif_bytes(&le, "a.kif").unwrap(), SAMPLE_KIF);
+
+        let mut be_with_bom = vec![0xFE, 0xFF];
+        be_with_bom.extend(SAMPLE_KIF.encode_utf16().flat_map(|u| u.to_be_bytes()));
+        assert_eq!(decode_kif_bytes(&be_with_bom, "a.kif").unwrap(), SAMPLE_KIF);
+    }
+
+    #[test]
+    fn test_decode_kif_bytes_without_bom_or_keywords() {
+        // 棋譜らしい語のない短い Shift_JIS
+        let (bytes, _, _) = SHIFT_JIS.encode("▲７六歩\n");
+        assert_eq!(decode_kif_bytes(&bytes, "a.kif").unwrap(), "▲７六歩\n");
+        let (bytes, _, _) = SHIFT_JIS.encode("先手：あ");
+        assert_eq!(decode_kif_bytes(&bytes, "a.kif").unwrap(), "先手：あ");
+
+        // 上位/下位バイトが 0 になる「一」や全角スペースの多い BOM なしの UTF-16
+        let text = "一一一\u{3000}一\u{3000}一一\n先手：一\n";
+        let le: Vec<u8> = text.encode_utf16().flat_map(|u| u.to_le_bytes()).collect();
+        assert_eq!(decode_kif_bytes(&le, "a.kif").unwrap(), text);
+        let be: Vec<u8> = text.encode_utf16().flat_map(|u| u.to_be_bytes()).collect();
+        assert_eq!(decode_kif_bytes(&be, "a.kif").unwrap(), text);
+
+        let be: Vec<u8> = SAMPLE_KIF
+            .encode_utf16()
+            .flat_map(|u| u.to_be_bytes())
+            .collect();
+        assert_eq!(decode_kif_bytes(&be, "a.kif").unwrap(), SAMPLE_KIF);
+    }
+
+    #[test]
+    fn test_decode_kif_bytes_declared_encoding() {
+        let text = format!("#KIF version=2.0 encoding=Shift_JIS\n{}", SAMPLE_KIF);
+        let (bytes, _, _) = SHIFT_JIS.encode(&text);
+        assert_eq!(
+            declared_encoding(&bytes).map(|e| e.name()),
+            Some("Shift_JIS")
+        );
+        assert_eq!(decode_kif_bytes(&bytes, "a.kif").unwrap(), text);
+    }
+
+    #[test]
+    fn test_decode_kif_bytes_rejects_mojibake() {
+        let bytes: Vec<u8> = (0xA1..=0xFE).flat_map(|b| [b, b]).collect();
+        assert!(decode_kif_bytes(&bytes, "a.kif").is_err());
+    }
 }
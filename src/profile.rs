@@ -0,0 +1,132 @@
+use crate::config::MY_USERNAMES;
+use crate::error::{Error, Result};
+use mysql::prelude::Queryable;
+use mysql::*;
+use serde::{Deserialize, Serialize};
+
+// プロファイル名の最大長（player_aliases.profile の列幅）
+const MAX_NAME_LEN: usize = 64;
+
+/// 対局者のプロファイル（将棋ウォーズ・81道場などのアカウント名をまとめたもの）
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PlayerProfile {
+    pub name: String,
+    pub aliases: Vec<String>,
+}
+
+impl PlayerProfile {
+    /// 前後の空白を除き、空のアカウント名と重複を取り除く
+    pub fn new(name: &str, aliases: &[String]) -> Result<PlayerProfile> {
+        let name = name.trim();
+        if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+            return Err(Error::query(format!(
+                "プロファイル名は 1〜{} 文字で指定してください",
+                MAX_NAME_LEN
+            ))
+            .with_text(name));
+        }
+
+        let mut unique: Vec<String> = Vec::new();
+        for alias in aliases.iter().map(|a| a.trim()).filter(|a| !a.is_empty()) {
+            if !unique.iter().any(|u| u == alias) {
+                unique.push(alias.to_string());
+            }
+        }
+        if unique.is_empty() {
+            return Err(Error::query("アカウント名を 1 つ以上指定してください").with_text(name));
+        }
+
+        Ok(PlayerProfile {
+            name: name.to_string(),
+            aliases: unique,
+        })
+    }
+}
+
+/// 保存済みのプロファイルを名前順に全て取得する
+pub fn list(conn: &mut PooledConn) -> Result<Vec<PlayerProfile>> {
+    let rows: Vec<(String, String)> =
+        conn.query("SELECT profile, username FROM player_aliases ORDER BY profile, username")?;
+
+    let mut profiles: Vec<PlayerProfile> = Vec::new();
+    for (name, username) in rows {
+        match profiles.last_mut() {
+            Some(last) if last.name == name => last.aliases.push(username),
+            _ => profiles.push(PlayerProfile {
+                name,
+                aliases: vec![username],
+            }),
+        }
+    }
+    Ok(profiles)
+}
+
+/// プロファイルを 1 件取得する
+pub fn fetch(conn: &mut PooledConn, name: &str) -> Result<Option<PlayerProfile>> {
+    let aliases: Vec<String> = conn.exec(
+        "SELECT username FROM player_aliases WHERE profile = ? ORDER BY username",
+        (name,),
+    )?;
+    if aliases.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(PlayerProfile {
+        name: name.to_string(),
+        aliases,
+    }))
+}
+
+/// プロファイルを保存する（同じ名前があればアカウント名を置き換える）
+pub fn save(conn: &mut PooledConn, profile: &PlayerProfile) -> Result<()> {
+    let mut tx = conn.start_transaction(TxOpts::default())?;
+    tx.exec_drop(
+        "DELETE FROM player_aliases WHERE profile = ?",
+        (&profile.name,),
+    )?;
+    tx.exec_batch(
+        "INSERT INTO player_aliases (profile, username) VALUES (?, ?)",
+        profile.aliases.iter().map(|a| (&profile.name, a)),
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// プロファイルを削除する（存在しなければ false）
+pub fn delete(conn: &mut PooledConn, name: &str) -> Result<bool> {
+    conn.exec_drop("DELETE FROM player_aliases WHERE profile = ?", (name,))?;
+    Ok(conn.affected_rows() > 0)
+}
+
+/// 検索の視点にするアカウント名を決める（None なら全員）
+///
+/// プロファイル名の指定があればそのアカウント名、なければ MY_USERNAMES（未設定なら全員）
+pub fn resolve(conn: &mut PooledConn, name: Option<&str>) -> Result<Option<Vec<String>>> {
+    match name {
+        Some(name) => fetch(conn, name)?
+            .map(|p| Some(p.aliases))
+            .ok_or_else(|| Error::not_found("プロファイルが見つかりません").with_text(name)),
+        None if MY_USERNAMES.is_empty() => Ok(None),
+        None => Ok(Some(MY_USERNAMES.clone())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_profile() {
+        let aliases = vec![
+            " Ringosky ".to_string(),
+            "".to_string(),
+            "ringo_81".to_string(),
+            "Ringosky".to_string(),
+        ];
+        let profile = PlayerProfile::new(" ringo ", &aliases).unwrap();
+        assert_eq!(profile.name, "ringo");
+        assert_eq!(profile.aliases, vec!["Ringosky", "ringo_81"]);
+
+        assert!(PlayerProfile::new("", &aliases).is_err());
+        assert!(PlayerProfile::new("ringo", &["  ".to_string()]).is_err());
+    }
+}
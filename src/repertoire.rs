@@ -0,0 +1,478 @@
+use crate::board::Board;
+use crate::config::MY_USERNAMES;
+use crate::error::{Error, Result};
+use crate::filter::Side;
+use crate::models::{KifBody, KifHeader};
+use crate::{parser, profile, search, zobrist};
+use mysql::prelude::Queryable;
+use mysql::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// どのレパートリーか（プロファイル名を省略すると MY_USERNAMES のもの）
+#[derive(Deserialize, Clone, Debug)]
+pub struct RepertoireKey {
+    #[serde(default)]
+    pub profile: Option<String>,
+    pub side: Side, // レパートリーでの自分の手番
+}
+
+impl RepertoireKey {
+    // DB の profile 列の値（MY_USERNAMES のレパートリーは空文字）
+    fn profile_column(&self) -> &str {
+        self.profile.as_deref().unwrap_or("")
+    }
+
+    fn is_sente(&self) -> bool {
+        self.side == Side::Sente
+    }
+
+    // 名前を指定したときは、そのプロファイルが保存済みであること
+    fn check_profile(&self, conn: &mut PooledConn) -> Result<()> {
+        if let Some(name) = &self.profile
+            && profile::fetch(conn, name)?.is_none()
+        {
+            return Err(Error::not_found("プロファイルが見つかりません").with_text(name));
+        }
+        Ok(())
+    }
+}
+
+/// レパートリーの 1 手（自分の手は局面ごとに 1 つ、相手の手は想定している手を複数持てる）
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct RepertoireMove {
+    #[serde(serialize_with = "zobrist::serialize_hash")]
+    pub position_hash: u64,
+    #[serde(serialize_with = "zobrist::serialize_hash")]
+    pub next_hash: u64, // 指した後の局面
+    pub fugo: String,  // "同" は移動先に置き換えた符号
+    pub is_mine: bool, // 自分の手かどうか
+}
+
+/// KIF からレパートリーを読み込んだ結果
+#[derive(Serialize, Default, Debug)]
+pub struct RepertoireImport {
+    pub moves: usize,   // 保存した手の数
+    pub skipped: usize, // 同じ局面で別の自分の手が先に出てきたため読まなかった変化の数
+}
+
+/// 取り込んだ棋譜で、自分か相手がレパートリーから外れた最初の手
+#[derive(Serialize, Clone, Debug)]
+pub struct Deviation {
+    pub kif_id: u64,
+    pub url: String,
+    pub kif_url: String,
+    pub kif_filename: String,
+    pub profile: Option<String>, // None なら MY_USERNAMES のレパートリー
+    pub is_sente: bool,          // 自分の手番
+    pub te: i32,
+    pub fugo: String,          // 実際に指された手
+    pub expected: Vec<String>, // レパートリーにあった手
+    pub by_me: bool,           // 自分が外れたかどうか
+}
+
+// SQL の結果の 1 行（position_hash, next_hash, fugo, is_mine）
+type MoveRow = (u64, u64, String, bool);
+
+/// レパートリーの手を全て取得する
+pub fn list(conn: &mut PooledConn, key: &RepertoireKey) -> Result<Vec<RepertoireMove>> {
+    let moves = conn.exec_map(
+        "SELECT position_hash, next_hash, fugo, is_mine FROM repertoire_moves
+            WHERE profile = ? AND is_sente = ? ORDER BY position_hash, is_mine DESC, fugo",
+        (key.profile_column(), key.is_sente()),
+        |(position_hash, next_hash, fugo, is_mine): MoveRow| RepertoireMove {
+            position_hash,
+            next_hash,
+            fugo,
+            is_mine,
+        },
+    )?;
+    Ok(moves)
+}
+
+/// 局面からの 1 手をレパートリーに加える（自分の手なら、その局面の自分の手を置き換える）
+pub fn save_move(
+    conn: &mut PooledConn,
+    key: &RepertoireKey,
+    position_hash: u64,
+    next_hash: u64,
+    fugo: &str,
+) -> Result<RepertoireMove> {
+    if fugo.trim().is_empty() {
+        return Err(Error::query("指し手を指定してください"));
+    }
+    key.check_profile(conn)?;
+    let is_mine = sente_to_move(conn, position_hash)? == key.is_sente();
+    let m = RepertoireMove {
+        position_hash,
+        next_hash,
+        fugo: parser::normalize_fugo(fugo, None),
+        is_mine,
+    };
+
+    let mut tx = conn.start_transaction(TxOpts::default())?;
+    if is_mine {
+        tx.exec_drop(
+            "DELETE FROM repertoire_moves WHERE profile = ? AND is_sente = ? AND position_hash = ?",
+            (key.profile_column(), key.is_sente(), position_hash),
+        )?;
+    }
+    tx.exec_drop(
+        "INSERT INTO repertoire_moves (profile, is_sente, position_hash, next_hash, fugo, is_mine)
+            VALUES (?, ?, ?, ?, ?, ?)
+            ON DUPLICATE KEY UPDATE fugo = VALUES(fugo), is_mine = VALUES(is_mine)",
+        (
+            key.profile_column(),
+            key.is_sente(),
+            m.position_hash,
+            m.next_hash,
+            &m.fugo,
+            m.is_mine,
+        ),
+    )?;
+    tx.commit()?;
+    Ok(m)
+}
+
+/// レパートリーから 1 手を削除する（存在しなければ false）
+pub fn delete_move(
+    conn: &mut PooledConn,
+    key: &RepertoireKey,
+    position_hash: u64,
+    next_hash: u64,
+) -> Result<bool> {
+    conn.exec_drop(
+        "DELETE FROM repertoire_moves
+            WHERE profile = ? AND is_sente = ? AND position_hash = ? AND next_hash = ?",
+        (
+            key.profile_column(),
+            key.is_sente(),
+            position_hash,
+            next_hash,
+        ),
+    )?;
+    Ok(conn.affected_rows() > 0)
+}
+
+// 局面の手番（取り込み済みの棋譜に出てきた局面のみ）
+fn sente_to_move(conn: &mut PooledConn, position_hash: u64) -> Result<bool> {
+    if position_hash == zobrist::initial_hash() {
+        return Ok(true);
+    }
+    let te: Option<i32> = conn.exec_first(
+        "SELECT te FROM kif_bodies WHERE position_hash = ? LIMIT 1",
+        (position_hash,),
+    )?;
+    // 偶数手目の後は先手番
+    te.map(|te| te % 2 == 0).ok_or_else(|| {
+        Error::not_found("局面が見つかりません").with_text(position_hash.to_string())
+    })
+}
+
+/// 変化つきの KIF からレパートリーを作り直す（保存済みの手は全て置き換える）
+pub fn import_kif(
+    conn: &mut PooledConn,
+    key: &RepertoireKey,
+    contents: &str,
+) -> Result<RepertoireImport> {
+    key.check_profile(conn)?;
+    let lines: Vec<String> = contents.lines().map(|l| l.to_string()).collect();
+    let (moves, skipped) = moves_from_variations(&parser::parse_kif_variations(&lines), key)?;
+
+    let mut tx = conn.start_transaction(TxOpts::default())?;
+    tx.exec_drop(
+        "DELETE FROM repertoire_moves WHERE profile = ? AND is_sente = ?",
+        (key.profile_column(), key.is_sente()),
+    )?;
+    tx.exec_batch(
+        "INSERT INTO repertoire_moves (profile, is_sente, position_hash, next_hash, fugo, is_mine)
+            VALUES (?, ?, ?, ?, ?, ?)",
+        moves.iter().map(|m| {
+            (
+                key.profile_column(),
+                key.is_sente(),
+                m.position_hash,
+                m.next_hash,
+                &m.fugo,
+                m.is_mine,
+            )
+        }),
+    )?;
+    tx.commit()?;
+
+    Ok(RepertoireImport {
+        moves: moves.len(),
+        skipped,
+    })
+}
+
+// 本譜と各変化を初期局面から並べ直し、局面ごとの手にする
+//
+// 自分の手番で先に別の手が出てきた局面からは、その変化の残りを読まない（本譜・前の変化が優先）
+fn moves_from_variations(
+    variations: &[Vec<parser::Move>],
+    key: &RepertoireKey,
+) -> Result<(Vec<RepertoireMove>, usize)> {
+    let mut moves: Vec<RepertoireMove> = Vec::new();
+    let mut seen = HashSet::new();
+    let mut mine: HashMap<u64, u64> = HashMap::new();
+    let mut skipped = 0;
+
+    for variation in variations {
+        let mut board = Board::new();
+        let mut position_hash = zobrist::initial_hash();
+        let mut prev_fugo: Option<String> = None;
+
+        for m in variation {
+            let fugo = parser::normalize_fugo(&m.fugo, prev_fugo.as_deref());
+            // 投了などの終局
+            if parser::parse_fugo(&fugo).is_none() {
+                break;
+            }
+            let is_sente_move = m.te % 2 == 1;
+            board
+                .apply_move(&fugo, is_sente_move)
+                .map_err(|e| e.with_te(m.te).with_text(&m.fugo))?;
+            let next_hash = zobrist::hash(&board, !is_sente_move);
+
+            let is_mine = is_sente_move == key.is_sente();
+            if is_mine && *mine.entry(position_hash).or_insert(next_hash) != next_hash {
+                skipped += 1;
+                break;
+            }
+            if seen.insert((position_hash, next_hash)) {
+                moves.push(RepertoireMove {
+                    position_hash,
+                    next_hash,
+                    fugo: fugo.clone(),
+                    is_mine,
+                });
+            }
+            position_hash = next_hash;
+            prev_fugo = Some(fugo);
+        }
+    }
+    Ok((moves, skipped))
+}
+
+/// レパートリーを変化つきの KIF にする（初期局面からつながっている手のみ）
+pub fn to_kif(conn: &mut PooledConn, key: &RepertoireKey) -> Result<String> {
+    let moves = list(conn, key)?;
+    let me = key.profile.as_deref().unwrap_or("自分");
+    let (sente, gote) = if key.is_sente() {
+        (me, "相手")
+    } else {
+        ("相手", me)
+    };
+
+    let mut lines = vec![
+        "手合割：平手".to_string(),
+        format!("先手：{}", sente),
+        format!("後手：{}", gote),
+        "手数----指手---------消費時間--".to_string(),
+    ];
+    write_variations(&mut lines, &moves);
+    Ok(lines.join("\n") + "\n")
+}
+
+// 本譜の後に変化を、分岐の深いものから順に書く
+//
+// KIF の「変化：N手」は直前に書いた手順のうち N 手目を含む最後のものから分岐するので、
+// 深い分岐から書けば浅い分岐が正しい手順につながる
+fn write_variations(lines: &mut Vec<String>, moves: &[RepertoireMove]) {
+    let mut children: HashMap<u64, Vec<&RepertoireMove>> = HashMap::new();
+    for m in moves {
+        children.entry(m.position_hash).or_default().push(m);
+    }
+    // 自分の手（局面ごとに 1 つ）を本譜にし、相手の手は符号の順
+    for kids in children.values_mut() {
+        kids.sort_by(|a, b| b.is_mine.cmp(&a.is_mine).then(a.fugo.cmp(&b.fugo)));
+    }
+
+    let mut visited = HashSet::new();
+    write_line(lines, &children, zobrist::initial_hash(), 1, &mut visited);
+}
+
+fn write_line(
+    lines: &mut Vec<String>,
+    children: &HashMap<u64, Vec<&RepertoireMove>>,
+    mut position_hash: u64,
+    mut te: usize,
+    visited: &mut HashSet<u64>,
+) {
+    let mut branches: Vec<(usize, &[&RepertoireMove])> = Vec::new();
+    // 手順違いで同じ局面に合流したら、その先は最初に書いた方にだけ書く
+    while visited.insert(position_hash) {
+        let Some(kids) = children.get(&position_hash) else {
+            break;
+        };
+        lines.push(format!("{:>4} {}", te, kids[0].fugo));
+        if kids.len() > 1 {
+            branches.push((te, &kids[1..]));
+        }
+        position_hash = kids[0].next_hash;
+        te += 1;
+    }
+
+    for (te, alternatives) in branches.into_iter().rev() {
+        for m in alternatives {
+            lines.push(String::new());
+            lines.push(format!("変化：{}手", te));
+            lines.push(format!("{:>4} {}", te, m.fugo));
+            write_line(lines, children, m.next_hash, te + 1, visited);
+        }
+    }
+}
+
+/// 取り込んだ棋譜を、対局者のレパートリーと照らし合わせる
+///
+/// MY_USERNAMES かプロファイルのアカウントが指した棋譜について、レパートリーの局面で
+/// 自分か相手がレパートリーにない手を指した最初の手を返す（レパートリーの手順が尽きたら何も返さない）
+pub fn check_game(
+    conn: &mut PooledConn,
+    kif_id: u64,
+    header: &KifHeader,
+    bodies: &[KifBody],
+) -> Result<Vec<Deviation>> {
+    let mut keys: Vec<RepertoireKey> = Vec::new();
+    let profiles = profile::list(conn)?;
+    for (player, side) in [
+        (&header.sente_player, Side::Sente),
+        (&header.gote_player, Side::Gote),
+    ] {
+        if MY_USERNAMES.contains(player) {
+            keys.push(RepertoireKey {
+                profile: None,
+                side,
+            });
+        }
+        for p in profiles.iter().filter(|p| p.aliases.contains(player)) {
+            keys.push(RepertoireKey {
+                profile: Some(p.name.clone()),
+                side,
+            });
+        }
+    }
+
+    let mut deviations = Vec::new();
+    for key in keys {
+        let moves = list(conn, &key)?;
+        if let Some((te, fugo, expected)) = find_deviation(&moves, bodies) {
+            deviations.push(Deviation {
+                kif_id,
+                url: search::game_url(kif_id),
+                kif_url: search::kif_url(kif_id),
+                kif_filename: header.kif_filename.clone(),
+                profile: key.profile.clone(),
+                is_sente: key.is_sente(),
+                te,
+                fugo,
+                expected,
+                by_me: (te % 2 == 1) == key.is_sente(),
+            });
+        }
+    }
+    Ok(deviations)
+}
+
+// レパートリーから外れた最初の手（手数, 指された手, レパートリーにあった手）
+fn find_deviation(
+    moves: &[RepertoireMove],
+    bodies: &[KifBody],
+) -> Option<(i32, String, Vec<String>)> {
+    let mut children: HashMap<u64, Vec<&RepertoireMove>> = HashMap::new();
+    for m in moves {
+        children.entry(m.position_hash).or_default().push(m);
+    }
+
+    let mut position_hash = zobrist::initial_hash();
+    for body in bodies {
+        let kids = children.get(&position_hash)?;
+        if !kids.iter().any(|m| m.next_hash == body.position_hash) {
+            let expected = kids.iter().map(|m| m.fugo.clone()).collect();
+            return Some((body.te, body.fugo.clone(), expected));
+        }
+        position_hash = body.position_hash;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPERTOIRE_KIF: &str = "手合割：平手
+手数----指手---------消費時間--
+   1 ７六歩(77)
+   2 ３四歩(33)
+   3 ２六歩(27)
+   4 ８四歩(83)
+
+変化：4手
+   4 ４四歩(43)
+
+変化：2手
+   2 ８四歩(83)
+   3 ６八飛(28)
+";
+
+    fn key() -> RepertoireKey {
+        RepertoireKey {
+            profile: None,
+            side: Side::Sente,
+        }
+    }
+
+    fn variations(kif: &str) -> Vec<Vec<parser::Move>> {
+        let lines: Vec<String> = kif.lines().map(|l| l.to_string()).collect();
+        parser::parse_kif_variations(&lines)
+    }
+
+    #[test]
+    fn test_variations_to_moves_and_back() {
+        let (moves, skipped) = moves_from_variations(&variations(REPERTOIRE_KIF), &key()).unwrap();
+        assert_eq!(skipped, 0);
+        // 7六歩・3四歩・2六歩・8四歩・4四歩・8四歩・6八飛
+        assert_eq!(moves.len(), 7);
+        assert_eq!(moves.iter().filter(|m| m.is_mine).count(), 3);
+
+        let mut lines = Vec::new();
+        write_variations(&mut lines, &moves);
+        let kif = lines.join("\n");
+        let (again, _) = moves_from_variations(&variations(&kif), &key()).unwrap();
+        assert_eq!(again.len(), moves.len());
+        assert!(again.iter().all(|m| moves.contains(m)));
+    }
+
+    #[test]
+    fn test_conflicting_own_move_is_skipped() {
+        let kif = format!("{}\n変化：3手\n   3 ７八飛(28)\n", REPERTOIRE_KIF);
+        let (_, skipped) = moves_from_variations(&variations(&kif), &key()).unwrap();
+        assert_eq!(skipped, 1);
+    }
+
+    #[test]
+    fn test_find_deviation() {
+        let (moves, _) = moves_from_variations(&variations(REPERTOIRE_KIF), &key()).unwrap();
+        let game = ["７六歩(77)", "３四歩(33)", "２六歩(27)", "３二飛(82)"]
+            .iter()
+            .enumerate()
+            .map(|(i, fugo)| parser::Move {
+                te: i + 1,
+                fugo: fugo.to_string(),
+                eval: None,
+                line: None,
+            })
+            .collect::<Vec<_>>();
+        let bodies = crate::import::replay_moves(&game, "test.kif").unwrap();
+
+        let (te, fugo, expected) = find_deviation(&moves, &bodies).unwrap();
+        assert_eq!((te, fugo.as_str()), (4, "３二飛(82)"));
+        assert_eq!(expected.len(), 2);
+        // 相手が外れた
+        assert_ne!(te % 2 == 1, key().is_sente());
+
+        // レパートリーどおりに進んで手順が尽きたら外れていない
+        assert!(find_deviation(&moves, &bodies[..3]).is_none());
+    }
+}
@@ -0,0 +1,335 @@
+use crate::config::{COLLECTED_DIR, IMPORTED_DIR};
+use crate::error::{Error, ErrorCategory, Result};
+use crate::search::{self, KifLink};
+use mysql::prelude::Queryable;
+use mysql::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{Cursor, Write};
+use std::path::PathBuf;
+use zip::write::SimpleFileOptions;
+
+// 検索結果を残しておく日数（これより古いものは次の検索時に削除する）
+const KEEP_DAYS: u32 = 7;
+
+/// 保存済みの検索結果
+pub struct ResultSet {
+    pub id: u64,
+    pub created_at: String,
+    pub links: Vec<KifLink>,
+}
+
+/// 並べ替えの基準
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    #[default]
+    StartedAt, // 対局開始日時
+    Te,       // 最初に一致した手数
+    Length,   // 総手数
+    Result,   // 勝敗（勝ち → 引き分け → 負け）
+    Opponent, // 対戦相手の名前
+}
+
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// 検索結果のどの範囲をどの順番で返すか
+#[derive(Deserialize, Clone, Copy, Default, Debug)]
+#[serde(default)]
+pub struct Page {
+    pub offset: usize,
+    pub limit: Option<usize>, // 省略時は全件
+    pub sort: SortKey,
+    pub order: SortOrder,
+}
+
+/// 勝敗の集計
+#[derive(Serialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct Record {
+    pub games: u64,
+    pub wins: u64,
+    pub losses: u64,
+    pub draws: u64,
+}
+
+impl Record {
+    pub fn add(&mut self, is_win: bool, is_draw: bool) {
+        self.games += 1;
+        if is_draw {
+            self.draws += 1;
+        } else if is_win {
+            self.wins += 1;
+        } else {
+            self.losses += 1;
+        }
+    }
+
+    /// 引き分けを除いた勝率（勝ち負けがなければ 0）
+    pub fn win_rate(&self) -> f64 {
+        let decided = self.wins + self.losses;
+        if decided == 0 {
+            0.0
+        } else {
+            self.wins as f64 / decided as f64
+        }
+    }
+}
+
+/// 検索結果全体（ページに分ける前）の件数と勝敗
+#[derive(Serialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct Summary {
+    pub all: Record,
+    pub sente: Record,
+    pub gote: Record,
+}
+
+impl Summary {
+    pub fn of(links: &[KifLink]) -> Summary {
+        let mut summary = Summary::default();
+        for link in links {
+            summary.all.add(link.is_win, link.is_draw);
+            let side = if link.is_sente {
+                &mut summary.sente
+            } else {
+                &mut summary.gote
+            };
+            side.add(link.is_win, link.is_draw);
+        }
+        summary
+    }
+}
+
+/// 検索結果の 1 ページ分
+#[derive(Serialize)]
+pub struct ResultPage {
+    pub result_id: u64,
+    pub total: usize,
+    pub offset: usize,
+    pub summary: Summary,
+    pub links: Vec<KifLink>,
+}
+
+impl ResultSet {
+    /// page の順番に並べ替えて範囲を切り出し、その範囲の棋譜にだけ一致した局面の詳細を付ける
+    pub fn into_page(mut self, conn: &mut PooledConn, page: &Page) -> Result<ResultPage> {
+        let total = self.links.len();
+        let summary = Summary::of(&self.links);
+
+        sort_links(&mut self.links, page.sort, page.order);
+        let mut links: Vec<KifLink> = self
+            .links
+            .into_iter()
+            .skip(page.offset)
+            .take(page.limit.unwrap_or(usize::MAX))
+            .collect();
+        for link in &mut links {
+            search::add_position_details(conn, link)?;
+        }
+
+        Ok(ResultPage {
+            result_id: self.id,
+            total,
+            offset: page.offset,
+            summary,
+            links,
+        })
+    }
+}
+
+/// 検索結果を並べ替える（同じ値の間は対局開始日時の新しい順）
+pub fn sort_links(links: &mut [KifLink], key: SortKey, order: SortOrder) {
+    let result_rank = |l: &KifLink| match (l.is_win, l.is_draw) {
+        (_, true) => 1,
+        (true, _) => 2,
+        _ => 0,
+    };
+    links.sort_by(|a, b| {
+        let ordering = match key {
+            SortKey::StartedAt => a.started_at.cmp(&b.started_at),
+            SortKey::Te => a.te.cmp(&b.te),
+            SortKey::Length => a.length.cmp(&b.length),
+            SortKey::Result => result_rank(a).cmp(&result_rank(b)),
+            SortKey::Opponent => a.opponent.cmp(&b.opponent),
+        };
+        let ordering = match order {
+            SortOrder::Asc => ordering,
+            SortOrder::Desc => ordering.reverse(),
+        };
+        ordering.then_with(|| b.started_at.cmp(&a.started_at))
+    });
+}
+
+/// 検索結果を書き出した結果
+#[derive(Serialize, Default)]
+pub struct CollectReport {
+    pub dir: String,
+    pub copied: Vec<String>,
+    pub missing: Vec<String>, // IMPORTED_DIR に見つからなかった棋譜
+}
+
+/// 検索結果を保存して ID を返す（古い検索結果はここで削除する）
+pub fn save(conn: &mut PooledConn, request: &str, links: &[KifLink]) -> Result<u64> {
+    conn.exec_drop(
+        "DELETE FROM search_result_sets WHERE created_at < NOW() - INTERVAL ? DAY",
+        (KEEP_DAYS,),
+    )?;
+
+    let links = serde_json::to_string(links).map_err(|e| {
+        Error::new(
+            ErrorCategory::Db,
+            format!("検索結果を保存できません: {}", e),
+        )
+    })?;
+    conn.exec_drop(
+        "INSERT INTO search_result_sets (created_at, request, links) VALUES (NOW(), ?, ?)",
+        (request, links),
+    )?;
+    Ok(conn.last_insert_id())
+}
+
+/// 保存済みの検索結果を取得する
+pub fn fetch(conn: &mut PooledConn, id: u64) -> Result<ResultSet> {
+    let row: Option<(String, String)> = conn.exec_first(
+        "SELECT DATE_FORMAT(created_at, '%Y-%m-%d %H:%i:%s'), links
+            FROM search_result_sets WHERE id = ?",
+        (id,),
+    )?;
+    let (created_at, links) =
+        row.ok_or_else(|| Error::not_found("検索結果が見つかりません").with_text(id.to_string()))?;
+
+    let links = serde_json::from_str(&links).map_err(|e| {
+        Error::new(
+            ErrorCategory::Db,
+            format!("検索結果を読み込めません: {}", e),
+        )
+        .with_text(id.to_string())
+    })?;
+    Ok(ResultSet {
+        id,
+        created_at,
+        links,
+    })
+}
+
+/// 検索結果の棋譜を COLLECTED_DIR/{id} にコピーする（他の検索結果のディレクトリには触らない）
+pub fn collect_to_dir(result: &ResultSet) -> Result<CollectReport> {
+    let dir = COLLECTED_DIR.join(result.id.to_string());
+    fs::create_dir_all(&dir).map_err(|e| Error::from(e).with_text(dir.display().to_string()))?;
+
+    let mut report = CollectReport {
+        dir: dir.display().to_string(),
+        ..Default::default()
+    };
+    for filename in unique_filenames(result) {
+        let src = IMPORTED_DIR.join(filename);
+        if !src.exists() {
+            report.missing.push(filename.to_string());
+            continue;
+        }
+        fs::copy(&src, dir.join(filename)).map_err(|e| Error::from(e).with_file(filename))?;
+        report.copied.push(filename.to_string());
+    }
+    Ok(report)
+}
+
+/// 検索結果の棋譜をまとめた zip を作る（IMPORTED_DIR に見つからない棋譜は含めない）
+pub fn to_zip(result: &ResultSet) -> Result<Vec<u8>> {
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default();
+
+    for filename in unique_filenames(result) {
+        let path: PathBuf = IMPORTED_DIR.join(filename);
+        let Ok(contents) = fs::read(&path) else {
+            continue;
+        };
+        zip.start_file(filename, options)?;
+        zip.write_all(&contents)
+            .map_err(|e| Error::from(e).with_file(filename))?;
+    }
+
+    Ok(zip.finish()?.into_inner())
+}
+
+// 先後の両方の視点でヒットした棋譜は 1 回だけ出力する
+fn unique_filenames(result: &ResultSet) -> Vec<&str> {
+    let mut filenames: Vec<&str> = Vec::new();
+    for link in &result.links {
+        if !filenames.contains(&link.kif_filename.as_str()) {
+            filenames.push(&link.kif_filename);
+        }
+    }
+    filenames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::Transform;
+
+    fn link(filename: &str, is_sente: bool) -> KifLink {
+        KifLink {
+            kif_id: 1,
+            url: "/api/games/1".to_string(),
+            kif_url: "/api/games/1/kif".to_string(),
+            kif_filename: filename.to_string(),
+            link: format!("imported/{}", filename),
+            te: 30,
+            tes: vec![30, 31],
+            sfen: None,
+            fugo: "７六歩(77)".to_string(),
+            next_moves: Vec::new(),
+            player: "Ringosky".to_string(),
+            opponent: "zzz208".to_string(),
+            is_win: is_sente,
+            is_draw: false,
+            started_at: Some("2025-08-11 06:40:41".to_string()),
+            length: 100,
+            is_sente,
+            transform: Transform::AsIs,
+        }
+    }
+
+    #[test]
+    fn test_stored_links() {
+        // 保存した JSON から読み直せること
+        let json = serde_json::to_string(&[link("a.kif", true), link("a.kif", false)]).unwrap();
+        let result = ResultSet {
+            id: 1,
+            created_at: String::new(),
+            links: serde_json::from_str(&json).unwrap(),
+        };
+        assert_eq!(result.links[1].transform, Transform::AsIs);
+        assert_eq!(unique_filenames(&result), vec!["a.kif"]);
+    }
+
+    #[test]
+    fn test_sort_and_summary() {
+        let mut links = vec![
+            link("a.kif", true),
+            link("b.kif", false),
+            link("c.kif", true),
+        ];
+        links[1].te = 10;
+        links[2].is_draw = true;
+        links[2].is_win = false;
+
+        let summary = Summary::of(&links);
+        assert_eq!(
+            (summary.all.wins, summary.all.losses, summary.all.draws),
+            (1, 1, 1)
+        );
+        assert_eq!(summary.sente.games, 2);
+
+        sort_links(&mut links, SortKey::Te, SortOrder::Asc);
+        assert_eq!(links[0].kif_filename, "b.kif");
+        sort_links(&mut links, SortKey::Result, SortOrder::Desc);
+        let order: Vec<&str> = links.iter().map(|l| l.kif_filename.as_str()).collect();
+        assert_eq!(order, vec!["a.kif", "c.kif", "b.kif"]);
+    }
+}
@@ -1,194 +1,330 @@
-use crate::config::{COLLECTED_DIR, IMPORTED_DIR, MY_USERNAMES};
-use crate::db;
-use axum::http::StatusCode;
-use mysql::prelude::Queryable;
-use serde::Deserialize;
-use serde::Serialize;
-use std::fs;
-
-// use axum::{extract::Json, response::IntoResponse};
-use axum::Json;
-use mysql::*;
+use crate::config::IMPORTED_DIR;
+use crate::error::{Error, ErrorCategory};
+use crate::explorer::{self, Explorer, ExplorerRequest};
+use crate::import::ImportReport;
+use crate::models::KifHeader;
+use crate::parser::{self, Move};
+use crate::profile::{self, PlayerProfile};
+use crate::repertoire::{self, RepertoireImport, RepertoireKey, RepertoireMove};
+use crate::result_set::{self, CollectReport, Page, ResultPage, ResultSet};
+use crate::search::{self, PositionHit, PositionQuery, SearchRequest};
+use crate::stats::{self, Dashboard, SearchStats};
+use crate::{db, export, import, zobrist};
+use axum::extract::{Path, Query};
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post, put};
+use axum::{Json, Router};
+use mysql::PooledConn;
+use serde::{Deserialize, Serialize};
+use tower_http::cors::CorsLayer;
 
-#[derive(Deserialize)]
-pub struct SearchCondition {
-    pub c: String,
-    pub sfen: String,
+/// HTTP API のルーティング
+pub fn router() -> Router {
+    Router::new()
+        .route("/api/admin/import", post(import_all_handler))
+        .route("/api/search", post(search_games))
+        .route("/api/search/stats", post(search_stats))
+        .route("/api/search/position", post(search_position))
+        .route("/api/explorer", post(explore_position))
+        .route("/api/games/:id", get(get_game))
+        .route("/api/games/:id/kif", get(get_game_kif))
+        .route("/api/results/:id", get(get_result_set))
+        .route("/api/results/:id/stats", get(get_result_stats))
+        .route("/api/results/:id/collect", post(collect_result_set))
+        .route("/api/results/:id/zip", get(zip_result_set))
+        .route("/api/repertoire", get(list_repertoire))
+        .route(
+            "/api/repertoire/moves",
+            post(save_repertoire_move).delete(delete_repertoire_move),
+        )
+        .route(
+            "/api/repertoire/kif",
+            get(export_repertoire).put(import_repertoire),
+        )
+        .route("/api/stats", get(player_stats))
+        .route("/api/profiles", get(list_profiles))
+        .route(
+            "/api/profiles/:name",
+            put(save_profile).get(get_profile).delete(delete_profile),
+        )
+        .layer(CorsLayer::permissive())
 }
 
-#[derive(Serialize)]
-pub struct KifLink {
-    pub link: String,
-    pub te: i32,
-    pub is_win: bool, // 自分が勝ったかどうか
-    pub started_at: Option<String>,
-    pub is_sente: bool,
-}
-
-pub async fn search_games(
-    Json(conditions): Json<Vec<SearchCondition>>,
-) -> Result<Json<Vec<KifLink>>, (StatusCode, String)> {
-    // ヒットした棋譜をコピーするディレクトリを整備
-    if COLLECTED_DIR.exists() {
-        fs::remove_dir_all(&*COLLECTED_DIR).map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("COLLECTED_DIR削除失敗: {}", e),
-            )
-        })?;
+/// エラーは分類・場所つきの JSON で返す
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status = match self.category {
+            ErrorCategory::Encoding
+            | ErrorCategory::Header
+            | ErrorCategory::MoveSyntax
+            | ErrorCategory::IllegalMove => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCategory::Query => StatusCode::BAD_REQUEST,
+            ErrorCategory::NotFound => StatusCode::NOT_FOUND,
+            ErrorCategory::Db | ErrorCategory::Io | ErrorCategory::Config => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        };
+        eprintln!("エラー: {}", self);
+        (status, Json(self)).into_response()
     }
-    fs::create_dir_all(&*COLLECTED_DIR).map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("COLLECTED_DIR作成失敗: {}", e),
-        )
-    })?;
+}
 
-    let mut conn =
-        db::get_conn().map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+async fn import_all_handler() -> Result<Json<ImportReport>, Error> {
+    let report = import::import_all_kif_files()?;
+    Ok(Json(report))
+}
 
-    let mut links: Vec<KifLink> = Vec::new(); // 先手＋後手それぞれの検索結果を格納
-    let mut where_clauses = Vec::new();
-    let mut params: Vec<Value> = Vec::new();
+/// 検索して結果を保存し、指定された範囲を返す（result_id で後から同じ結果の別の範囲を取得できる）
+pub async fn search_games(Json(body): Json<serde_json::Value>) -> Result<Json<ResultPage>, Error> {
+    let mut conn = db::get_conn()?;
+    let (result, page) = search_and_save(&mut conn, body)?;
+    Ok(Json(result.into_page(&mut conn, &page)?))
+}
 
-    // 検索条件の数が0の場合は、全対局を取得する
-    println!("検索条件: {}件", conditions.len());
+/// 検索して結果を保存し、場所・持ち時間・月・次の一手ごとの勝敗を返す
+async fn search_stats(Json(body): Json<serde_json::Value>) -> Result<Json<SearchStats>, Error> {
+    let mut conn = db::get_conn()?;
+    let (result, _) = search_and_save(&mut conn, body)?;
+    Ok(Json(stats::for_result_set(&mut conn, &result)?))
+}
 
-    // 先手の場合の処理
-    // 与えられた条件を一旦、where_clausesに格納
-    for cond in &conditions {
-        let col_name = format!("b.c{}", cond.c); // テーブルエイリアスbを付ける
-        where_clauses.push(format!("{} = ? COLLATE utf8mb4_bin", col_name));
-        params.push(cond.sfen.clone().into());
-    }
+fn search_and_save(
+    conn: &mut PooledConn,
+    body: serde_json::Value,
+) -> Result<(ResultSet, Page), Error> {
+    let request: SearchRequest = serde_json::from_value(body.clone())
+        .map_err(|e| Error::query(format!("検索条件を読み取れません: {}", e)))?;
 
-    // ユーザー名の条件も一旦、where_clausesに格納
-    where_clauses.push(format!(
-        "h.sente_player IN ({})",
-        MY_USERNAMES
-            .iter()
-            .map(|_| "?")
-            .collect::<Vec<_>>()
-            .join(", ")
-    ));
-    params.extend(MY_USERNAMES.iter().cloned().map(Value::from));
-
-    let sql = format!(
-        r#"
-SELECT h.kif_filename, MIN(b.te) as min_te, h.is_sente_win as is_win, DATE_FORMAT(h.started_at, '%Y-%m-%d %H:%i:%s') AS started_at, 1 as sengo 
-FROM kif_bodies b
-LEFT JOIN kif_headers h ON b.kif_id = h.id
-WHERE {}
-GROUP BY b.kif_id
-    "#,
-        where_clauses.join(" AND ")
-    );
-
-    println!("SQL: {}", sql);
-    println!("PARAMS: {:?}", params);
-
-    let rows: Vec<(String, i32, bool, Option<String>, bool)> = conn
-        // let rows: Vec<(String, i32)> = conn
-        .exec(sql, params)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-
-    // 後手の場合の処理
-    let mut gote_where_clauses = Vec::new();
-    let mut gote_params: Vec<Value> = Vec::new();
-
-    for cond in &conditions {
-        // cの反転: 82 - c（数値に変換）
-        let c_val: i32 = cond.c.parse().unwrap_or(0);
-        let reversed_c = 82 - c_val;
-
-        // sfenの大文字小文字反転
-        let reversed_sfen = cond
-            .sfen
-            .chars()
-            .map(|ch| {
-                if ch.is_ascii_lowercase() {
-                    ch.to_ascii_uppercase()
-                } else {
-                    ch.to_ascii_lowercase()
-                }
-            })
-            .collect::<String>();
-
-        let col_name = format!("b.c{}", reversed_c);
-        // gote_where_clauses.push(format!("{} = ?", col_name));
-        gote_where_clauses.push(format!("{} = ? COLLATE utf8mb4_bin", col_name));
-        gote_params.push(reversed_sfen.into());
-    }
+    let (query, options, page) = request.into_parts();
+    let links = search::find_games(conn, &query, &options)?;
+    let id = result_set::save(conn, &body.to_string(), &links)?;
+
+    let result = ResultSet {
+        id,
+        created_at: String::new(),
+        links,
+    };
+    Ok((result, page))
+}
+
+/// /api/games/{id} のレスポンス
+#[derive(Serialize)]
+pub struct GameDetail {
+    pub id: u64,
+    #[serde(flatten)]
+    pub header: KifHeader,
+    pub moves: Vec<Move>,
+    pub kif_url: String,
+}
+
+/// 取り込み済みの棋譜の対局情報と指し手
+async fn get_game(Path(id): Path<u64>) -> Result<Json<GameDetail>, Error> {
+    let mut conn = db::get_conn()?;
+    let header = fetch_header(&mut conn, id)?;
+    let moves = db::fetch_kif_moves(&mut conn, id)?;
+    Ok(Json(GameDetail {
+        id,
+        header,
+        moves,
+        kif_url: search::kif_url(id),
+    }))
+}
+
+#[derive(Deserialize)]
+struct KifParams {
+    #[serde(default)]
+    regenerate: bool, // 元のファイルではなく DB の指し手から作り直す
+}
+
+/// 取り込み済みの棋譜の KIF（UTF-8）
+///
+/// 元のファイルが残っていればコメントなども含めてそのまま、なければ DB の指し手から作り直して返す
+async fn get_game_kif(
+    Path(id): Path<u64>,
+    Query(params): Query<KifParams>,
+) -> Result<Response, Error> {
+    let mut conn = db::get_conn()?;
+    let header = fetch_header(&mut conn, id)?;
+
+    let path = IMPORTED_DIR.join(&header.kif_filename);
+    let kif = if !params.regenerate && path.exists() {
+        let (contents, _) = parser::read_kif_file(&path.to_string_lossy())?;
+        export::to_utf8_kif(&contents)
+    } else {
+        let moves = db::fetch_kif_moves(&mut conn, id)?;
+        export::to_kif(&header, &moves)
+    };
+
+    Ok(([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], kif).into_response())
+}
+
+fn fetch_header(conn: &mut PooledConn, id: u64) -> Result<KifHeader, Error> {
+    db::fetch_kif_header(conn, id)?
+        .ok_or_else(|| Error::not_found("棋譜が見つかりません").with_text(id.to_string()))
+}
+
+/// 保存済みの検索結果の指定された範囲
+async fn get_result_set(
+    Path(id): Path<u64>,
+    Query(page): Query<Page>,
+) -> Result<Json<ResultPage>, Error> {
+    let mut conn = db::get_conn()?;
+    let result = result_set::fetch(&mut conn, id)?;
+    Ok(Json(result.into_page(&mut conn, &page)?))
+}
+
+/// 保存済みの検索結果の場所・持ち時間・月・次の一手ごとの勝敗
+async fn get_result_stats(Path(id): Path<u64>) -> Result<Json<SearchStats>, Error> {
+    let mut conn = db::get_conn()?;
+    let result = result_set::fetch(&mut conn, id)?;
+    Ok(Json(stats::for_result_set(&mut conn, &result)?))
+}
+
+/// 検索結果の棋譜を COLLECTED_DIR/{id} にコピーする
+async fn collect_result_set(Path(id): Path<u64>) -> Result<Json<CollectReport>, Error> {
+    let mut conn = db::get_conn()?;
+    let result = result_set::fetch(&mut conn, id)?;
+    Ok(Json(result_set::collect_to_dir(&result)?))
+}
 
-    gote_where_clauses.push(format!(
-        "h.gote_player IN ({})",
-        MY_USERNAMES
-            .iter()
-            .map(|_| "?")
-            .collect::<Vec<_>>()
-            .join(", ")
-    ));
-    gote_params.extend(MY_USERNAMES.iter().cloned().map(Value::from));
-
-    // let gote_where_sql = if gote_where_clauses.is_empty() {
-    //     "1".to_string()
-    // } else {
-    //     gote_where_clauses.join(" AND ")
-    // };
-
-    let gote_sql = format!(
-        r#"
-SELECT h.kif_filename, MIN(b.te) as min_te, NOT h.is_sente_win as is_win, DATE_FORMAT(h.started_at, '%Y-%m-%d %H:%i:%s') AS started_at, 0 as sengo 
-FROM kif_bodies b
-LEFT JOIN kif_headers h ON b.kif_id = h.id
-WHERE {}
-GROUP BY b.kif_id
-"#,
-        gote_where_clauses.join(" AND ")
-    );
-    println!("SQL: {}", gote_sql);
-    println!("PARAMS: {:?}", gote_params);
-
-    let gote_rows: Vec<(String, i32, bool, Option<String>, bool)> = conn
-        .exec(gote_sql, gote_params)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-
-    // 検索件数をprint
-    println!(
-        "先手検索結果: {}件, 後手検索結果: {}件",
-        rows.len(),
-        gote_rows.len()
-    );
-
-    // 先手 + 後手の一致ファイルをコピー
-    for (filename, te, is_win, started_at, is_sente) in rows.into_iter().chain(gote_rows) {
-        let src = IMPORTED_DIR.join(&filename);
-        let dst = COLLECTED_DIR.join(&filename);
-
-        links.push(KifLink {
-            link: format!("{}/{}", IMPORTED_DIR.display(), filename),
-            te,
-            is_win: is_win,
-            started_at,
-            is_sente,
-        });
-
-        // ファイルが存在しない場合はスキップ
-        if !src.exists() {
-            println!("ファイルが存在しません: {}", src.display());
-            continue;
-        }
-
-        fs::copy(&src, &dst).map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("コピー失敗: {} → {}: {}", src.display(), dst.display(), e),
-            )
-        })?;
+/// 検索結果の棋譜をまとめた zip をダウンロードする
+async fn zip_result_set(Path(id): Path<u64>) -> Result<Response, Error> {
+    let mut conn = db::get_conn()?;
+    let result = result_set::fetch(&mut conn, id)?;
+    let bytes = result_set::to_zip(&result)?;
+
+    let disposition = format!("attachment; filename=\"kifquery-{}.zip\"", id);
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/zip".to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        bytes,
+    )
+        .into_response())
+}
+
+/// 盤面・持ち駒・手番が完全に一致する局面を探す
+async fn search_position(
+    Json(query): Json<PositionQuery>,
+) -> Result<Json<Vec<PositionHit>>, Error> {
+    let mut conn = db::get_conn()?;
+    let hits = search::find_position(&mut conn, &query)?;
+    Ok(Json(hits))
+}
+
+/// 局面から指された手ごとの対局数・勝率・評価値と、指した後の局面
+async fn explore_position(Json(request): Json<ExplorerRequest>) -> Result<Json<Explorer>, Error> {
+    let mut conn = db::get_conn()?;
+    Ok(Json(explorer::explore(&mut conn, &request)?))
+}
+
+/// レパートリーの手の一覧
+async fn list_repertoire(
+    Query(key): Query<RepertoireKey>,
+) -> Result<Json<Vec<RepertoireMove>>, Error> {
+    let mut conn = db::get_conn()?;
+    Ok(Json(repertoire::list(&mut conn, &key)?))
+}
+
+/// レパートリーに加える・削除する手（局面ハッシュは /api/explorer の hash）
+#[derive(Deserialize)]
+struct RepertoireMoveRequest {
+    #[serde(flatten)]
+    key: RepertoireKey,
+    hash: String,
+    next_hash: String,
+    #[serde(default)]
+    fugo: String,
+}
+
+async fn save_repertoire_move(
+    Json(request): Json<RepertoireMoveRequest>,
+) -> Result<Json<RepertoireMove>, Error> {
+    let hash = zobrist::parse_hash(&request.hash)?;
+    let next_hash = zobrist::parse_hash(&request.next_hash)?;
+    let mut conn = db::get_conn()?;
+    let m = repertoire::save_move(&mut conn, &request.key, hash, next_hash, &request.fugo)?;
+    Ok(Json(m))
+}
+
+async fn delete_repertoire_move(
+    Json(request): Json<RepertoireMoveRequest>,
+) -> Result<StatusCode, Error> {
+    let hash = zobrist::parse_hash(&request.hash)?;
+    let next_hash = zobrist::parse_hash(&request.next_hash)?;
+    let mut conn = db::get_conn()?;
+    if !repertoire::delete_move(&mut conn, &request.key, hash, next_hash)? {
+        return Err(Error::not_found("レパートリーに手が見つかりません").with_text(request.hash));
     }
+    Ok(StatusCode::NO_CONTENT)
+}
 
-    // started_at の降順でソート
-    links.sort_by(|a, b| b.started_at.cmp(&a.started_at));
+/// レパートリーを変化つきの KIF（UTF-8）で返す
+async fn export_repertoire(Query(key): Query<RepertoireKey>) -> Result<Response, Error> {
+    let mut conn = db::get_conn()?;
+    let kif = repertoire::to_kif(&mut conn, &key)?;
+    Ok(([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], kif).into_response())
+}
 
-    Ok(Json(links))
+/// 変化つきの KIF（UTF-8）からレパートリーを作り直す
+async fn import_repertoire(
+    Query(key): Query<RepertoireKey>,
+    body: String,
+) -> Result<Json<RepertoireImport>, Error> {
+    let mut conn = db::get_conn()?;
+    Ok(Json(repertoire::import_kif(&mut conn, &key, &body)?))
+}
+
+/// 成績を集計する対局者（どちらも省略すれば MY_USERNAMES）
+#[derive(Deserialize)]
+struct StatsQuery {
+    profile: Option<String>, // プロファイル名
+    player: Option<String>,  // アカウント名
+}
+
+/// 対局者の成績（期間・場所・持ち時間・時間帯・手数・終局の理由・相手ごと）
+async fn player_stats(Query(query): Query<StatsQuery>) -> Result<Json<Dashboard>, Error> {
+    let mut conn = db::get_conn()?;
+    let players =
+        stats::resolve_players(&mut conn, query.profile.as_deref(), query.player.as_deref())?;
+    Ok(Json(stats::for_players(&mut conn, &players)?))
+}
+
+/// 保存済みのプロファイルの一覧
+async fn list_profiles() -> Result<Json<Vec<PlayerProfile>>, Error> {
+    let mut conn = db::get_conn()?;
+    Ok(Json(profile::list(&mut conn)?))
+}
+
+async fn get_profile(Path(name): Path<String>) -> Result<Json<PlayerProfile>, Error> {
+    let mut conn = db::get_conn()?;
+    profile::fetch(&mut conn, &name)?
+        .map(Json)
+        .ok_or_else(|| Error::not_found("プロファイルが見つかりません").with_text(name))
+}
+
+#[derive(Deserialize)]
+struct SaveProfileRequest {
+    aliases: Vec<String>,
+}
+
+/// プロファイルを保存する（同じ名前があればアカウント名を置き換える）
+async fn save_profile(
+    Path(name): Path<String>,
+    Json(request): Json<SaveProfileRequest>,
+) -> Result<Json<PlayerProfile>, Error> {
+    let profile = PlayerProfile::new(&name, &request.aliases)?;
+    let mut conn = db::get_conn()?;
+    profile::save(&mut conn, &profile)?;
+    Ok(Json(profile))
+}
+
+async fn delete_profile(Path(name): Path<String>) -> Result<StatusCode, Error> {
+    let mut conn = db::get_conn()?;
+    if !profile::delete(&mut conn, &name)? {
+        return Err(Error::not_found("プロファイルが見つかりません").with_text(name));
+    }
+    Ok(StatusCode::NO_CONTENT)
 }
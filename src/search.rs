@@ -0,0 +1,1241 @@
+use crate::board::Board;
+use crate::config::IMPORTED_DIR;
+use crate::error::{Error, Result};
+use crate::filter::{self, GameFilter};
+use crate::packed::{self, BITBOARD_PIECES, Bitboards};
+use crate::result_set::Page;
+use crate::{db, index, profile, zobrist};
+use mysql::prelude::Queryable;
+use mysql::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// マスに対する条件の種類
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConditionOp {
+    #[default]
+    Eq, // 駒 sfen がある
+    Not,      // 駒 sfen 以外（空きマスを含む）
+    Empty,    // 空きマス
+    Occupied, // 先後問わず何かの駒がある
+    Mine,     // 自分の駒がある（先手視点では大文字）
+    Theirs,   // 相手の駒がある（先手視点では小文字）
+}
+
+/// 局面の条件（マス c に駒 sfen がある、など）
+#[derive(Deserialize, Clone, Debug)]
+pub struct SearchCondition {
+    pub c: String,
+    #[serde(default)]
+    pub sfen: String, // op が eq / not のときだけ使う
+    #[serde(default)]
+    pub op: ConditionOp,
+}
+
+/// 条件を AND / OR / NOT で組み合わせた検索クエリ（1 つの局面に対して評価する）
+///
+/// JSON では `{"and": [...]}`, `{"or": [...]}`, `{"not": {...}}`, 条件そのもの、
+/// または条件の配列（and と同じ）で表す
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum Query {
+    // 構造体の形の variant は配列からも読めてしまうので、配列は最初に判定する
+    All(Vec<Query>), // 従来の条件の配列
+    And { and: Vec<Query> },
+    Or { or: Vec<Query> },
+    Not { not: Box<Query> },
+    Shape(Shape),
+    Condition(SearchCondition),
+}
+
+/// 盤上の位置を問わない形の検索（`{"shape": {...}, "shift_files": true}` など）
+///
+/// shape の条件全体を、盤からはみ出さない範囲で平行移動（と左右反転）したもののどれかに一致すればよい
+#[derive(Deserialize, Clone, Debug)]
+pub struct Shape {
+    pub shape: Box<Query>,
+    #[serde(default)]
+    pub shift_files: bool, // 左右（筋方向）にずらす
+    #[serde(default)]
+    pub shift_ranks: bool, // 上下（段方向）にずらす
+    #[serde(default)]
+    pub mirror: bool, // 左右反転（1筋⇔9筋）した形も探す
+}
+
+/// 検索のオプション
+#[derive(Deserialize, Clone, Default, Debug)]
+pub struct SearchOptions {
+    #[serde(default)]
+    pub mirror: bool, // 左右反転（1筋⇔9筋）した条件でも検索する
+    #[serde(default)]
+    pub everyone: bool, // 自分の対局に限らず、DB の全ての棋譜を先手・後手両方の視点で検索する
+    #[serde(default)]
+    pub profile: Option<String>, // 視点にする対局者のプロファイル名（省略時は MY_USERNAMES）
+    #[serde(flatten)]
+    pub filter: GameFilter, // 対局情報・手数による絞り込み
+}
+
+/// /api/search のリクエスト（クエリだけ、またはクエリとオプション）
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum SearchRequest {
+    WithOptions {
+        query: Query,
+        #[serde(flatten)]
+        options: Box<SearchOptions>,
+        #[serde(flatten)]
+        page: Page,
+    },
+    Query(Query),
+}
+
+impl SearchRequest {
+    pub fn into_parts(self) -> (Query, SearchOptions, Page) {
+        match self {
+            SearchRequest::WithOptions {
+                query,
+                options,
+                page,
+            } => (query, *options, page),
+            SearchRequest::Query(query) => (query, SearchOptions::default(), Page::default()),
+        }
+    }
+}
+
+/// どの変換をした条件で一致したか
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Transform {
+    AsIs,            // 指定した条件のまま（自分が先手）
+    Flipped,         // 先後反転（自分が後手）
+    Mirrored,        // 左右反転（自分が先手）
+    FlippedMirrored, // 先後反転 + 左右反転（自分が後手）
+}
+
+// 検索結果に含める、一致した局面の後の指し手の数
+const NEXT_MOVES: i32 = 5;
+
+/// 棋譜の情報（対局情報と指し手）の URL
+pub fn game_url(kif_id: u64) -> String {
+    format!("/api/games/{}", kif_id)
+}
+
+/// 棋譜の KIF の URL
+pub fn kif_url(kif_id: u64) -> String {
+    format!("/api/games/{}/kif", kif_id)
+}
+
+/// 検索でヒットした棋譜
+#[derive(Serialize, Deserialize)]
+pub struct KifLink {
+    pub kif_id: u64,
+    pub url: String,     // 棋譜の情報（/api/games/{id}）
+    pub kif_url: String, // KIF（/api/games/{id}/kif）
+    pub kif_filename: String,
+    pub link: String,            // サーバー上のファイルのパス
+    pub te: i32,                 // 最初に一致した手数
+    pub tes: Vec<i32>,           // 一致した全ての手数
+    pub sfen: Option<String>,    // te の局面
+    pub fugo: String,            // te の局面になった指し手
+    pub next_moves: Vec<String>, // te の後に実際に指された手（最大 NEXT_MOVES 手）
+    pub player: String,          // 視点にした対局者（通常は自分）
+    pub opponent: String,
+    pub is_win: bool, // player が勝ったかどうか
+    pub is_draw: bool,
+    pub started_at: Option<String>,
+    pub length: i32, // 総手数
+    pub is_sente: bool,
+    pub transform: Transform,
+}
+
+impl SearchCondition {
+    /// マス番号（1〜81）。SQL の列名に使うので必ず範囲を確認する
+    pub fn square(&self) -> Result<u8> {
+        self.c
+            .trim()
+            .parse::<u8>()
+            .ok()
+            .filter(|c| (1..=81).contains(c))
+            .ok_or_else(|| Error::query("マス番号は1〜81で指定してください").with_text(&self.c))
+    }
+
+    /// 自分が後手の場合の条件（盤面を 180 度回転し、先後を入れ替える）
+    pub fn reversed(&self) -> Result<SearchCondition> {
+        // cの反転: 82 - c
+        let reversed_c = 82 - self.square()?;
+
+        // sfenの大文字小文字反転
+        let reversed_sfen = self
+            .sfen
+            .chars()
+            .map(|ch| {
+                if ch.is_ascii_lowercase() {
+                    ch.to_ascii_uppercase()
+                } else {
+                    ch.to_ascii_lowercase()
+                }
+            })
+            .collect::<String>();
+
+        let op = match self.op {
+            ConditionOp::Mine => ConditionOp::Theirs,
+            ConditionOp::Theirs => ConditionOp::Mine,
+            op => op,
+        };
+
+        Ok(SearchCondition {
+            c: reversed_c.to_string(),
+            sfen: reversed_sfen,
+            op,
+        })
+    }
+
+    /// 盤面上で平行移動した条件（d_col は 1筋方向、d_row は九段目方向が正）
+    pub fn shifted(&self, d_col: i32, d_row: i32) -> Result<SearchCondition> {
+        let (col, row) = col_row(self.square()?);
+        let (col, row) = (col as i32 + d_col, row as i32 + d_row);
+        if !(0..9).contains(&col) || !(0..9).contains(&row) {
+            return Err(Error::query("盤外に移動しました").with_text(&self.c));
+        }
+        Ok(self.with_square(row as u8 * 9 + col as u8 + 1))
+    }
+
+    /// 左右反転（1筋⇔9筋）した条件。先後はそのまま
+    pub fn mirrored(&self) -> Result<SearchCondition> {
+        let (col, row) = col_row(self.square()?);
+        Ok(self.with_square(row * 9 + (8 - col) + 1))
+    }
+
+    fn with_square(&self, c: u8) -> SearchCondition {
+        SearchCondition {
+            c: c.to_string(),
+            ..self.clone()
+        }
+    }
+
+    /// ビットボードでの判定の木に変換する（先手視点で、大文字が自分の駒）
+    fn node(&self) -> Result<Node> {
+        let mask = packed::square_mask(self.square()?);
+        let test = |source, all_set| BitTest {
+            source,
+            mask,
+            all_set,
+        };
+
+        // 駒の条件は「駒の列に立っている」かつ「成り駒の列が成り（+）の指定と同じ」
+        let piece = || -> Result<Vec<BitTest>> {
+            let (promoted, piece) = match self.sfen.strip_prefix('+') {
+                Some(piece) => (true, piece),
+                None => (false, self.sfen.as_str()),
+            };
+            let mut chars = piece.chars();
+            let piece = match (chars.next(), chars.next()) {
+                (Some(piece), None) => Some(piece),
+                _ => None,
+            };
+            let index = piece
+                .and_then(|p| BITBOARD_PIECES.iter().position(|&b| b == p))
+                .filter(|&i| !promoted || can_promote(BITBOARD_PIECES[i]))
+                .ok_or_else(|| {
+                    Error::query(
+                        "駒(sfen)は PLNSGBRK（後手は小文字、成り駒は +P など）で指定してください",
+                    )
+                    .with_text(format!("{}: {}", self.c, self.sfen))
+                })?;
+
+            let mut tests = vec![test(BitSource::Piece(index), true)];
+            if can_promote(BITBOARD_PIECES[index]) {
+                tests.push(test(BitSource::Promoted, promoted));
+            }
+            Ok(tests)
+        };
+
+        let node = match self.op {
+            ConditionOp::Eq => Node::tests(piece()?),
+            // 1 マスだけの判定は、否定もマスクの判定で表せる
+            ConditionOp::Not => match <[BitTest; 1]>::try_from(piece()?) {
+                Ok([t]) => Node::Test(BitTest {
+                    all_set: !t.all_set,
+                    ..t
+                }),
+                Err(tests) => Node::Not(Box::new(Node::tests(tests))),
+            },
+            ConditionOp::Empty => Node::Test(test(BitSource::Occupied, false)),
+            ConditionOp::Occupied => Node::Test(test(BitSource::Occupied, true)),
+            ConditionOp::Mine => Node::Test(test(BitSource::Sente, true)),
+            ConditionOp::Theirs => Node::Test(test(BitSource::Gote, true)),
+        };
+        Ok(node)
+    }
+}
+
+/// 成ることができる駒か
+fn can_promote(piece: char) -> bool {
+    "PLNSBR".contains(piece.to_ascii_uppercase())
+}
+
+/// 判定に使うビットボード
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BitSource {
+    Piece(usize), // BITBOARD_PIECES の添字
+    Sente,
+    Gote,
+    Occupied, // 先後問わず駒のあるマス
+    Promoted, // 先後問わず成り駒のあるマス
+}
+
+impl BitSource {
+    /// ビットボードの i バイト目（0 始まり、上位から）の値を返す SQL（テーブルエイリアスbを付ける）
+    ///
+    /// バイナリ文字列どうしのビット演算は MySQL 8.0 からなので、1 バイトずつ数値にして比べる。
+    /// 列を関数に通すので索引は効かず、DB での検索は kif_bodies の全行を調べる（速さが要るなら POSITION_INDEX）
+    fn byte_sql(self, i: usize) -> String {
+        let byte = |column: &str| format!("ASCII(SUBSTRING(b.{}, {}, 1))", column, i + 1);
+        match self {
+            BitSource::Piece(index) => {
+                byte(&packed::column_name(BITBOARD_PIECES[index]).unwrap_or_default())
+            }
+            BitSource::Sente => byte("bb_sente"),
+            BitSource::Gote => byte("bb_gote"),
+            BitSource::Occupied => format!("({} | {})", byte("bb_sente"), byte("bb_gote")),
+            // 成り駒の列を追加する前の局面（再計算前）は、成り駒がないものとして扱う
+            BitSource::Promoted => format!("IFNULL({}, 0)", byte(packed::PROMOTED_COLUMN)),
+        }
+    }
+
+    /// 旧形式の列（c1〜c81）でマス c を判定する SQL（成りの区別はできない）
+    fn legacy_sql(self, c: u8, is_set: bool) -> String {
+        let column = format!("b.c{}", c);
+        let sql = match self {
+            BitSource::Piece(index) => format!("BINARY {} = '{}'", column, BITBOARD_PIECES[index]),
+            BitSource::Sente => format!("BINARY {} BETWEEN 'A' AND 'Z'", column),
+            BitSource::Gote => format!("BINARY {} BETWEEN 'a' AND 'z'", column),
+            BitSource::Occupied => format!("{} <> ''", column),
+            BitSource::Promoted => return "TRUE".to_string(),
+        };
+        if is_set {
+            sql
+        } else {
+            format!("NOT ({})", sql)
+        }
+    }
+
+    fn bits(self, bb: &Bitboards) -> u128 {
+        match self {
+            BitSource::Piece(index) => bb.pieces[index],
+            BitSource::Sente => bb.sente,
+            BitSource::Gote => bb.gote,
+            BitSource::Occupied => bb.sente | bb.gote,
+            BitSource::Promoted => bb.promoted,
+        }
+    }
+}
+
+/// ビットボード source のうち、mask のマスが全て立っている（all_set が false なら全て立っていない）
+#[derive(Debug, Clone)]
+struct BitTest {
+    source: BitSource,
+    mask: u128,
+    all_set: bool,
+}
+
+impl BitTest {
+    // マスク・列名はプログラムで作ったものだけなので、SQL に直接埋め込む
+    fn to_sql(&self) -> String {
+        let clauses: Vec<String> = packed::to_bytes(self.mask)
+            .iter()
+            .enumerate()
+            .filter(|&(_, &mask)| mask != 0)
+            .map(|(i, &mask)| {
+                let expected = if self.all_set { mask } else { 0 };
+                format!("({} & {}) = {}", self.source.byte_sql(i), mask, expected)
+            })
+            .collect();
+        join_clauses(&clauses)
+    }
+
+    // c1〜c81 の列で判定する（mask のマスそれぞれについての条件を AND でつなぐ）
+    fn to_legacy_sql(&self) -> String {
+        let clauses: Vec<String> = (1..=81)
+            .filter(|&c| self.mask & packed::square_mask(c) != 0)
+            .map(|c| self.source.legacy_sql(c, self.all_set))
+            .collect();
+        join_clauses(&clauses)
+    }
+
+    fn matches(&self, bb: &Bitboards) -> bool {
+        let bits = self.source.bits(bb) & self.mask;
+        if self.all_set {
+            bits == self.mask
+        } else {
+            bits == 0
+        }
+    }
+}
+
+/// クエリをビットボードの判定の木にしたもの（SQL でもメモリ上の索引でも同じものを使う）
+#[derive(Debug, Clone)]
+pub struct Matcher(Node);
+
+#[derive(Debug, Clone)]
+enum Node {
+    All(Vec<Node>),
+    Any(Vec<Node>),
+    Not(Box<Node>),
+    Test(BitTest),
+}
+
+impl Matcher {
+    /// 局面が条件に一致するか
+    pub fn matches(&self, bb: &Bitboards) -> bool {
+        self.0.matches(bb)
+    }
+
+    /// WHERE 句に変換する
+    pub fn to_sql(&self) -> String {
+        self.0.to_sql(&BitTest::to_sql)
+    }
+
+    /// 旧形式の c1〜c81 列で判定する WHERE 句に変換する（ビットボードを計算する前の局面用）
+    pub fn to_legacy_sql(&self) -> String {
+        self.0.to_sql(&BitTest::to_legacy_sql)
+    }
+}
+
+impl Node {
+    /// 判定を AND でつないだもの（1 つだけならそのまま）
+    fn tests(tests: Vec<BitTest>) -> Node {
+        match <[BitTest; 1]>::try_from(tests) {
+            Ok([test]) => Node::Test(test),
+            Err(tests) => Node::All(tests.into_iter().map(Node::Test).collect()),
+        }
+    }
+
+    fn matches(&self, bb: &Bitboards) -> bool {
+        match self {
+            Node::All(nodes) => nodes.iter().all(|n| n.matches(bb)),
+            Node::Any(nodes) => nodes.iter().any(|n| n.matches(bb)),
+            Node::Not(node) => !node.matches(bb),
+            Node::Test(test) => test.matches(bb),
+        }
+    }
+
+    fn to_sql(&self, test_sql: &dyn Fn(&BitTest) -> String) -> String {
+        match self {
+            Node::All(nodes) => join_sql(nodes, " AND ", "TRUE", test_sql),
+            Node::Any(nodes) => join_sql(nodes, " OR ", "FALSE", test_sql),
+            Node::Not(node) => format!("NOT ({})", node.to_sql(test_sql)),
+            Node::Test(test) => test_sql(test),
+        }
+    }
+}
+
+/// 子を括弧でくくってつなぐ（子がなければ empty を返す）
+fn join_sql(
+    nodes: &[Node],
+    separator: &str,
+    empty: &str,
+    test_sql: &dyn Fn(&BitTest) -> String,
+) -> String {
+    if nodes.is_empty() {
+        return empty.to_string();
+    }
+    nodes
+        .iter()
+        .map(|n| format!("({})", n.to_sql(test_sql)))
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+/// 1 つの判定の中の条件を AND でつなぐ（条件がなければ TRUE）
+fn join_clauses(clauses: &[String]) -> String {
+    if clauses.is_empty() {
+        "TRUE".to_string()
+    } else {
+        clauses.join(" AND ")
+    }
+}
+
+impl Query {
+    /// 条件を全て AND でつないだクエリ
+    pub fn all(conditions: Vec<SearchCondition>) -> Query {
+        Query::All(conditions.into_iter().map(Query::Condition).collect())
+    }
+
+    /// 自分が後手の場合のクエリ（木の形はそのままで、各条件を反転する）
+    pub fn reversed(&self) -> Result<Query> {
+        self.map_conditions(&|cond| cond.reversed())
+    }
+
+    /// 左右反転（1筋⇔9筋）したクエリ
+    pub fn mirrored(&self) -> Result<Query> {
+        self.map_conditions(&SearchCondition::mirrored)
+    }
+
+    /// 木の形はそのままで、各条件を置き換える
+    fn map_conditions(
+        &self,
+        f: &dyn Fn(&SearchCondition) -> Result<SearchCondition>,
+    ) -> Result<Query> {
+        let map_all = |queries: &[Query]| -> Result<Vec<Query>> {
+            queries.iter().map(|q| q.map_conditions(f)).collect()
+        };
+
+        let mapped = match self {
+            Query::And { and } => Query::And { and: map_all(and)? },
+            Query::Or { or } => Query::Or { or: map_all(or)? },
+            Query::Not { not } => Query::Not {
+                not: Box::new(not.map_conditions(f)?),
+            },
+            Query::Shape(shape) => Query::Shape(Shape {
+                shape: Box::new(shape.shape.map_conditions(f)?),
+                ..*shape
+            }),
+            Query::Condition(cond) => Query::Condition(f(cond)?),
+            Query::All(queries) => Query::All(map_all(queries)?),
+        };
+        Ok(mapped)
+    }
+
+    /// 条件に使われているマスの (列, 段) をすべて集める
+    fn collect_squares(&self, squares: &mut Vec<(u8, u8)>) -> Result<()> {
+        match self {
+            Query::And { and: queries } | Query::Or { or: queries } | Query::All(queries) => {
+                for q in queries {
+                    q.collect_squares(squares)?;
+                }
+            }
+            Query::Not { not } => not.collect_squares(squares)?,
+            Query::Shape(shape) => shape.shape.collect_squares(squares)?,
+            Query::Condition(cond) => squares.push(col_row(cond.square()?)),
+        }
+        Ok(())
+    }
+
+    /// 判定の木に変換する
+    pub fn matcher(&self) -> Result<Matcher> {
+        Ok(Matcher(self.node()?))
+    }
+
+    fn node(&self) -> Result<Node> {
+        let node = match self {
+            Query::And { and: queries } | Query::All(queries) => all_node(queries)?,
+            Query::Or { or } => Node::Any(or.iter().map(Query::node).collect::<Result<_>>()?),
+            Query::Not { not } => Node::Not(Box::new(not.node()?)),
+            Query::Shape(shape) => Node::Any(
+                shape
+                    .variants()?
+                    .iter()
+                    .map(Query::node)
+                    .collect::<Result<_>>()?,
+            ),
+            Query::Condition(cond) => cond.node()?,
+        };
+        Ok(node)
+    }
+
+    /// WHERE 句に変換する
+    #[cfg(test)]
+    fn to_sql(&self) -> Result<String> {
+        Ok(self.matcher()?.to_sql())
+    }
+}
+
+/// AND でつなぐ。同じビットボードに対する条件は 1 つのマスクにまとめる
+fn all_node(queries: &[Query]) -> Result<Node> {
+    let mut tests: Vec<BitTest> = Vec::new();
+    let mut others = Vec::new();
+    for q in queries {
+        let node = match q {
+            Query::Condition(cond) => cond.node()?,
+            _ => {
+                others.push(q.node()?);
+                continue;
+            }
+        };
+        let condition_tests = match node {
+            Node::Test(test) => vec![test],
+            Node::All(nodes) if nodes.iter().all(|n| matches!(n, Node::Test(_))) => nodes
+                .into_iter()
+                .filter_map(|n| match n {
+                    Node::Test(test) => Some(test),
+                    _ => None,
+                })
+                .collect(),
+            node => {
+                others.push(node);
+                continue;
+            }
+        };
+        for test in condition_tests {
+            match tests
+                .iter_mut()
+                .find(|t| t.source == test.source && t.all_set == test.all_set)
+            {
+                Some(t) => t.mask |= test.mask,
+                None => tests.push(test),
+            }
+        }
+    }
+
+    Ok(Node::All(
+        tests.into_iter().map(Node::Test).chain(others).collect(),
+    ))
+}
+
+impl Shape {
+    /// 平行移動・左右反転した形をすべて列挙する（盤からはみ出すものは除く）
+    pub fn variants(&self) -> Result<Vec<Query>> {
+        let mut bases = vec![(*self.shape).clone()];
+        if self.mirror {
+            bases.push(self.shape.mirrored()?);
+        }
+
+        let mut variants = Vec::new();
+        for base in bases {
+            let mut squares = Vec::new();
+            base.collect_squares(&mut squares)?;
+            if squares.is_empty() {
+                variants.push(base);
+                continue;
+            }
+
+            let col_shifts = shift_range(self.shift_files, squares.iter().map(|&(col, _)| col));
+            let row_shifts = shift_range(self.shift_ranks, squares.iter().map(|&(_, row)| row));
+            for d_row in row_shifts {
+                for d_col in col_shifts.clone() {
+                    variants.push(base.map_conditions(&|cond| cond.shifted(d_col, d_row))?);
+                }
+            }
+        }
+        Ok(variants)
+    }
+}
+
+/// マス番号（1〜81）を (列, 段) に分ける。列 0 が 9筋、段 0 が一段目
+fn col_row(c: u8) -> (u8, u8) {
+    ((c - 1) % 9, (c - 1) / 9)
+}
+
+/// 使われている列（段）がすべて盤内に収まる移動量の範囲
+fn shift_range(
+    enabled: bool,
+    values: impl Iterator<Item = u8> + Clone,
+) -> std::ops::RangeInclusive<i32> {
+    if !enabled {
+        return 0..=0;
+    }
+    let min = values.clone().min().unwrap_or(0) as i32;
+    let max = values.max().unwrap_or(8) as i32;
+    -min..=8 - max
+}
+
+/// 条件に一致する局面が登場した棋譜を、自分が先手・後手の両方の場合について検索する
+///
+/// 全員の棋譜を検索するときは、各対局を先手・後手それぞれの視点で調べ、両方で一致すれば両方返す
+pub fn find_games(
+    conn: &mut PooledConn,
+    query: &Query,
+    options: &SearchOptions,
+) -> Result<Vec<KifLink>> {
+    // 先手の場合はそのまま、後手の場合は条件を反転して検索する
+    let mut searches = vec![
+        (Transform::AsIs, query.clone(), true),
+        (Transform::Flipped, query.reversed()?, false),
+    ];
+    if options.mirror {
+        let mirrored = query.mirrored()?;
+        searches.push((Transform::Mirrored, mirrored.clone(), true));
+        searches.push((Transform::FlippedMirrored, mirrored.reversed()?, false));
+    }
+
+    let players = if options.everyone {
+        None
+    } else {
+        profile::resolve(conn, options.profile.as_deref())?
+    };
+    // 別のプロセスが棋譜を取り込んでいれば、索引を読み直してから探す
+    index::refresh(conn)?;
+
+    let mut links: Vec<KifLink> = Vec::new();
+    let mut seen = HashSet::new();
+    for (transform, query, is_sente) in searches {
+        if !options.filter.includes_side(is_sente) {
+            continue;
+        }
+        let rows =
+            find_games_for_side(conn, &query, &options.filter, players.as_deref(), is_sente)?;
+
+        // 反転前の条件でも一致した棋譜は、そちらを優先する
+        for hit in rows {
+            if !seen.insert((hit.kif_filename.clone(), hit.is_sente)) {
+                continue;
+            }
+            links.push(KifLink {
+                kif_id: hit.kif_id,
+                url: game_url(hit.kif_id),
+                kif_url: kif_url(hit.kif_id),
+                link: format!("{}/{}", IMPORTED_DIR.display(), hit.kif_filename),
+                kif_filename: hit.kif_filename,
+                te: hit.tes[0],
+                tes: hit.tes,
+                sfen: None,
+                fugo: String::new(),
+                next_moves: Vec::new(),
+                player: hit.player,
+                opponent: hit.opponent,
+                is_win: hit.is_win,
+                is_draw: hit.is_draw,
+                started_at: hit.started_at,
+                length: hit.length,
+                is_sente: hit.is_sente,
+                transform,
+            });
+        }
+    }
+
+    // started_at の降順でソート
+    links.sort_by(|a, b| b.started_at.cmp(&a.started_at));
+
+    Ok(links)
+}
+
+/// 最初に一致した局面の SFEN・その局面になった指し手・続く指し手を埋める
+///
+/// 1 件ごとに DB を読むので、表示する範囲（ページ）の棋譜だけに対して呼ぶ
+pub fn add_position_details(conn: &mut PooledConn, link: &mut KifLink) -> Result<()> {
+    let rows: Vec<(i32, String, Option<Vec<u8>>)> = conn.exec(
+        "SELECT te, fugo, packed FROM kif_bodies
+            WHERE kif_id = ? AND te BETWEEN ? AND ? ORDER BY te",
+        (link.kif_id, link.te, link.te + NEXT_MOVES),
+    )?;
+
+    for (te, fugo, packed) in rows {
+        if te != link.te {
+            link.next_moves.push(fugo);
+            continue;
+        }
+        link.fugo = fugo;
+        if let Some(bytes) = packed {
+            let (board, sente_to_move) = packed::unpack(&bytes)?;
+            link.sfen = Some(board.to_sfen(sente_to_move, te as usize + 1));
+        }
+    }
+    Ok(())
+}
+
+/// 局面の完全一致検索の条件（SFEN・取り込み済み棋譜の局面・局面ハッシュのいずれか）
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum PositionQuery {
+    Sfen { sfen: String },
+    Stored { kif_id: u64, te: i32 },
+    Hash { hash: String }, // JavaScript の数値では 64bit を表せないので 10 進の文字列で受け取る
+}
+
+/// 局面の完全一致検索でヒットした局面（先後の視点は変えない）
+#[derive(Serialize, Debug)]
+pub struct PositionHit {
+    pub kif_id: u64,
+    pub url: String, // 棋譜の情報（/api/games/{id}）
+    pub link: String,
+    pub te: i32,
+    pub fugo: String, // この局面になった指し手
+    pub sente_player: String,
+    pub gote_player: String,
+    pub started_at: Option<String>,
+}
+
+impl PositionQuery {
+    /// 局面ハッシュ（取り込み済みの局面が見つからなければ None）
+    pub fn position_hash(&self, conn: &mut PooledConn) -> Result<Option<u64>> {
+        match self {
+            PositionQuery::Sfen { sfen } => {
+                let (board, sente_to_move) = Board::from_sfen(sfen)?;
+                Ok(Some(zobrist::hash(&board, sente_to_move)))
+            }
+            PositionQuery::Stored { kif_id, te } => Ok(conn.exec_first(
+                "SELECT position_hash FROM kif_bodies WHERE kif_id = ? AND te = ?",
+                (kif_id, te),
+            )?),
+            PositionQuery::Hash { hash } => zobrist::parse_hash(hash).map(Some),
+        }
+    }
+}
+
+/// 盤面・持ち駒・手番が完全に一致する局面を、全ての棋譜から探す（手順違いの合流も含む）
+pub fn find_position(conn: &mut PooledConn, query: &PositionQuery) -> Result<Vec<PositionHit>> {
+    let Some(hash) = query.position_hash(conn)? else {
+        return Ok(Vec::new());
+    };
+
+    let hits = conn.exec_map(
+        r"SELECT h.id, h.kif_filename, b.te, b.fugo, h.sente_player, h.gote_player,
+                DATE_FORMAT(h.started_at, '%Y-%m-%d %H:%i:%s')
+            FROM kif_bodies b
+            JOIN kif_headers h ON b.kif_id = h.id
+            WHERE b.position_hash = ?
+            ORDER BY h.started_at DESC, b.te",
+        (hash,),
+        |(kif_id, filename, te, fugo, sente_player, gote_player, started_at): PositionRow| {
+            PositionHit {
+                kif_id,
+                url: game_url(kif_id),
+                link: format!("{}/{}", IMPORTED_DIR.display(), filename),
+                te,
+                fugo,
+                sente_player,
+                gote_player,
+                started_at,
+            }
+        },
+    )?;
+
+    Ok(hits)
+}
+
+// 完全一致検索の 1 行（id, kif_filename, te, fugo, sente_player, gote_player, started_at）
+type PositionRow = (u64, String, i32, String, String, String, Option<String>);
+
+// 片方の視点での検索結果の 1 件
+struct GameHit {
+    kif_id: u64,
+    kif_filename: String,
+    tes: Vec<i32>, // 一致した手数（昇順）
+    player: String,
+    opponent: String,
+    is_win: bool,
+    is_draw: bool,
+    started_at: Option<String>,
+    length: i32,
+    is_sente: bool,
+}
+
+// SQL の結果の 1 行（id, kif_filename, tes（カンマ区切り）, player, opponent, is_win, is_draw,
+// started_at, length, sengo）
+type GameSqlRow = (
+    u64,
+    String,
+    String,
+    String,
+    String,
+    bool,
+    bool,
+    Option<String>,
+    i32,
+    bool,
+);
+
+/// players（None なら全員）が先手（または後手）の対局から、条件に一致する局面を探す
+fn find_games_for_side(
+    conn: &mut PooledConn,
+    query: &Query,
+    filter: &GameFilter,
+    players: Option<&[String]>,
+    is_sente: bool,
+) -> Result<Vec<GameHit>> {
+    // メモリ上の索引があればそちらで探す（索引は検索の前に DB と揃えてある）
+    if let Some(rows) = find_games_in_index(query, filter, players, is_sente)? {
+        return Ok(rows);
+    }
+
+    let mut params: Vec<Value> = Vec::new();
+    let (player_column, opponent_column) = if is_sente {
+        ("h.sente_player", "h.gote_player")
+    } else {
+        ("h.gote_player", "h.sente_player")
+    };
+
+    // 与えられた条件を一旦、where_clausesに格納
+    // （ビットボードを計算し終えていない局面は、残っている c1〜c81 列で判定する）
+    let matcher = query.matcher()?;
+    let mut where_clauses = vec![if db::has_pending_positions(conn)? {
+        format!(
+            "((b.packed IS NOT NULL AND ({})) OR (b.packed IS NULL AND ({})))",
+            matcher.to_sql(),
+            matcher.to_legacy_sql()
+        )
+    } else {
+        format!("({})", matcher.to_sql())
+    }];
+
+    // ユーザー名の条件も一旦、where_clausesに格納（索引と同じく大文字・小文字を区別する）
+    if let Some(players) = players {
+        where_clauses.push(format!(
+            "BINARY {} IN ({})",
+            player_column,
+            players.iter().map(|_| "?").collect::<Vec<_>>().join(", ")
+        ));
+        params.extend(players.iter().cloned().map(Value::from));
+    }
+
+    // 対局情報・手数の絞り込み
+    where_clauses.extend(filter.to_sql(is_sente, &mut params)?);
+
+    let sql = format!(
+        r#"
+SELECT h.id, h.kif_filename, GROUP_CONCAT(b.te ORDER BY b.te) as tes, {} as player, {} as opponent, {} as is_win, h.is_draw, DATE_FORMAT(h.started_at, '%Y-%m-%d %H:%i:%s') AS started_at,
+    (SELECT MAX(b2.te) FROM kif_bodies b2 WHERE b2.kif_id = h.id) as length, {} as sengo
+FROM kif_bodies b
+LEFT JOIN kif_headers h ON b.kif_id = h.id
+WHERE {}
+GROUP BY b.kif_id
+    "#,
+        player_column,
+        opponent_column,
+        filter::is_win_sql(is_sente),
+        if is_sente { 1 } else { 0 },
+        where_clauses.join(" AND ")
+    );
+
+    // 一致した手数が多い棋譜でも GROUP_CONCAT が途中で切れないように
+    conn.query_drop("SET SESSION group_concat_max_len = 65535")?;
+    let rows = conn.exec_map(
+        sql,
+        params,
+        |(
+            kif_id,
+            kif_filename,
+            tes,
+            player,
+            opponent,
+            is_win,
+            is_draw,
+            started_at,
+            length,
+            is_sente,
+        ): GameSqlRow| GameHit {
+            kif_id,
+            kif_filename,
+            tes: tes.split(',').filter_map(|t| t.parse().ok()).collect(),
+            player,
+            opponent,
+            is_win,
+            is_draw,
+            started_at,
+            length,
+            is_sente,
+        },
+    )?;
+    Ok(rows)
+}
+
+/// find_games_for_side をメモリ上の索引で行う（索引を読み込んでいなければ None）
+fn find_games_in_index(
+    query: &Query,
+    filter: &GameFilter,
+    players: Option<&[String]>,
+    is_sente: bool,
+) -> Result<Option<Vec<GameHit>>> {
+    let matcher = query.matcher()?;
+    // 日付の形式の誤りは走査の前に返す
+    filter.to_sql(is_sente, &mut Vec::new())?;
+
+    let rows = index::scan(|game| {
+        let (me, opponent) = if is_sente {
+            (&game.sente_player, &game.gote_player)
+        } else {
+            (&game.gote_player, &game.sente_player)
+        };
+        if players.is_some_and(|p| !p.contains(me))
+            || !filter.matches(game, is_sente).unwrap_or(false)
+        {
+            return None;
+        }
+
+        let tes: Vec<i32> = game
+            .positions
+            .iter()
+            .filter(|(te, bb)| filter.includes_te(*te) && matcher.matches(bb))
+            .map(|(te, _)| *te)
+            .collect();
+        if tes.is_empty() {
+            return None;
+        }
+        Some(GameHit {
+            kif_id: game.kif_id,
+            kif_filename: game.kif_filename.clone(),
+            tes,
+            player: me.clone(),
+            opponent: opponent.clone(),
+            is_win: !game.is_draw && game.is_sente_win == is_sente,
+            is_draw: game.is_draw,
+            started_at: game.started_at.clone(),
+            length: game.positions.last().map_or(0, |(te, _)| *te),
+            is_sente,
+        })
+    });
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::result_set::SortKey;
+
+    fn cond(c: &str, sfen: &str, op: ConditionOp) -> SearchCondition {
+        SearchCondition {
+            c: c.to_string(),
+            sfen: sfen.to_string(),
+            op,
+        }
+    }
+
+    #[test]
+    fn test_reversed_condition() {
+        let reversed = cond("68", "R", ConditionOp::Eq).reversed().unwrap();
+        assert_eq!((reversed.c.as_str(), reversed.sfen.as_str()), ("14", "r"));
+
+        let reversed = cond("5", "", ConditionOp::Mine).reversed().unwrap();
+        assert_eq!(
+            (reversed.c.as_str(), reversed.op),
+            ("77", ConditionOp::Theirs)
+        );
+    }
+
+    // source（ビットボードの列名か "occupied"）の squares のマスが全て立っている（all_set が false なら全て立っていない）判定の SQL
+    fn bits(source: &str, squares: &[u8], all_set: bool) -> String {
+        let mask: u128 = squares.iter().map(|&c| 1u128 << (c - 1)).sum();
+        let bytes = mask.to_be_bytes();
+        (5..16)
+            .filter(|&i| bytes[i] != 0)
+            .map(|i| {
+                let pos = i - 4;
+                let expr = match source {
+                    "occupied" => format!(
+                        "(ASCII(SUBSTRING(b.bb_sente, {0}, 1)) | ASCII(SUBSTRING(b.bb_gote, {0}, 1)))",
+                        pos
+                    ),
+                    "bb_promoted" => {
+                        format!("IFNULL(ASCII(SUBSTRING(b.bb_promoted, {}, 1)), 0)", pos)
+                    }
+                    column => format!("ASCII(SUBSTRING(b.{}, {}, 1))", column, pos),
+                };
+                let expected = if all_set { bytes[i] } else { 0 };
+                format!("({} & {}) = {}", expr, bytes[i], expected)
+            })
+            .collect::<Vec<_>>()
+            .join(" AND ")
+    }
+
+    fn sql(c: &str, sfen: &str, op: ConditionOp) -> String {
+        Query::Condition(cond(c, sfen, op)).to_sql().unwrap()
+    }
+
+    #[test]
+    fn test_condition_sql() {
+        // 5五（41）は 88bit の 41 ビット目 → 上位から 6 バイト目の最下位ビット
+        assert_eq!(
+            sql("41", "", ConditionOp::Empty),
+            "((ASCII(SUBSTRING(b.bb_sente, 6, 1)) | ASCII(SUBSTRING(b.bb_gote, 6, 1))) & 1) = 0"
+        );
+        assert_eq!(
+            sql("41", "G", ConditionOp::Not),
+            bits("bb_sente_g", &[41], false)
+        );
+        // 成れる駒は成り駒の列も見る
+        assert_eq!(
+            sql("41", "p", ConditionOp::Eq),
+            format!(
+                "({}) AND ({})",
+                bits("bb_gote_p", &[41], true),
+                bits("bb_promoted", &[41], false)
+            )
+        );
+        assert_eq!(
+            sql("41", "+p", ConditionOp::Eq),
+            format!(
+                "({}) AND ({})",
+                bits("bb_gote_p", &[41], true),
+                bits("bb_promoted", &[41], true)
+            )
+        );
+        assert_eq!(
+            sql("41", "+P", ConditionOp::Not),
+            format!(
+                "NOT (({}) AND ({}))",
+                bits("bb_sente_p", &[41], true),
+                bits("bb_promoted", &[41], true)
+            )
+        );
+
+        // 列名に埋め込むので、マス番号・駒以外は受け付けない
+        let is_err = |c: &str, sfen: &str| cond(c, sfen, ConditionOp::Eq).node().is_err();
+        assert!(is_err("1; DROP TABLE kif_bodies", "P"));
+        assert!(is_err("82", "P"));
+        assert!(is_err("41", "P' OR 1"));
+        assert!(is_err("41", "+G"));
+        assert!(is_err("41", "++P"));
+    }
+
+    #[test]
+    fn test_legacy_sql() {
+        let legacy = |json: &str| {
+            let query: Query = serde_json::from_str(json).unwrap();
+            query.matcher().unwrap().to_legacy_sql()
+        };
+        assert_eq!(
+            legacy(r#"[{"c": "68", "sfen": "P"}, {"c": "59", "sfen": "P"}]"#),
+            "(BINARY b.c59 = 'P' AND BINARY b.c68 = 'P') AND (TRUE AND TRUE)"
+        );
+        assert_eq!(
+            legacy(r#"{"or": [{"c": "41", "op": "empty"}, {"c": "5", "op": "theirs"}]}"#),
+            "(NOT (b.c41 <> '')) OR (BINARY b.c5 BETWEEN 'a' AND 'z')"
+        );
+    }
+
+    #[test]
+    fn test_query_tree() {
+        let query: Query = serde_json::from_str(
+            r#"{"and": [
+                {"or": [{"c": "66", "sfen": "G"}, {"c": "67", "sfen": "G"}]},
+                {"not": {"c": "65", "sfen": "K"}}
+            ]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            query.to_sql().unwrap(),
+            format!(
+                "(({}) OR ({})) AND (NOT ({}))",
+                bits("bb_sente_g", &[66], true),
+                bits("bb_sente_g", &[67], true),
+                bits("bb_sente_k", &[65], true)
+            )
+        );
+
+        // 後手用は木の形を保ったまま各条件を反転する
+        assert_eq!(
+            query.reversed().unwrap().to_sql().unwrap(),
+            format!(
+                "(({}) OR ({})) AND (NOT ({}))",
+                bits("bb_gote_g", &[16], true),
+                bits("bb_gote_g", &[15], true),
+                bits("bb_gote_k", &[17], true)
+            )
+        );
+    }
+
+    #[test]
+    fn test_query_accepts_condition_list() {
+        let query: Query = serde_json::from_str(
+            r#"[{"c": "68", "sfen": "P"}, {"c": "41", "op": "empty"}, {"c": "59", "sfen": "P"}]"#,
+        )
+        .unwrap();
+
+        // 同じ駒の条件は 1 つのマスクにまとめる
+        assert_eq!(
+            query.to_sql().unwrap(),
+            format!(
+                "({}) AND ({}) AND ({})",
+                bits("bb_sente_p", &[68, 59], true),
+                bits("bb_promoted", &[68, 59], false),
+                bits("occupied", &[41], false)
+            )
+        );
+
+        let query: Query = serde_json::from_str("[]").unwrap();
+        assert_eq!(query.to_sql().unwrap(), "TRUE");
+    }
+
+    #[test]
+    fn test_shape_variants() {
+        // 8八玉・7八金の形（2マス）を左右にずらす
+        let query: Query = serde_json::from_str(
+            r#"{"shape": [{"c": "65", "sfen": "K"}, {"c": "66", "sfen": "G"}], "shift_files": true}"#,
+        )
+        .unwrap();
+        let Query::Shape(shape) = &query else {
+            panic!("shape として読めていない: {:?}", query);
+        };
+        assert_eq!(shape.variants().unwrap().len(), 8);
+
+        // 上下にもずらし、左右反転も含める
+        let shape = Shape {
+            shift_ranks: true,
+            mirror: true,
+            ..shape.clone()
+        };
+        assert_eq!(shape.variants().unwrap().len(), 8 * 9 * 2);
+
+        assert!(
+            query
+                .to_sql()
+                .unwrap()
+                .contains(&bits("bb_sente_g", &[72], true))
+        );
+    }
+
+    #[test]
+    fn test_mirrored_condition() {
+        // 2八（71）⇔ 8八（65）、先後はそのまま
+        let mirrored = cond("71", "R", ConditionOp::Eq).mirrored().unwrap();
+        assert_eq!((mirrored.c.as_str(), mirrored.sfen.as_str()), ("65", "R"));
+
+        assert!(cond("9", "K", ConditionOp::Eq).shifted(1, 0).is_err());
+    }
+
+    #[test]
+    fn test_search_request_options() {
+        let request: SearchRequest =
+            serde_json::from_str(r#"{"query": [{"c": "71", "sfen": "R"}], "mirror": true}"#)
+                .unwrap();
+        let (_, options, _) = request.into_parts();
+        assert!(options.mirror);
+
+        // 絞り込みの項目も同じ階層に書く
+        let request: SearchRequest = serde_json::from_str(
+            r#"{"query": [{"c": "71", "sfen": "R"}], "side": "gote", "te_to": 60, "limit": 20, "sort": "te"}"#,
+        )
+        .unwrap();
+        let (_, options, page) = request.into_parts();
+        assert!(!options.filter.includes_side(true));
+        assert_eq!(options.filter.te_to, Some(60));
+        assert_eq!((page.limit, page.sort), (Some(20), SortKey::Te));
+
+        // 条件が 1 つだけの配列も、従来どおり条件の配列として読む
+        let request: SearchRequest = serde_json::from_str(r#"[{"c": "71", "sfen": "R"}]"#).unwrap();
+        let (query, options, _) = request.into_parts();
+        assert!(!options.mirror);
+        assert!(matches!(query, Query::All(_)));
+    }
+
+    #[test]
+    fn test_matcher_on_bitboards() {
+        let bb = Bitboards::from_board(&Board::new());
+        let matches = |json: &str| {
+            let query: Query = serde_json::from_str(json).unwrap();
+            query.matcher().unwrap().matches(&bb)
+        };
+
+        // 初期局面: 2八飛（71）・5九玉（77）、5五（41）は空き
+        assert!(matches(
+            r#"[{"c": "71", "sfen": "R"}, {"c": "77", "sfen": "K"}]"#
+        ));
+        assert!(matches(
+            r#"{"or": [{"c": "41", "sfen": "P"}, {"c": "41", "op": "empty"}]}"#
+        ));
+        assert!(!matches(r#"{"not": {"c": "71", "op": "mine"}}"#));
+        assert!(matches(
+            r#"{"shape": [{"c": "1", "sfen": "R"}], "shift_files": true, "shift_ranks": true}"#
+        ));
+
+        // 成り駒は "+" を付けた駒でだけ一致する
+        let mut board = Board::new();
+        for (te, fugo) in ["７六歩(77)", "３四歩(33)", "２二角成(88)"]
+            .iter()
+            .enumerate()
+        {
+            board.apply_move(fugo, te % 2 == 0).unwrap();
+        }
+        let bb = Bitboards::from_board(&board);
+        let matches = |json: &str| {
+            let query: Query = serde_json::from_str(json).unwrap();
+            query.matcher().unwrap().matches(&bb)
+        };
+        assert!(matches(r#"[{"c": "17", "sfen": "+B"}]"#));
+        assert!(!matches(r#"[{"c": "17", "sfen": "B"}]"#));
+        assert!(matches(r#"[{"c": "17", "op": "not", "sfen": "B"}]"#));
+    }
+}
@@ -0,0 +1,506 @@
+use crate::error::{Error, Result};
+use crate::profile;
+use crate::result_set::{Record, ResultSet, Summary};
+use crate::search::KifLink;
+use chrono::{Datelike, Days, NaiveDateTime, Timelike};
+use mysql::prelude::Queryable;
+use mysql::*;
+use serde::Serialize;
+use std::collections::HashMap;
+
+// IN 句に一度に並べる棋譜の数
+const CHUNK_SIZE: usize = 500;
+
+/// ある値（場所・持ち時間・月・指し手など）ごとの勝敗
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Bucket {
+    pub key: String,
+    #[serde(flatten)]
+    pub record: Record,
+    pub win_rate: f64, // 引き分けを除いた勝率（0.0〜1.0）
+}
+
+/// 検索結果全体の勝敗の集計
+#[derive(Serialize, Debug)]
+pub struct SearchStats {
+    pub result_id: u64,
+    #[serde(flatten)]
+    pub summary: Summary, // 全て・先手・後手
+    pub by_site: Vec<Bucket>,
+    pub by_time_control: Vec<Bucket>,
+    pub by_month: Vec<Bucket>,     // 対局開始日時の年月（古い順）
+    pub by_next_move: Vec<Bucket>, // 一致した局面から自分が次に指した手
+}
+
+/// 値ごとに勝敗を数える
+#[derive(Default)]
+struct Tally(HashMap<String, Record>);
+
+impl Tally {
+    fn add(&mut self, key: &str, link: &KifLink) {
+        self.add_result(key, link.is_win, link.is_draw);
+    }
+
+    fn add_result(&mut self, key: &str, is_win: bool, is_draw: bool) {
+        self.0
+            .entry(key.to_string())
+            .or_default()
+            .add(is_win, is_draw);
+    }
+
+    /// 対局数の多い順（同数なら値の順）
+    fn by_games(self) -> Vec<Bucket> {
+        let mut buckets = self.into_buckets();
+        buckets.sort_by(|a, b| b.record.games.cmp(&a.record.games).then(a.key.cmp(&b.key)));
+        buckets
+    }
+
+    /// 値の順
+    fn by_key(self) -> Vec<Bucket> {
+        let mut buckets = self.into_buckets();
+        buckets.sort_by(|a, b| a.key.cmp(&b.key));
+        buckets
+    }
+
+    /// keys の順（数えていない値は含めない）
+    fn in_order(mut self, keys: &[String]) -> Vec<Bucket> {
+        keys.iter()
+            .filter_map(|key| {
+                self.0.remove(key).map(|record| Bucket {
+                    key: key.clone(),
+                    win_rate: record.win_rate(),
+                    record,
+                })
+            })
+            .collect()
+    }
+
+    fn into_buckets(self) -> Vec<Bucket> {
+        self.0
+            .into_iter()
+            .map(|(key, record)| Bucket {
+                key,
+                win_rate: record.win_rate(),
+                record,
+            })
+            .collect()
+    }
+}
+
+/// 一致した局面（te 手目の後）から、先手（または後手）が次に指す手の手数
+pub fn next_own_te(te: i32, is_sente: bool) -> i32 {
+    // 偶数手目の後は先手番
+    let sente_to_move = te % 2 == 0;
+    if sente_to_move == is_sente {
+        te + 1
+    } else {
+        te + 2
+    }
+}
+
+/// 保存済みの検索結果を場所・持ち時間・月・次の一手ごとに集計する
+///
+/// 次の一手は一致した最初の局面から自分が指した手で、その前に終局した棋譜は by_next_move に含めない
+pub fn for_result_set(conn: &mut PooledConn, result: &ResultSet) -> Result<SearchStats> {
+    let headers = fetch_site_and_time_control(conn, &result.links)?;
+    let next_moves = fetch_next_moves(conn, &result.links)?;
+
+    let mut stats = SearchStats {
+        result_id: result.id,
+        summary: Summary::of(&result.links),
+        by_site: Vec::new(),
+        by_time_control: Vec::new(),
+        by_month: Vec::new(),
+        by_next_move: Vec::new(),
+    };
+    let (mut sites, mut time_controls, mut months, mut moves) = (
+        Tally::default(),
+        Tally::default(),
+        Tally::default(),
+        Tally::default(),
+    );
+
+    for link in &result.links {
+        let (site, time_control) = headers.get(&link.kif_id).cloned().unwrap_or((None, None));
+        sites.add(site.as_deref().unwrap_or("不明"), link);
+        time_controls.add(time_control.as_deref().unwrap_or("不明"), link);
+        months.add(&month_of(link.started_at.as_deref()), link);
+
+        let te = next_own_te(link.te, link.is_sente);
+        if let Some(fugo) = next_moves.get(&(link.kif_id, te)) {
+            moves.add(fugo, link);
+        }
+    }
+
+    stats.by_site = sites.by_games();
+    stats.by_time_control = time_controls.by_games();
+    stats.by_month = months.by_key();
+    stats.by_next_move = moves.by_games();
+    Ok(stats)
+}
+
+// 対局開始日時の年月（"2025-08"）
+fn month_of(started_at: Option<&str>) -> String {
+    match started_at {
+        Some(s) if s.len() >= 7 => s[..7].replace('/', "-"),
+        _ => "不明".to_string(),
+    }
+}
+
+// 棋譜ごとの場所・持ち時間
+type SiteAndTimeControl = (Option<String>, Option<String>);
+
+fn fetch_site_and_time_control(
+    conn: &mut PooledConn,
+    links: &[KifLink],
+) -> Result<HashMap<u64, SiteAndTimeControl>> {
+    let mut ids: Vec<u64> = links.iter().map(|l| l.kif_id).collect();
+    ids.sort_unstable();
+    ids.dedup();
+
+    let mut headers = HashMap::new();
+    for chunk in ids.chunks(CHUNK_SIZE) {
+        let sql = format!(
+            "SELECT id, site, time_control FROM kif_headers WHERE id IN ({})",
+            chunk.iter().map(|_| "?").collect::<Vec<_>>().join(", ")
+        );
+        let rows: Vec<(u64, Option<String>, Option<String>)> = conn.exec(sql, chunk.to_vec())?;
+        // 不明な場合の空文字は NULL と同じく「不明」にまとめる
+        for (id, site, time_control) in rows {
+            headers.insert(
+                id,
+                (
+                    site.filter(|s| !s.is_empty()),
+                    time_control.filter(|s| !s.is_empty()),
+                ),
+            );
+        }
+    }
+    Ok(headers)
+}
+
+// (kif_id, 手数) ごとの、一致した局面から自分が次に指した手
+fn fetch_next_moves(
+    conn: &mut PooledConn,
+    links: &[KifLink],
+) -> Result<HashMap<(u64, i32), String>> {
+    let mut keys: Vec<(u64, i32)> = links
+        .iter()
+        .map(|l| (l.kif_id, next_own_te(l.te, l.is_sente)))
+        .collect();
+    keys.sort_unstable();
+    keys.dedup();
+
+    let mut moves = HashMap::new();
+    for chunk in keys.chunks(CHUNK_SIZE) {
+        let sql = format!(
+            "SELECT kif_id, te, fugo FROM kif_bodies WHERE (kif_id, te) IN ({})",
+            chunk
+                .iter()
+                .map(|_| "(?, ?)")
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        let params: Vec<Value> = chunk
+            .iter()
+            .flat_map(|(id, te)| [Value::from(id), Value::from(te)])
+            .collect();
+        let rows: Vec<(u64, i32, String)> = conn.exec(sql, params)?;
+        for (id, te, fugo) in rows {
+            moves.insert((id, te), fugo);
+        }
+    }
+    Ok(moves)
+}
+
+/// 対局者の成績（自分から見た勝敗）
+#[derive(Serialize, Debug)]
+pub struct Dashboard {
+    pub players: Vec<String>, // 集計したアカウント名
+    #[serde(flatten)]
+    pub summary: Summary, // 全て・先手・後手
+    pub by_week: Vec<Bucket>, // 対局開始日の週（その週の月曜日。古い順）
+    pub by_month: Vec<Bucket>, // 対局開始日時の年月（古い順）
+    pub by_site: Vec<Bucket>,
+    pub by_time_control: Vec<Bucket>,
+    pub by_hour: Vec<Bucket>,       // 対局開始時刻の時（"00"〜"23"）
+    pub by_weekday: Vec<Bucket>,    // 対局開始日の曜日（月〜日の順）
+    pub by_length: Vec<Bucket>,     // 総手数の範囲（短い順）
+    pub by_end_reason: Vec<Bucket>, // 終局の理由（投了・時間切れなど）
+    pub opponents: Vec<Bucket>,     // よく対戦する相手との成績（対局数の多い順に TOP_OPPONENTS 人）
+}
+
+/// 成績に数える 1 局（自分から見た勝敗）
+pub struct PlayedGame {
+    pub opponent: String,
+    pub is_sente: bool,
+    pub is_win: bool,
+    pub is_draw: bool,
+    pub started_at: Option<String>, // "YYYY-MM-DD HH:MM:SS"
+    pub site: Option<String>,
+    pub time_control: Option<String>,
+    pub end_reason: Option<String>,
+    pub length: i32, // 総手数
+}
+
+// 相手ごとの成績に載せる人数
+const TOP_OPPONENTS: usize = 20;
+
+// 手数の分布の区切り（それぞれの範囲の終わり。これ以上は最後の範囲）
+const LENGTH_BOUNDS: [i32; 6] = [40, 60, 80, 100, 120, 150];
+
+const WEEKDAYS: [&str; 7] = ["月", "火", "水", "木", "金", "土", "日"];
+
+/// 成績を集計する対局者のアカウント名を決める
+///
+/// player（アカウント名 1 つ）、profile（プロファイル名）、MY_USERNAMES の順に使う
+pub fn resolve_players(
+    conn: &mut PooledConn,
+    profile: Option<&str>,
+    player: Option<&str>,
+) -> Result<Vec<String>> {
+    if let Some(player) = player {
+        return Ok(vec![player.to_string()]);
+    }
+    profile::resolve(conn, profile)?.ok_or_else(|| {
+        Error::query(
+            "成績を集計する対局者（player か profile、または MY_USERNAMES）を指定してください",
+        )
+    })
+}
+
+// 集計に使う kif_headers の 1 行（sente_player, gote_player, is_sente_win, is_draw,
+// started_at, site, time_control, end_reason, 総手数）
+type PlayedRow = (
+    String,
+    String,
+    bool,
+    bool,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<i32>,
+);
+
+/// players が指した全ての対局の成績を集計する
+pub fn for_players(conn: &mut PooledConn, players: &[String]) -> Result<Dashboard> {
+    let placeholders = players.iter().map(|_| "?").collect::<Vec<_>>().join(", ");
+    let sql = format!(
+        r"SELECT h.sente_player, h.gote_player, h.is_sente_win, h.is_draw,
+                DATE_FORMAT(h.started_at, '%Y-%m-%d %H:%i:%s'), h.site, h.time_control, h.end_reason,
+                (SELECT MAX(b.te) FROM kif_bodies b WHERE b.kif_id = h.id)
+            FROM kif_headers h
+            WHERE BINARY h.sente_player IN ({0}) OR BINARY h.gote_player IN ({0})",
+        placeholders
+    );
+    let params: Vec<Value> = players
+        .iter()
+        .chain(players)
+        .cloned()
+        .map(Value::from)
+        .collect();
+    let rows: Vec<PlayedRow> = conn.exec(sql, params)?;
+
+    let games: Vec<PlayedGame> = rows
+        .into_iter()
+        .map(
+            |(
+                sente_player,
+                gote_player,
+                is_sente_win,
+                is_draw,
+                started_at,
+                site,
+                time_control,
+                end_reason,
+                length,
+            )| {
+                // 自分同士の対局は先手として数える（SQL も BINARY で大文字・小文字を区別しているので、
+                // ここで見つからなければ後手は必ず players のどれか）
+                let is_sente = players.contains(&sente_player);
+                PlayedGame {
+                    opponent: if is_sente { gote_player } else { sente_player },
+                    is_sente,
+                    is_win: !is_draw && is_sente_win == is_sente,
+                    is_draw,
+                    started_at,
+                    site: site.filter(|s| !s.is_empty()),
+                    time_control: time_control.filter(|s| !s.is_empty()),
+                    end_reason: end_reason.filter(|s| !s.is_empty()),
+                    length: length.unwrap_or(0),
+                }
+            },
+        )
+        .collect();
+
+    Ok(dashboard(players, &games))
+}
+
+/// 対局の一覧から成績を集計する
+pub fn dashboard(players: &[String], games: &[PlayedGame]) -> Dashboard {
+    let mut summary = Summary::default();
+    let mut weeks = Tally::default();
+    let mut months = Tally::default();
+    let mut sites = Tally::default();
+    let mut time_controls = Tally::default();
+    let mut hours = Tally::default();
+    let mut weekdays = Tally::default();
+    let mut lengths = Tally::default();
+    let mut end_reasons = Tally::default();
+    let mut opponents = Tally::default();
+
+    for game in games {
+        let (is_win, is_draw) = (game.is_win, game.is_draw);
+        summary.all.add(is_win, is_draw);
+        let side = if game.is_sente {
+            &mut summary.sente
+        } else {
+            &mut summary.gote
+        };
+        side.add(is_win, is_draw);
+
+        let started_at = game
+            .started_at
+            .as_deref()
+            .and_then(|s| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").ok());
+        let (week, hour, weekday) = match started_at {
+            Some(dt) => {
+                let weekday = dt.weekday().num_days_from_monday() as usize;
+                let monday = dt.date() - Days::new(weekday as u64);
+                (
+                    monday.format("%Y-%m-%d").to_string(),
+                    format!("{:02}", dt.hour()),
+                    WEEKDAYS[weekday].to_string(),
+                )
+            }
+            None => ("不明".to_string(), "不明".to_string(), "不明".to_string()),
+        };
+        weeks.add_result(&week, is_win, is_draw);
+        months.add_result(&month_of(game.started_at.as_deref()), is_win, is_draw);
+        hours.add_result(&hour, is_win, is_draw);
+        weekdays.add_result(&weekday, is_win, is_draw);
+
+        sites.add_result(game.site.as_deref().unwrap_or("不明"), is_win, is_draw);
+        time_controls.add_result(
+            game.time_control.as_deref().unwrap_or("不明"),
+            is_win,
+            is_draw,
+        );
+        lengths.add_result(&length_range(game.length), is_win, is_draw);
+        end_reasons.add_result(
+            game.end_reason.as_deref().unwrap_or("不明"),
+            is_win,
+            is_draw,
+        );
+        opponents.add_result(&game.opponent, is_win, is_draw);
+    }
+
+    let weekday_keys: Vec<String> = WEEKDAYS.iter().map(|d| d.to_string()).collect();
+    let length_keys: Vec<String> = [0]
+        .iter()
+        .chain(&LENGTH_BOUNDS)
+        .map(|&start| length_range(start))
+        .collect();
+    let mut opponents = opponents.by_games();
+    opponents.truncate(TOP_OPPONENTS);
+
+    Dashboard {
+        players: players.to_vec(),
+        summary,
+        by_week: weeks.by_key(),
+        by_month: months.by_key(),
+        by_site: sites.by_games(),
+        by_time_control: time_controls.by_games(),
+        by_hour: hours.by_key(),
+        by_weekday: weekdays.in_order(&weekday_keys),
+        by_length: lengths.in_order(&length_keys),
+        by_end_reason: end_reasons.by_games(),
+        opponents,
+    }
+}
+
+// 総手数の範囲（"40〜59手" など）
+fn length_range(length: i32) -> String {
+    let index = LENGTH_BOUNDS.iter().take_while(|&&b| length >= b).count();
+    let start = if index == 0 {
+        0
+    } else {
+        LENGTH_BOUNDS[index - 1]
+    };
+    match LENGTH_BOUNDS.get(index) {
+        Some(end) => format!("{}〜{}手", start, end - 1),
+        None => format!("{}手〜", start),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_own_te() {
+        // 初期局面（0 手目）からは先手が 1 手目、後手が 2 手目を指す
+        assert_eq!(next_own_te(0, true), 1);
+        assert_eq!(next_own_te(0, false), 2);
+        assert_eq!(next_own_te(31, true), 33);
+        assert_eq!(next_own_te(31, false), 32);
+    }
+
+    #[test]
+    fn test_win_rate_and_month() {
+        let mut record = Record::default();
+        record.add(true, false);
+        record.add(false, false);
+        record.add(false, true);
+        assert_eq!(record.win_rate(), 0.5);
+        assert_eq!(Record::default().win_rate(), 0.0);
+
+        assert_eq!(month_of(Some("2025/08/11 06:40:41")), "2025-08");
+        assert_eq!(month_of(None), "不明");
+    }
+
+    fn played(opponent: &str, started_at: &str, is_win: bool, length: i32) -> PlayedGame {
+        PlayedGame {
+            opponent: opponent.to_string(),
+            is_sente: is_win,
+            is_win,
+            is_draw: false,
+            started_at: Some(started_at.to_string()),
+            site: Some("将棋ウォーズ".to_string()),
+            time_control: None,
+            end_reason: Some("投了".to_string()),
+            length,
+        }
+    }
+
+    #[test]
+    fn test_dashboard() {
+        let games = vec![
+            played("zzz208", "2025-08-11 06:40:41", true, 39), // 月曜日
+            played("zzz208", "2025-08-17 21:00:00", false, 40), // 日曜日（同じ週）
+            played("Ringosky", "2025-09-03 06:10:00", true, 151),
+        ];
+        let dashboard = dashboard(&["me".to_string()], &games);
+
+        assert_eq!(
+            (dashboard.summary.all.wins, dashboard.summary.all.losses),
+            (2, 1)
+        );
+        assert_eq!(dashboard.summary.sente.games, 2);
+
+        let keys = |buckets: &[Bucket]| buckets.iter().map(|b| b.key.clone()).collect::<Vec<_>>();
+        assert_eq!(keys(&dashboard.by_week), vec!["2025-08-11", "2025-09-01"]);
+        assert_eq!(dashboard.by_week[0].record.games, 2);
+        assert_eq!(keys(&dashboard.by_month), vec!["2025-08", "2025-09"]);
+        assert_eq!(keys(&dashboard.by_hour), vec!["06", "21"]);
+        assert_eq!(keys(&dashboard.by_weekday), vec!["月", "水", "日"]);
+        assert_eq!(
+            keys(&dashboard.by_length),
+            vec!["0〜39手", "40〜59手", "150手〜"]
+        );
+        assert_eq!(keys(&dashboard.by_time_control), vec!["不明"]);
+        assert_eq!(keys(&dashboard.opponents), vec!["zzz208", "Ringosky"]);
+        assert_eq!(dashboard.opponents[0].win_rate, 0.5);
+    }
+}
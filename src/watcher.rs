@@ -0,0 +1,81 @@
+use crate::config::{KIF_PATH, KIF_WATCH_DEBOUNCE_MS};
+use crate::{db, import};
+use notify_debouncer_mini::new_debouncer;
+use notify_debouncer_mini::notify::RecursiveMode;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// KIF_PATH（サブディレクトリを含む）を監視し、追加・更新された棋譜を自動でインポートする
+pub fn spawn_kif_watcher() {
+    thread::spawn(|| {
+        if let Err(e) = watch_kif_path() {
+            eprintln!("KIF_PATH の監視に失敗しました: {}", e);
+        }
+    });
+}
+
+fn watch_kif_path() -> Result<(), Box<dyn std::error::Error>> {
+    let (tx, rx) = mpsc::channel();
+
+    // 連続した書き込みは debounce でまとめて 1 回のイベントにする
+    let mut debouncer = new_debouncer(Duration::from_millis(*KIF_WATCH_DEBOUNCE_MS), tx)?;
+    debouncer
+        .watcher()
+        .watch(KIF_PATH.as_path(), RecursiveMode::Recursive)?;
+
+    println!("👀 KIF_PATH を監視中: {}", KIF_PATH.display());
+
+    for result in rx {
+        let events = match result {
+            Ok(events) => events,
+            Err(e) => {
+                eprintln!("監視イベントの取得に失敗しました: {}", e);
+                continue;
+            }
+        };
+
+        // 同じファイルのイベントが複数来ることがあるので重複を除く
+        let paths: BTreeSet<PathBuf> = events
+            .into_iter()
+            .map(|event| event.path)
+            .filter(|path| import::is_import_target(path))
+            .collect();
+
+        if paths.is_empty() {
+            continue;
+        }
+
+        let mut conn = match db::get_conn() {
+            Ok(conn) => conn,
+            Err(e) => {
+                eprintln!("DB接続に失敗しました: {}", e);
+                continue;
+            }
+        };
+
+        for path in paths {
+            println!("\n=== 自動インポート: {} ===", path.display());
+
+            match import::import_kif_file(&mut conn, &path.to_string_lossy()) {
+                Ok(deviations) => {
+                    for d in deviations {
+                        println!(
+                            "レパートリーから外れました: {} {}手目 {}（{}） {}",
+                            d.kif_filename,
+                            d.te,
+                            d.fugo,
+                            if d.by_me { "自分" } else { "相手" },
+                            d.kif_url
+                        );
+                    }
+                }
+                Err(e) => eprintln!("棋譜取り込み失敗: {}", e),
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,139 @@
+use crate::board::Board;
+use crate::error::{Error, Result};
+use once_cell::sync::Lazy;
+use serde::Serializer;
+
+// 盤上の駒の種類（玉を含む）
+const BOARD_PIECES: [char; 8] = ['P', 'L', 'N', 'S', 'G', 'B', 'R', 'K'];
+// 1 種類の持ち駒の最大枚数（歩18枚）
+const MAX_HAND: usize = 18;
+
+/// 局面ハッシュ用の乱数表
+///
+/// DB に保存した値と比較するので、乱数は固定の種から毎回同じものを作る（変えると全局面の再計算が必要）
+struct Keys {
+    board: [[[[u64; 81]; 2]; 2]; 8],      // [駒の種類][先後][成り][マス]
+    hands: [[[u64; MAX_HAND + 1]; 7]; 2], // [先後][持ち駒の種類][枚数]
+    gote_to_move: u64,
+}
+
+static KEYS: Lazy<Box<Keys>> = Lazy::new(|| {
+    let mut state = 0x4b49_4651_5545_5259; // "KIFQUERY"
+    let mut next = || splitmix64(&mut state);
+
+    let mut keys = Box::new(Keys {
+        board: [[[[0; 81]; 2]; 2]; 8],
+        hands: [[[0; MAX_HAND + 1]; 7]; 2],
+        gote_to_move: 0,
+    });
+    for key in keys.board.iter_mut().flatten().flatten().flatten() {
+        *key = next();
+    }
+    for side in keys.hands.iter_mut() {
+        for piece in side.iter_mut() {
+            // 0 枚は「持っていない」なので 0（XOR しても変わらない）
+            for key in piece.iter_mut().skip(1) {
+                *key = next();
+            }
+        }
+    }
+    keys.gote_to_move = next();
+    keys
+});
+
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// 盤面・持ち駒・手番から 64bit の局面ハッシュを計算する
+pub fn hash(board: &Board, sente_to_move: bool) -> u64 {
+    let keys = &*KEYS;
+    let mut h = 0;
+
+    for (y, rank) in board.squares.iter().enumerate() {
+        for (x, square) in rank.iter().enumerate() {
+            let Some(piece) = square else {
+                continue;
+            };
+            let Some(kind) = BOARD_PIECES
+                .iter()
+                .position(|&p| p == piece.to_ascii_uppercase())
+            else {
+                continue;
+            };
+            let side = if piece.is_ascii_uppercase() { 0 } else { 1 };
+            let promoted = board.promoted[y][x] as usize;
+            h ^= keys.board[kind][side][promoted][y * 9 + x];
+        }
+    }
+
+    for (side, hand) in board.hands.iter().enumerate() {
+        for (kind, &count) in hand.iter().enumerate() {
+            h ^= keys.hands[side][kind][(count as usize).min(MAX_HAND)];
+        }
+    }
+
+    if !sente_to_move {
+        h ^= keys.gote_to_move;
+    }
+    h
+}
+
+/// 平手の初期局面の局面ハッシュ（kif_bodies には 1 手目以降の局面しかない）
+pub fn initial_hash() -> u64 {
+    hash(&Board::new(), true)
+}
+
+/// 10 進の文字列の局面ハッシュを読む（JavaScript の数値では 64bit を表せないので文字列でやりとりする）
+pub fn parse_hash(text: &str) -> Result<u64> {
+    text.trim()
+        .parse()
+        .map_err(|_| Error::query("局面ハッシュが正しくありません").with_text(text))
+}
+
+/// 局面ハッシュを 10 進の文字列で出力する（#[serde(serialize_with = "zobrist::serialize_hash")]）
+pub fn serialize_hash<S: Serializer>(
+    hash: &u64,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&hash.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_distinguishes_side_hands_and_promotion() {
+        let board = Board::new();
+        assert_eq!(hash(&board, true), hash(&Board::new(), true));
+        assert_ne!(hash(&board, true), hash(&board, false));
+
+        let mut with_hand = board.clone();
+        with_hand.hands[0][0] = 1;
+        assert_ne!(hash(&board, true), hash(&with_hand, true));
+
+        let mut promoted = board.clone();
+        promoted.promoted[6][0] = true;
+        assert_ne!(hash(&board, true), hash(&promoted, true));
+    }
+
+    #[test]
+    fn test_transposition_has_same_hash() {
+        let mut a = Board::new();
+        a.apply_move("７六歩(77)", true).unwrap();
+        a.apply_move("３四歩(33)", false).unwrap();
+        a.apply_move("２六歩(27)", true).unwrap();
+
+        let mut b = Board::new();
+        b.apply_move("２六歩(27)", true).unwrap();
+        b.apply_move("３四歩(33)", false).unwrap();
+        b.apply_move("７六歩(77)", true).unwrap();
+
+        assert_eq!(hash(&a, false), hash(&b, false));
+    }
+}
@@ -0,0 +1,29 @@
+use kifquery::{Board, parser};
+use std::path::Path;
+
+/// ライブラリ API だけで棋譜を読み込み、最後まで盤面を再現できること
+#[test]
+fn replay_kif_with_library_api() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("kif/imported/Ringosky-zzz208-20250811_064041.kif");
+    let (contents, filename) = parser::read_kif_file(path.to_str().unwrap()).unwrap();
+
+    let header = parser::parse_header_and_result(&contents, &filename).unwrap();
+    assert_eq!(header.sente_player, "Ringosky");
+    assert_eq!(header.gote_player, "zzz208");
+    assert!(!header.is_sente_win);
+
+    let lines: Vec<String> = contents.lines().map(|line| line.to_string()).collect();
+    let moves = parser::parse_kif_moves(&lines);
+
+    let mut board = Board::new();
+    let mut prev_fugo: Option<String> = None;
+    for m in moves.iter().take_while(|m| !m.fugo.contains("投了")) {
+        let normalized_fugo = parser::normalize_fugo(&m.fugo, prev_fugo.as_deref());
+        board.apply_move(&normalized_fugo, m.te % 2 == 1).unwrap();
+        prev_fugo = Some(normalized_fugo);
+    }
+
+    // 76手目の ３五金打 が盤上にある
+    assert_eq!(board.squares[4][6], Some('g'));
+}
@@ -19,6 +19,7 @@ for (let row = 0; row < 9; row++) {
       e.preventDefault();
       const textPiece = e.dataTransfer.getData('text/piece');
       const textSfen = e.dataTransfer.getData('text/sfen');
+      const textOp = e.dataTransfer.getData('text/op') || 'eq';
       const isRotated = e.dataTransfer.getData('rotated') === 'true';
 
       if (textPiece) {
@@ -29,10 +30,12 @@ for (let row = 0; row < 9; row++) {
         newPiece.setAttribute('draggable', true);
         newPiece.setAttribute('data-piece', textPiece);
         newPiece.setAttribute('data-sfen', textSfen);
+        newPiece.setAttribute('data-op', textOp);
 
         newPiece.addEventListener('dragstart', (ev) => {
           ev.dataTransfer.setData('text/piece', ev.target.dataset.piece);
           ev.dataTransfer.setData('text/sfen', ev.target.dataset.sfen);
+          ev.dataTransfer.setData('text/op', ev.target.dataset.op);
           ev.dataTransfer.setData('rotated', ev.target.classList.contains('rotate-180'));
           setTimeout(() => {
             if (ev.target.parentNode.classList.contains('cell')) {
@@ -54,17 +57,59 @@ document.querySelectorAll('.piece').forEach(piece => {
   piece.addEventListener('dragstart', (e) => {
     e.dataTransfer.setData('text/piece', e.target.dataset.piece);
     e.dataTransfer.setData('text/sfen', e.target.dataset.sfen);
+    e.dataTransfer.setData('text/op', e.target.dataset.op || 'eq');
     e.dataTransfer.setData('rotated', e.target.classList.contains('rotate-180'));
     e.dataTransfer.effectAllowed = "copy";
   });
 });
 
+// 保存済みのプロファイルを対局者の選択肢に追加する
+async function loadProfiles() {
+  try {
+    const response = await fetch("http://localhost:3000/api/profiles");
+    if (!response.ok) {
+      return;
+    }
+    const select = document.getElementById("profile-select");
+    (await response.json()).forEach(profile => {
+      const option = document.createElement("option");
+      option.value = profile.name;
+      option.textContent = `${profile.name}（${profile.aliases.join(", ")}）`;
+      select.appendChild(option);
+    });
+  } catch (e) {
+    console.error(e);
+  }
+}
+loadProfiles();
+
+// 検索結果を zip でダウンロード・COLLECTED_DIR に書き出すボタンを表示する
+function showResultActions(resultId) {
+  const actions = document.getElementById("result-actions");
+  actions.innerHTML = "";
+
+  const zip = document.createElement("a");
+  zip.href = `http://localhost:3000/api/results/${resultId}/zip`;
+  zip.textContent = "zip でダウンロード";
+  actions.appendChild(zip);
+
+  const collect = document.createElement("button");
+  collect.textContent = "COLLECTED_DIR に書き出す";
+  collect.onclick = async () => {
+    const response = await fetch(`http://localhost:3000/api/results/${resultId}/collect`, { method: "POST" });
+    const report = await response.json();
+    collect.textContent = response.ok ? `書き出しました: ${report.dir}` : report.message;
+  };
+  actions.appendChild(collect);
+}
+
 async function searchKifGames() {
   const searchConditions = [];
   // const searchConditions = [
   //   { c: "68", sfen: "R" },
   //   { c: "11", sfen: "r" },
-  //   { c: "38", sfen: "p" }
+  //   { c: "38", sfen: "p" },
+  //   { c: "41", op: "empty" }
   // ];
 
   document.querySelectorAll("#condition-board .piece").forEach(piece => {
@@ -73,6 +118,7 @@ async function searchKifGames() {
       searchConditions.push({
         c: parent.getAttribute("data-c"),
         sfen: piece.getAttribute("data-sfen"),
+        op: piece.getAttribute("data-op") || "eq",
       });
     }
   });
@@ -83,95 +129,334 @@ async function searchKifGames() {
       headers: {
         "Content-Type": "application/json"
       },
-      body: JSON.stringify(searchConditions)
+      body: JSON.stringify({
+        query: searchConditions,
+        mirror: document.getElementById("mirror-search").checked,
+        everyone: document.getElementById("everyone-search").checked,
+        profile: document.getElementById("profile-select").value || null,
+        limit: PAGE_SIZE,
+        sort: document.getElementById("sort-select").value,
+      })
     });
 
     if (!response.ok) {
       throw new Error(`サーバーエラー: ${response.status}`);
     }
 
-    const result = await response.json();
-    if (result.length === 0) {
-      resultLog.textContent = "一致する棋譜は見つかりませんでした。";
-      return;
-    }
+    const page = await response.json();
+    showResultActions(page.result_id);
+    showResultPage(page);
+    showResultStats(page.result_id);
 
-    const tbody = document.getElementById("result-body");
-    tbody.innerHTML = ""; // 前回の結果をクリア
-    var winCount = 0;
-    var senteWinCount = 0;
-    var senteCount = 0;
-
-    result.forEach(linkObj => {
-      const tr = document.createElement("tr");
-
-      // 手数
-      const tdTe = document.createElement("td");
-      tdTe.textContent = linkObj.te;
-      tr.appendChild(tdTe);
-
-      // 勝敗
-      const tdResult = document.createElement("td");
-      tdResult.textContent = linkObj.is_win ? "⭕️" : "✖︎";
-      tr.appendChild(tdResult);
-      winCount += linkObj.is_win ? 1 : 0;
-
-      // 先手/後手
-      const tdSengo = document.createElement("td");
-      console.log(linkObj.is_sente);
-      tdSengo.textContent = linkObj.is_sente ? "先手" : "後手";
-      tr.appendChild(tdSengo);
-      senteCount += linkObj.is_sente ? 1 : 0;
-      if (linkObj.is_sente && linkObj.is_win) {
-        senteWinCount++;
-      }
+  } catch (error) {
+    console.error("検索リクエスト失敗:", error);
+    // const resultLog = document.getElementById("result-log");
+    // resultLog.textContent = "エラーが発生しました: " + error.message;
+  }
 
-      // 対局開始日時
-      const tdDate = document.createElement("td");
-      if (!linkObj.started_at) {
-        tdDate.textContent = "-";
-      } else if (linkObj.started_at.length < 10) {
-        tdDate.textContent = linkObj.started_at; // 日時が短い場合はそのまま表示
-      } else {
-        tdDate.textContent = linkObj.started_at.substring(0, 10);
-      }
-      tr.appendChild(tdDate);
+}
+
+// 1 ページに表示する件数
+const PAGE_SIZE = 50;
+
+// 保存済みの検索結果から別のページを取得する
+async function fetchResultPage(resultId, offset) {
+  const sort = document.getElementById("sort-select").value;
+  const response = await fetch(
+    `http://localhost:3000/api/results/${resultId}?offset=${offset}&limit=${PAGE_SIZE}&sort=${sort}`
+  );
+  if (!response.ok) {
+    console.error("検索結果の取得に失敗:", response.status);
+    return;
+  }
+  showResultPage(await response.json());
+}
 
-      // リンク
-      const tdLink = document.createElement("td");
-      const a = document.createElement("a");
-      a.href = linkObj.link;
-      a.textContent = linkObj.link.split('/').pop();
-      a.target = "_blank";
-      tdLink.appendChild(a);
-      tr.appendChild(tdLink);
+function formatRecord(label, record) {
+  const decided = record.wins + record.losses;
+  const winRate = decided > 0 ? (record.wins / decided * 100).toFixed(2) : 0;
+  return `${label}： ${record.wins}勝 ${record.losses}敗 ${record.draws}分 (勝率: ${winRate}%)`;
+}
 
-      // 行を tbody に追加
-      tbody.appendChild(tr);
+function showResultPage(page) {
+  const resultLog = document.getElementById("result-log");
+  const tbody = document.getElementById("result-body");
+  tbody.innerHTML = ""; // 前回の結果をクリア
+  showPager(page);
 
+  if (page.total === 0) {
+    resultLog.textContent = "一致する棋譜は見つかりませんでした。";
+    return;
+  }
+
+  page.links.forEach(linkObj => {
+    const tr = document.createElement("tr");
+
+    // 手数
+    const tdTe = document.createElement("td");
+    tdTe.textContent = linkObj.te;
+    tdTe.title = `一致した手数: ${linkObj.tes.join(", ")}`;
+    tr.appendChild(tdTe);
+
+    // 一致した局面になった指し手と、その後の指し手
+    const tdMoves = document.createElement("td");
+    tdMoves.textContent = [linkObj.fugo, ...linkObj.next_moves].join(" ");
+    tdMoves.title = linkObj.sfen || "";
+    tr.appendChild(tdMoves);
+
+    // 勝敗
+    const tdResult = document.createElement("td");
+    tdResult.textContent = linkObj.is_draw ? "△" : linkObj.is_win ? "⭕️" : "✖︎";
+    tr.appendChild(tdResult);
+
+    // どちらの対局者の視点で一致したか
+    const tdPlayer = document.createElement("td");
+    tdPlayer.textContent = linkObj.player;
+    tdPlayer.title = `対戦相手: ${linkObj.opponent}`;
+    tr.appendChild(tdPlayer);
+
+    // 先手/後手
+    const tdSengo = document.createElement("td");
+    tdSengo.textContent = linkObj.is_sente ? "先手" : "後手";
+    tr.appendChild(tdSengo);
+
+    // 左右反転した条件で一致したか
+    const tdTransform = document.createElement("td");
+    tdTransform.textContent = linkObj.transform.includes("mirrored") ? "左右" : "-";
+    tr.appendChild(tdTransform);
+
+    // 対局開始日時
+    const tdDate = document.createElement("td");
+    if (!linkObj.started_at) {
+      tdDate.textContent = "-";
+    } else if (linkObj.started_at.length < 10) {
+      tdDate.textContent = linkObj.started_at; // 日時が短い場合はそのまま表示
+    } else {
+      tdDate.textContent = linkObj.started_at.substring(0, 10);
+    }
+    tr.appendChild(tdDate);
+
+    // リンク
+    const tdLink = document.createElement("td");
+    const a = document.createElement("a");
+    a.href = `http://localhost:3000${linkObj.kif_url}`;
+    a.textContent = linkObj.kif_filename;
+    a.target = "_blank";
+    tdLink.appendChild(a);
+
+    // サーバーから KIF を取得して盤面に表示する
+    const preview = document.createElement("button");
+    preview.textContent = "表示";
+    preview.onclick = async () => {
+      const response = await fetch(a.href);
+      embedKifuInIframe(await response.text(), "01");
+    };
+    tdLink.appendChild(preview);
+    tr.appendChild(tdLink);
+
+    // 行を tbody に追加
+    tbody.appendChild(tr);
+  });
+
+  // 勝敗はサーバーが検索結果全体で集計したものを表示する
+  const { all, sente, gote } = page.summary;
+  resultLog.textContent = `検索結果: ${page.total} 件
+${formatRecord("全て", all)}
+${formatRecord("先手", sente)}
+${formatRecord("後手", gote)}`;
+}
+
+// 次の一手・場所・持ち時間・月ごとの勝敗をサーバーで集計して表示する
+async function showResultStats(resultId) {
+  const container = document.getElementById("result-stats");
+  container.innerHTML = "";
+  const response = await fetch(`http://localhost:3000/api/results/${resultId}/stats`);
+  if (!response.ok) {
+    console.error("集計の取得に失敗:", response.status);
+    return;
+  }
+  const stats = await response.json();
+
+  [
+    ["次の一手", stats.by_next_move],
+    ["場所", stats.by_site],
+    ["持ち時間", stats.by_time_control],
+    ["月", stats.by_month],
+  ].forEach(([title, buckets]) => appendBucketTable(container, title, buckets));
+}
+
+// 値ごとの勝敗の表を追加する
+function appendBucketTable(container, title, buckets) {
+  if (buckets.length === 0) return;
+  const table = document.createElement("table");
+  table.border = 1;
+  const caption = table.createCaption();
+  caption.textContent = title;
+  buckets.forEach(bucket => {
+    const tr = table.insertRow();
+    [
+      bucket.key,
+      `${bucket.games}局`,
+      `${bucket.wins}勝 ${bucket.losses}敗 ${bucket.draws}分`,
+      `${(bucket.win_rate * 100).toFixed(1)}%`,
+    ].forEach(text => {
+      tr.insertCell().textContent = text;
     });
+  });
+  container.appendChild(table);
+}
 
-    // 結果ログに勝率を表示
-    const resultLog = document.getElementById("result-log");
-    const totalGames = result.length;
-    const winRate = totalGames > 0 ? (winCount / totalGames * 100).toFixed(2) : 0;
+// 対局者の成績（プロファイル名もアカウント名も空なら MY_USERNAMES）
+async function showDashboard() {
+  const container = document.getElementById("dashboard");
+  container.innerHTML = "";
+  const params = new URLSearchParams();
+  const profile = document.getElementById("dashboard-profile").value.trim();
+  const player = document.getElementById("dashboard-player").value.trim();
+  if (profile) params.set("profile", profile);
+  if (player) params.set("player", player);
+
+  const response = await fetch(`http://localhost:3000/api/stats?${params}`);
+  if (!response.ok) {
+    container.textContent = `成績の取得に失敗しました（${response.status}）`;
+    return;
+  }
+  const stats = await response.json();
+
+  const summary = document.createElement("div");
+  summary.textContent = [
+    formatRecord("全て", stats.all),
+    formatRecord("先手", stats.sente),
+    formatRecord("後手", stats.gote),
+  ].join(" / ");
+  container.appendChild(summary);
+
+  [
+    ["週", stats.by_week],
+    ["月", stats.by_month],
+    ["場所", stats.by_site],
+    ["持ち時間", stats.by_time_control],
+    ["時間帯", stats.by_hour],
+    ["曜日", stats.by_weekday],
+    ["手数", stats.by_length],
+    ["終局の理由", stats.by_end_reason],
+    ["よく対戦する相手", stats.opponents],
+  ].forEach(([title, buckets]) => appendBucketTable(container, title, buckets));
+}
 
-    // 検索結果: {totalGames} 件
-    // {winCount}勝 {totalGames - winCount}敗 (勝率: {winRate}%)
-    // 改行を有効にする
+// 前後のページへ移動するボタン
+function showPager(page) {
+  const pager = document.getElementById("result-pager");
+  pager.innerHTML = "";
+  if (page.total <= PAGE_SIZE) {
+    return;
+  }
 
-    resultLog.textContent = `検索結果: ${totalGames} 件
-全て： ${winCount}勝 ${totalGames - winCount}敗 (勝率: ${winRate}%)
-先手： ${senteWinCount}勝 ${senteCount - senteWinCount}敗 (勝率: ${(senteCount > 0 ? (senteWinCount / senteCount * 100).toFixed(2) : 0)}%)
-後手： ${winCount - senteWinCount}勝 ${totalGames - senteCount - (winCount - senteWinCount)}敗 (勝率: ${(totalGames - senteCount > 0 ? ((winCount - senteWinCount) / (totalGames - senteCount) * 100).toFixed(2) : 0)}%)`
-      ;
+  const prev = document.createElement("button");
+  prev.textContent = "前へ";
+  prev.disabled = page.offset === 0;
+  prev.onclick = () => fetchResultPage(page.result_id, Math.max(0, page.offset - PAGE_SIZE));
+  pager.appendChild(prev);
+
+  const range = document.createElement("span");
+  range.textContent = ` ${page.offset + 1}〜${page.offset + page.links.length} / ${page.total} 件 `;
+  pager.appendChild(range);
+
+  const next = document.createElement("button");
+  next.textContent = "次へ";
+  next.disabled = page.offset + PAGE_SIZE >= page.total;
+  next.onclick = () => fetchResultPage(page.result_id, page.offset + PAGE_SIZE);
+  pager.appendChild(next);
+}
 
-  } catch (error) {
-    console.error("検索リクエスト失敗:", error);
-    // const resultLog = document.getElementById("result-log");
-    // resultLog.textContent = "エラーが発生しました: " + error.message;
+// 定跡エクスプローラーでたどった局面（ハッシュ）の履歴
+const explorerHistory = [];
+
+const INITIAL_SFEN = "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1";
+
+function startExplorer() {
+  explorerHistory.length = 0;
+  const sfen = document.getElementById("explorer-sfen").value.trim() || INITIAL_SFEN;
+  exploreFrom({ sfen });
+}
+
+function backExplorer() {
+  if (explorerHistory.length < 2) return;
+  explorerHistory.pop();
+  exploreFrom({ hash: explorerHistory.pop() });
+}
+
+// 局面から指された手を取得し、手を押すとその先の局面に進む
+async function exploreFrom(position) {
+  const response = await fetch("http://localhost:3000/api/explorer", {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify({
+      ...position,
+      everyone: document.getElementById("everyone-search").checked,
+      profile: document.getElementById("profile-select").value || null,
+    })
+  });
+  const explorer = await response.json();
+  const tbody = document.getElementById("explorer-body");
+  tbody.innerHTML = "";
+  if (!response.ok) {
+    document.getElementById("explorer-position").textContent = explorer.message;
+    return;
   }
 
+  explorerHistory.push(explorer.hash);
+  document.getElementById("explorer-position").textContent =
+    `${explorer.sfen || explorer.hash}（${explorer.games} 局）`;
+
+  explorer.moves.forEach(move => {
+    const tr = tbody.insertRow();
+    const next = document.createElement("a");
+    next.href = "#";
+    next.textContent = move.fugo;
+    next.title = move.sfen || "";
+    next.onclick = (e) => {
+      e.preventDefault();
+      exploreFrom({ hash: move.hash });
+    };
+    tr.insertCell().appendChild(next);
+    tr.insertCell().textContent = move.games;
+    tr.insertCell().textContent = `${move.mine.wins}勝 ${move.mine.losses}敗 ${move.mine.draws}分`;
+    tr.insertCell().textContent = `${(move.win_rate * 100).toFixed(1)}%`;
+    tr.insertCell().textContent = move.avg_eval === null ? "-" : Math.round(move.avg_eval);
+
+    const add = document.createElement("button");
+    add.textContent = "追加";
+    add.onclick = async () => {
+      const response = await fetch("http://localhost:3000/api/repertoire/moves", {
+        method: "POST",
+        headers: { "Content-Type": "application/json" },
+        body: JSON.stringify({
+          ...repertoireKey(),
+          hash: explorer.hash,
+          next_hash: move.hash,
+          fugo: move.fugo,
+        })
+      });
+      const saved = await response.json();
+      add.textContent = response.ok ? (saved.is_mine ? "自分の手" : "相手の手") : saved.message;
+    };
+    tr.insertCell().appendChild(add);
+  });
+}
+
+// どのレパートリーか（プロファイルは検索と同じ選択を使う）
+function repertoireKey() {
+  const key = { side: document.getElementById("repertoire-side").value };
+  const profile = document.getElementById("profile-select").value;
+  if (profile) key.profile = profile;
+  return key;
+}
+
+function downloadRepertoire(e) {
+  e.preventDefault();
+  const params = new URLSearchParams(repertoireKey());
+  window.open(`http://localhost:3000/api/repertoire/kif?${params}`, "_blank");
 }
 
 ["01", "02", "03"].forEach(function (id) {